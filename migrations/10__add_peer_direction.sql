@@ -0,0 +1,11 @@
+-- Migration: per-peer replication direction and pull cursor
+
+-- 'push': we POST to the peer's /relay/replicate (previous behaviour)
+-- 'pull': we GET the peer's /relay/replicate/pull, for peers that can't reach us
+-- 'both': push and pull
+-- 'none': we never connect; the peer may still push to or pull from us
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS direction TEXT NOT NULL DEFAULT 'push'
+    CHECK (direction IN ('push', 'pull', 'both', 'none'));
+
+-- Highest server_seq of the peer's events we've pulled and stored
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS remote_cursor BIGINT NOT NULL DEFAULT 0;
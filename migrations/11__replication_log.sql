@@ -0,0 +1,47 @@
+-- Migration: replication cursors on a commit-ordered local sequence
+--
+-- Peer cursors used (occurred_at, event_id), and occurred_at is client-supplied: an event
+-- arriving with a timestamp older than a peer's cursor was never replicated to that peer.
+-- server_seq alone isn't enough either, since it is assigned at insert and transactions
+-- can commit out of order. repl_seq is assigned to committed events by a single
+-- sequencer at a time (db::sequence_replication_log), so the visible positions always
+-- form a gap-free prefix and a cursor over them never skips anything.
+
+ALTER TABLE events ADD COLUMN IF NOT EXISTS repl_seq BIGINT UNIQUE;
+CREATE SEQUENCE IF NOT EXISTS events_repl_seq;
+
+-- Existing events are all committed, so server_seq order is final for them. Keeping the
+-- same values also keeps pull cursors (peers.remote_cursor, stored by peers pulling from
+-- us) valid.
+UPDATE events SET repl_seq = server_seq WHERE repl_seq IS NULL;
+SELECT setval('events_repl_seq', (SELECT COALESCE(MAX(server_seq), 0) + 1 FROM events), false);
+
+CREATE INDEX IF NOT EXISTS events_repl_pending_idx ON events (server_seq) WHERE repl_seq IS NULL;
+
+-- Events above a peer's cursor that it already received under the old cursor
+CREATE TABLE IF NOT EXISTS peer_replication_skips (
+    peer_id UUID NOT NULL REFERENCES peers (peer_id) ON DELETE CASCADE,
+    repl_seq BIGINT NOT NULL,
+    PRIMARY KEY (peer_id, repl_seq)
+);
+
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS last_cursor_seq BIGINT NOT NULL DEFAULT 0;
+
+-- Convert: the new cursor sits just below the oldest event the peer hasn't been sent.
+-- Anything above it that the old cursor had already passed goes in the skip list, so
+-- nothing is sent twice and nothing is dropped.
+UPDATE peers p SET last_cursor_seq = COALESCE(
+    (SELECT MIN(e.repl_seq) - 1 FROM events e
+      WHERE (e.occurred_at, e.event_id) > (COALESCE(p.last_cursor_time, '-infinity'), COALESCE(p.last_cursor_id, '00000000-0000-0000-0000-000000000000'))),
+    (SELECT COALESCE(MAX(repl_seq), 0) FROM events));
+
+INSERT INTO peer_replication_skips (peer_id, repl_seq)
+SELECT p.peer_id, e.repl_seq
+  FROM peers p
+  JOIN events e
+    ON e.repl_seq > p.last_cursor_seq
+   AND (e.occurred_at, e.event_id) <= (COALESCE(p.last_cursor_time, '-infinity'), COALESCE(p.last_cursor_id, '00000000-0000-0000-0000-000000000000'))
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE peers DROP COLUMN IF EXISTS last_cursor_time;
+ALTER TABLE peers DROP COLUMN IF EXISTS last_cursor_id;
@@ -0,0 +1,13 @@
+-- Migration: record where replicated events came from
+--
+-- origin_relay: relay the event was first stored on (NULL: this relay)
+-- hops: relay-to-relay transfers it took to get here (0: pushed here by a client)
+-- relay_path: relays that held it before us, origin first; never forwarded back to them
+-- Events stored before this migration look local and may be offered back to their source
+-- once, where they are deduplicated.
+ALTER TABLE events ADD COLUMN IF NOT EXISTS origin_relay UUID;
+ALTER TABLE events ADD COLUMN IF NOT EXISTS hops INT NOT NULL DEFAULT 0;
+ALTER TABLE events ADD COLUMN IF NOT EXISTS relay_path UUID[] NOT NULL DEFAULT '{}';
+
+-- The peer's own relay id, learned from X-Relay-Id and replicate responses
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS remote_relay_id UUID;
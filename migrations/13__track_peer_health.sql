@@ -0,0 +1,14 @@
+-- Migration: peer health tracking and backoff
+--
+-- health: 'unknown' (never contacted), 'healthy', 'degraded' (recent failures, retried
+-- with backoff) or 'down' (only /health probes until one succeeds)
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS consecutive_failures INT NOT NULL DEFAULT 0;
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS last_success_at TIMESTAMPTZ;
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS last_error_at TIMESTAMPTZ;
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS last_error TEXT;
+-- No replication or probe before this time; NULL means now
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS next_attempt_at TIMESTAMPTZ;
+
+UPDATE peers SET health = 'unknown' WHERE health IS NULL OR health NOT IN ('unknown', 'healthy', 'degraded', 'down');
+ALTER TABLE peers ALTER COLUMN health SET NOT NULL;
+ALTER TABLE peers ADD CONSTRAINT peers_health_check CHECK (health IN ('unknown', 'healthy', 'degraded', 'down'));
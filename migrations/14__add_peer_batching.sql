@@ -0,0 +1,6 @@
+-- Migration: per-peer batch limits for outbound replication
+--
+-- batch_size: events per replicate request (and per pull response we serve the peer)
+-- max_batch_bytes: JSON size budget per batch; a single larger event is still sent alone
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS batch_size INT NOT NULL DEFAULT 50 CHECK (batch_size > 0);
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS max_batch_bytes INT NOT NULL DEFAULT 1048576 CHECK (max_batch_bytes > 0);
@@ -0,0 +1,5 @@
+-- Migration: per-peer outbound filters
+--
+-- outbound_filter: which of our events the peer is sent (filter::OutboundFilter as JSON:
+-- event_types, authors, tags, deny_authors, max_age_secs). Empty means everything.
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS outbound_filter JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,11 @@
+-- Migration: per-peer inbound policy and automatic suspension
+--
+-- inbound_policy: what we accept from the peer (filter::InboundPolicy as JSON: event_types,
+-- authors, max_batch_events, max_events_per_min, read_only). Empty means everything.
+-- policy_violations counts violations within the hour since last_violation_at; a peer
+-- reaching PEER_VIOLATION_LIMIT is suspended until an operator lifts it.
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS inbound_policy JSONB NOT NULL DEFAULT '{}';
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS policy_violations INT NOT NULL DEFAULT 0;
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS last_violation TEXT;
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS last_violation_at TIMESTAMPTZ;
+ALTER TABLE peers ADD COLUMN IF NOT EXISTS suspended_at TIMESTAMPTZ;
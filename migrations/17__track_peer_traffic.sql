@@ -0,0 +1,15 @@
+-- Migration: replication status reporting
+--
+-- received_at: when this relay stored the event, so replication lag can be measured without
+-- trusting client-supplied occurred_at. Events stored before this migration have none.
+ALTER TABLE events ADD COLUMN IF NOT EXISTS received_at TIMESTAMPTZ;
+ALTER TABLE events ALTER COLUMN received_at SET DEFAULT NOW();
+
+-- Events exchanged with each peer per minute, for throughput; kept for a day
+CREATE TABLE IF NOT EXISTS peer_traffic (
+    peer_id UUID NOT NULL REFERENCES peers (peer_id) ON DELETE CASCADE,
+    minute TIMESTAMPTZ NOT NULL,
+    events_sent BIGINT NOT NULL DEFAULT 0,
+    events_received BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (peer_id, minute)
+);
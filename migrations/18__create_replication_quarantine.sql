@@ -0,0 +1,16 @@
+-- Migration: quarantine for events a peer permanently rejects
+--
+-- Replication moves past such events instead of retrying them forever. direction is
+-- 'outbound' (the peer refused our event) or 'inbound' (we refused one pulled from it);
+-- event holds the replicated event as sent, for inspection.
+CREATE TABLE IF NOT EXISTS replication_quarantine (
+    peer_id UUID NOT NULL REFERENCES peers (peer_id) ON DELETE CASCADE,
+    event_id UUID NOT NULL,
+    direction TEXT NOT NULL CHECK (direction IN ('outbound', 'inbound')),
+    error TEXT NOT NULL,
+    event JSONB NOT NULL,
+    quarantined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (peer_id, event_id, direction)
+);
+
+CREATE INDEX IF NOT EXISTS replication_quarantine_time_idx ON replication_quarantine (quarantined_at DESC);
@@ -0,0 +1,12 @@
+-- Migration: webhook enqueue cursors on the commit-ordered replication log
+--
+-- webhooks.last_seq was a cursor over server_seq, which is assigned at insert: an event
+-- whose transaction committed after a higher server_seq had been scanned was never
+-- enqueued. It now holds a repl_seq (see migration 11).
+
+-- The cursor moves to just below the first event the old one hadn't passed. Matching
+-- events above it that were already enqueued are kept from repeating by
+-- UNIQUE (webhook_id, event_id).
+UPDATE webhooks w SET last_seq = COALESCE(
+    (SELECT MIN(e.repl_seq) - 1 FROM events e WHERE e.server_seq > w.last_seq AND e.repl_seq IS NOT NULL),
+    (SELECT COALESCE(MAX(repl_seq), 0) FROM events));
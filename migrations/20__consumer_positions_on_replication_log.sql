@@ -0,0 +1,15 @@
+-- Migration: consumer positions on the commit-ordered replication log
+--
+-- acked_seq, delivered_seq and claim ranges were server_seq positions, which are
+-- assigned at insert: an event committing after a claim had passed its server_seq was
+-- never handed out. They now hold repl_seq positions (see migration 11).
+
+-- Each consumer restarts just below the first event it hadn't acked. Events above that
+-- which it had already processed are handed out again; consumers are at-least-once.
+UPDATE consumers c SET acked_seq = COALESCE(
+    (SELECT MIN(e.repl_seq) - 1 FROM events e WHERE e.server_seq > c.acked_seq AND e.repl_seq IS NOT NULL),
+    (SELECT COALESCE(MAX(repl_seq), 0) FROM events));
+
+-- In-flight claims are re-issued from the acked position; their acks will be refused
+UPDATE consumers SET delivered_seq = acked_seq;
+DELETE FROM consumer_claims;
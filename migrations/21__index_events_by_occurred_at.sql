@@ -0,0 +1,5 @@
+-- Migration: index for newest-first reads by occurrence time
+--
+-- Nostr REQs return the latest `limit` matches by created_at (occurred_at here), with
+-- server_seq as the tie-breaker.
+CREATE INDEX IF NOT EXISTS events_occurred_at_seq_idx ON events (occurred_at, server_seq);
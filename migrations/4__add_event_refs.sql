@@ -0,0 +1,15 @@
+-- Migration: structured references between events (replies, reactions, edits)
+
+-- 1. Signed refs as sent by the author, kept on the row so events can be re-verified and replicated
+ALTER TABLE events ADD COLUMN refs JSONB;
+
+-- 2. Edge table for graph traversal
+CREATE TABLE IF NOT EXISTS event_refs (
+    event_id UUID NOT NULL,
+    target_id UUID NOT NULL,
+    kind TEXT NOT NULL,
+    PRIMARY KEY (event_id, target_id, kind)
+);
+
+-- 3. Reverse lookup (who points at this event); targets may not exist locally yet, so no FK
+CREATE INDEX IF NOT EXISTS event_refs_target_idx ON event_refs (target_id);
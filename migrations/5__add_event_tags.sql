@@ -0,0 +1,14 @@
+-- Migration: indexed key/value tags on events
+
+-- 1. Signed tags as sent by the author
+ALTER TABLE events ADD COLUMN tags JSONB;
+
+-- 2. Normalized index: one row per [key, value, ...] tag
+CREATE TABLE IF NOT EXISTS event_tags (
+    event_id UUID NOT NULL,
+    key TEXT NOT NULL,
+    value TEXT NOT NULL,
+    PRIMARY KEY (event_id, key, value)
+);
+
+CREATE INDEX IF NOT EXISTS event_tags_key_value_idx ON event_tags (key, value);
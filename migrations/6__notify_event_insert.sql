@@ -0,0 +1,13 @@
+-- Migration: announce inserted events so relays can wake subscribers without polling
+
+CREATE OR REPLACE FUNCTION notify_event_inserted() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('tisane_events', NEW.server_seq::text);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS events_notify_insert ON events;
+CREATE TRIGGER events_notify_insert
+    AFTER INSERT ON events
+    FOR EACH ROW EXECUTE FUNCTION notify_event_inserted();
@@ -0,0 +1,24 @@
+-- Migration: durable named consumers with relay-side cursors
+
+-- 1. Consumer position: everything at or below acked_seq is processed,
+--    delivered_seq is the highest server_seq handed out in a claim
+CREATE TABLE IF NOT EXISTS consumers (
+    name TEXT PRIMARY KEY,
+    filter JSONB NOT NULL DEFAULT '{}',
+    acked_seq BIGINT NOT NULL DEFAULT 0,
+    delivered_seq BIGINT NOT NULL DEFAULT 0,
+    ack_timeout_secs INT NOT NULL DEFAULT 30,
+    created_at TIMESTAMPTZ DEFAULT NOW(),
+    updated_at TIMESTAMPTZ DEFAULT NOW()
+);
+
+-- 2. In-flight ranges (from_seq, to_seq] leased to one worker until expires_at
+CREATE TABLE IF NOT EXISTS consumer_claims (
+    claim_id UUID PRIMARY KEY,
+    consumer TEXT NOT NULL REFERENCES consumers (name) ON DELETE CASCADE,
+    from_seq BIGINT NOT NULL,
+    to_seq BIGINT NOT NULL,
+    expires_at TIMESTAMPTZ NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS consumer_claims_consumer_idx ON consumer_claims (consumer, expires_at);
@@ -0,0 +1,6 @@
+-- Migration: record which signature scheme an event is verified with
+
+-- 'ed25519' for native events, 'nostr' for NIP-01 events (BIP-340 Schnorr over the Nostr id)
+ALTER TABLE events ADD COLUMN IF NOT EXISTS sig_scheme TEXT NOT NULL DEFAULT 'ed25519';
+
+CREATE INDEX IF NOT EXISTS events_sig_scheme_idx ON events (sig_scheme, server_seq) WHERE sig_scheme <> 'ed25519';
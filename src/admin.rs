@@ -0,0 +1,78 @@
+// ----- ADMIN API -----
+//
+// Operator endpoints, enabled by setting ADMIN_TOKEN and authenticated with
+// `Authorization: Bearer <token>`:
+//
+//   GET /admin/peers            replication status of every peer
+//   GET /admin/peers/:peer_id   replication status of one peer
+
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use tisane_relay::db;
+
+use crate::AppState;
+
+fn error(code: StatusCode, msg: impl Into<String>) -> axum::response::Response {
+    (code, Json(serde_json::json!({"error": msg.into()}))).into_response()
+}
+
+/// Compares digests so the comparison takes the same time whatever the token
+fn token_matches(given: &str, expected: &str) -> bool {
+    let (a, b) = (Sha256::digest(given.as_bytes()), Sha256::digest(expected.as_bytes()));
+    a.iter().zip(b.iter()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn authorize(state: &AppState, headers: &HeaderMap) -> Result<(), axum::response::Response> {
+    let Some(expected) = state.admin_token.as_deref() else {
+        return Err(error(StatusCode::NOT_FOUND, "admin API is disabled (set ADMIN_TOKEN)"));
+    };
+    let given = headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    match given {
+        Some(token) if token_matches(token, expected) => Ok(()),
+        Some(_) => Err(error(StatusCode::FORBIDDEN, "invalid admin token")),
+        None => Err(error(StatusCode::UNAUTHORIZED, "missing bearer token")),
+    }
+}
+
+pub async fn peers_status_handler(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &headers) {
+        return resp;
+    }
+    let peers = match db::fetch_all_peers(&state.pool).await {
+        Ok(peers) => peers,
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    let mut statuses = Vec::with_capacity(peers.len());
+    for peer in &peers {
+        match db::fetch_peer_status(&state.pool, peer).await {
+            Ok(status) => statuses.push(status),
+            Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        }
+    }
+    (StatusCode::OK, Json(statuses)).into_response()
+}
+
+pub async fn peer_status_handler(State(state): State<AppState>, headers: HeaderMap, Path(peer_id): Path<Uuid>) -> impl IntoResponse {
+    if let Err(resp) = authorize(&state, &headers) {
+        return resp;
+    }
+    let peer = match db::fetch_peer(&state.pool, peer_id).await {
+        Ok(Some(peer)) => peer,
+        Ok(None) => return error(StatusCode::NOT_FOUND, "peer not found"),
+        Err(e) => return error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    };
+    match db::fetch_peer_status(&state.pool, &peer).await {
+        Ok(status) => (StatusCode::OK, Json(status)).into_response(),
+        Err(e) => error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
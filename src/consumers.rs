@@ -0,0 +1,198 @@
+// ----- DURABLE CONSUMERS -----
+//
+// A named consumer keeps its position on the relay. Workers sharing a consumer call
+// fetch to lease a batch (a claim), then ack the claim once processed. Unacked claims
+// are handed to another worker after the consumer's ack timeout.
+//
+//   PUT    /relay/consumers/:name        {"filter":{...},"start":"latest"|"earliest"|<seq>,"ack_timeout_secs":30}
+//
+// Positions (start, acked_seq, delivered_seq, head_seq) are replication log positions,
+// which follow commit order, not the `server_seq` of the events handed out.
+//   GET    /relay/consumers/:name        position and lag
+//   DELETE /relay/consumers/:name
+//   POST   /relay/consumers/:name/fetch  ?limit=&wait=  -> {"claim_id","expires_at","events"}
+//   POST   /relay/consumers/:name/ack    {"claim_id"}   -> {"acked_seq"}
+
+use std::time::Duration;
+
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::IntoResponse,
+    Json,
+};
+use serde::Deserialize;
+use tracing::error;
+use uuid::Uuid;
+
+use tisane_relay::db;
+use tisane_relay::filter::EventFilter;
+
+use crate::AppState;
+
+const MAX_NAME_LEN: usize = 64;
+const DEFAULT_ACK_TIMEOUT_SECS: i32 = 30;
+const MAX_ACK_TIMEOUT_SECS: i32 = 3600;
+const MAX_FETCH_LIMIT: i64 = 1000;
+const MAX_FETCH_WAIT_SECS: u64 = 60;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum StartPosition {
+    Named(String),
+    Seq(i64),
+}
+
+#[derive(Deserialize)]
+pub struct ConsumerReq {
+    #[serde(default)]
+    filter: EventFilter,
+    /// Only applied when given; updating a consumer keeps its position otherwise.
+    /// New consumers start at "latest".
+    start: Option<StartPosition>,
+    ack_timeout_secs: Option<i32>,
+}
+
+#[derive(Deserialize)]
+pub struct FetchQuery {
+    limit: Option<i64>,
+    wait: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct AckReq {
+    claim_id: Uuid,
+}
+
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+fn error_response(code: StatusCode, msg: impl ToString) -> axum::response::Response {
+    (code, Json(serde_json::json!({"error": msg.to_string()}))).into_response()
+}
+
+fn db_error(e: sqlx::Error) -> axum::response::Response {
+    error!("consumer error: {}", e);
+    error_response(StatusCode::INTERNAL_SERVER_ERROR, e)
+}
+
+pub async fn put_consumer_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<ConsumerReq>,
+) -> impl IntoResponse {
+    if !valid_name(&name) {
+        return error_response(StatusCode::BAD_REQUEST, "consumer name must be 1-64 characters of [A-Za-z0-9._-]");
+    }
+    let ack_timeout = req.ack_timeout_secs.unwrap_or(DEFAULT_ACK_TIMEOUT_SECS);
+    if !(1..=MAX_ACK_TIMEOUT_SECS).contains(&ack_timeout) {
+        return error_response(StatusCode::BAD_REQUEST, format!("ack_timeout_secs must be 1-{}", MAX_ACK_TIMEOUT_SECS));
+    }
+
+    let existing = match db::fetch_consumer(&state.pool, &name).await {
+        Ok(c) => c,
+        Err(e) => return db_error(e),
+    };
+    let start = match (req.start, &existing) {
+        (None, Some(_)) => None,
+        (None, None) => Some(StartPosition::Named("latest".to_string())),
+        (start, _) => start,
+    };
+    let start_seq = match start {
+        None => None,
+        Some(StartPosition::Seq(seq)) if seq >= 0 => Some(seq),
+        Some(StartPosition::Named(ref s)) if s == "earliest" => Some(0),
+        Some(StartPosition::Named(ref s)) if s == "latest" => match db::fetch_replication_log_head(&state.pool).await {
+            Ok(head) => Some(head),
+            Err(e) => return db_error(e),
+        },
+        Some(_) => return error_response(StatusCode::BAD_REQUEST, "start must be \"earliest\", \"latest\" or a log position"),
+    };
+
+    match db::upsert_consumer(&state.pool, &name, &req.filter, ack_timeout, start_seq).await {
+        Ok(consumer) => {
+            let code = if existing.is_some() { StatusCode::OK } else { StatusCode::CREATED };
+            (code, Json(consumer)).into_response()
+        },
+        Err(e) => db_error(e),
+    }
+}
+
+pub async fn get_consumer_handler(State(state): State<AppState>, Path(name): Path<String>) -> impl IntoResponse {
+    let consumer = match db::fetch_consumer(&state.pool, &name).await {
+        Ok(Some(c)) => c,
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "consumer not found"),
+        Err(e) => return db_error(e),
+    };
+    match db::fetch_consumer_lag(&state.pool, &consumer).await {
+        Ok(lag) => (StatusCode::OK, Json(serde_json::json!({
+            "name": consumer.name,
+            "filter": consumer.filter.0,
+            "acked_seq": consumer.acked_seq,
+            "delivered_seq": consumer.delivered_seq,
+            "ack_timeout_secs": consumer.ack_timeout_secs,
+            "head_seq": lag.head_seq,
+            "lag": lag.events,
+            "in_flight_claims": lag.in_flight_claims,
+        }))).into_response(),
+        Err(e) => db_error(e),
+    }
+}
+
+pub async fn delete_consumer_handler(State(state): State<AppState>, Path(name): Path<String>) -> impl IntoResponse {
+    match db::remove_consumer(&state.pool, &name).await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => error_response(StatusCode::NOT_FOUND, "consumer not found"),
+        Err(e) => db_error(e),
+    }
+}
+
+pub async fn fetch_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(q): Query<FetchQuery>,
+) -> impl IntoResponse {
+    match db::fetch_consumer(&state.pool, &name).await {
+        Ok(Some(_)) => {},
+        Ok(None) => return error_response(StatusCode::NOT_FOUND, "consumer not found"),
+        Err(e) => return db_error(e),
+    }
+
+    let limit = q.limit.unwrap_or(100).clamp(1, MAX_FETCH_LIMIT);
+    let wait = Duration::from_secs(q.wait.unwrap_or(0).min(MAX_FETCH_WAIT_SECS));
+    let deadline = tokio::time::Instant::now() + wait;
+    let mut head = state.hub.watch_head();
+
+    loop {
+        head.borrow_and_update();
+        match db::claim_consumer_batch(&state.pool, &name, limit).await {
+            Ok(Some(claim)) => return (StatusCode::OK, Json(claim)).into_response(),
+            Ok(None) => {},
+            Err(e) => return db_error(e),
+        }
+        if wait.is_zero() {
+            break;
+        }
+        match tokio::time::timeout_at(deadline, head.changed()).await {
+            Ok(Ok(())) => continue,
+            _ => break,
+        }
+    }
+    (StatusCode::OK, Json(serde_json::json!({"claim_id": null, "events": []}))).into_response()
+}
+
+pub async fn ack_handler(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(req): Json<AckReq>,
+) -> impl IntoResponse {
+    match db::ack_consumer_claim(&state.pool, &name, req.claim_id).await {
+        Ok(Some(acked_seq)) => (StatusCode::OK, Json(serde_json::json!({"acked_seq": acked_seq}))).into_response(),
+        // The lease ran out and the batch went to another worker
+        Ok(None) => error_response(StatusCode::CONFLICT, "unknown or expired claim"),
+        Err(e) => db_error(e),
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde::{Deserialize, Serialize};
+
+use crate::db::EventPage;
+use crate::filter::EventFilter;
+
+const CURSOR_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Position in a pull listing.
+///
+/// `order` is the display order of the listing, `backward` says whether this cursor
+/// walks against it (a `prev` cursor), and `seq` is the exclusive `server_seq` bound.
+/// The filter travels with the cursor so a page can't be continued under different filters.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    #[serde(rename = "v")]
+    pub version: u8,
+    #[serde(rename = "o")]
+    pub order: Order,
+    #[serde(rename = "b", default)]
+    pub backward: bool,
+    #[serde(rename = "s")]
+    pub seq: i64,
+    #[serde(rename = "f", default)]
+    pub filter: EventFilter,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CursorError {
+    Malformed,
+    BadSignature,
+}
+
+impl fmt::Display for CursorError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CursorError::Malformed => write!(f, "malformed cursor"),
+            CursorError::BadSignature => write!(f, "cursor signature mismatch"),
+        }
+    }
+}
+
+impl std::error::Error for CursorError {}
+
+/// Signs and verifies cursor tokens.
+/// Tokens are `base64url(json).base64url(blake3_keyed(json))`, so clients can't edit
+/// the position or filters without the relay noticing.
+#[derive(Clone)]
+pub struct CursorCodec {
+    key: [u8; 32],
+}
+
+impl CursorCodec {
+    /// Derives the MAC key from an operator-provided secret.
+    /// Relays behind the same load balancer must share the secret to accept each other's cursors.
+    pub fn from_secret(secret: &str) -> Self {
+        CursorCodec { key: blake3::derive_key("tisane-relay cursor v1", secret.as_bytes()) }
+    }
+
+    pub fn from_key(key: [u8; 32]) -> Self {
+        CursorCodec { key }
+    }
+
+    pub fn encode(&self, cursor: &Cursor) -> String {
+        // Serializing a plain struct of strings/ints cannot fail
+        let body = serde_json::to_vec(cursor).unwrap_or_default();
+        let mac = blake3::keyed_hash(&self.key, &body);
+        format!("{}.{}", URL_SAFE_NO_PAD.encode(&body), URL_SAFE_NO_PAD.encode(mac.as_bytes()))
+    }
+
+    pub fn decode(&self, token: &str) -> Result<Cursor, CursorError> {
+        let (body_b64, mac_b64) = token.split_once('.').ok_or(CursorError::Malformed)?;
+        let body = URL_SAFE_NO_PAD.decode(body_b64).map_err(|_| CursorError::Malformed)?;
+        let mac_bytes: [u8; 32] = URL_SAFE_NO_PAD
+            .decode(mac_b64)
+            .map_err(|_| CursorError::Malformed)?
+            .try_into()
+            .map_err(|_| CursorError::Malformed)?;
+
+        // blake3::Hash equality is constant-time
+        if blake3::Hash::from(mac_bytes) != blake3::keyed_hash(&self.key, &body) {
+            return Err(CursorError::BadSignature);
+        }
+
+        let cursor: Cursor = serde_json::from_slice(&body).map_err(|_| CursorError::Malformed)?;
+        if cursor.version != CURSOR_VERSION {
+            return Err(CursorError::Malformed);
+        }
+        Ok(cursor)
+    }
+
+    /// Next and previous cursors for a pull page; shared by the HTTP and gRPC pulls.
+    pub fn page_cursors(
+        &self,
+        order: Order,
+        backward: bool,
+        after: Option<i64>,
+        filter: &EventFilter,
+        page: &EventPage,
+    ) -> (Option<String>, Option<String>) {
+        let first = page.events.first().map(|e| e.server_seq);
+        let last = page.events.last().map(|e| e.server_seq);
+
+        let next = match (order, backward) {
+            // Ascending listings are tailed, so there is always somewhere to continue from
+            (Order::Asc, false) => Some(last.or(after).unwrap_or(0)),
+            (Order::Desc, false) => if page.has_more { last } else { None },
+            // Walking back: the page we came from is always ahead
+            (_, true) => last.or(after),
+        };
+        let prev = if backward {
+            if page.has_more { first } else { None }
+        } else if after.is_some() {
+            first.or(after)
+        } else {
+            None
+        };
+
+        // Cursors are always derived from server_seq, which every projection keeps
+        let encode = |seq: i64, back: bool| self.encode(&Cursor::new(order, back, seq, filter.clone()));
+        (next.map(|seq| encode(seq, false)), prev.map(|seq| encode(seq, true)))
+    }
+}
+
+impl Cursor {
+    pub fn new(order: Order, backward: bool, seq: i64, filter: EventFilter) -> Self {
+        Cursor { version: CURSOR_VERSION, order, backward, seq, filter }
+    }
+}
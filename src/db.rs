@@ -1,14 +1,35 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::{PgPool, Row};
+use sqlx::postgres::PgRow;
+use sqlx::types::Json;
+use sqlx::{PgPool, Postgres, QueryBuilder, Row};
 use uuid::Uuid;
 
+use crate::cursor::Order;
+use crate::filter::{EventFilter, InboundPolicy, OutboundFilter};
+
 pub async fn run_migrations(pool: &PgPool) -> Result<(), sqlx::Error> {
     // Embeds migrations from ./migrations
     sqlx::migrate!("./migrations").run(pool).await?;
     Ok(())
 }
 
+/// A signed link from one event to another (reply, reaction, edit, ...)
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq, Eq)]
+pub struct EventRef {
+    pub event_id: Uuid,
+    pub kind: String,
+}
+
+/// Native events: Ed25519 over `utils::signing_bytes`
+pub const SIG_SCHEME_ED25519: &str = "ed25519";
+/// NIP-01 events: Schnorr over the Nostr event id, see `nostr`
+pub const SIG_SCHEME_NOSTR: &str = "nostr";
+
+fn default_sig_scheme() -> String {
+    SIG_SCHEME_ED25519.to_string()
+}
+
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct EventInput {
     pub event_id: Uuid,
@@ -22,9 +43,16 @@ pub struct EventInput {
     pub payload_json: Option<serde_json::Value>,
     pub occurred_at: Option<DateTime<Utc>>,
     pub lamport: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub refs: Vec<EventRef>,
+    /// Nostr-style tags: `[key, value, ...extra]`. Key/value pairs are indexed for filtering.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<Vec<String>>,
+    #[serde(default = "default_sig_scheme")]
+    pub sig_scheme: String,
 }
 
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Event {
     pub event_id: Uuid,
     pub server_seq: i64,
@@ -38,39 +66,251 @@ pub struct Event {
     pub payload_json: Option<serde_json::Value>,
     pub occurred_at: Option<DateTime<Utc>>,
     pub lamport: Option<i64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub refs: Vec<EventRef>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<Vec<String>>,
+    #[serde(default = "default_sig_scheme")]
+    pub sig_scheme: String,
+}
+
+/// Where a replicated event has been. Sent alongside each event between relays.
+#[derive(Debug, Deserialize, Serialize, Clone, Default, PartialEq, Eq)]
+pub struct Provenance {
+    /// `None` for events first stored on this relay
+    #[serde(default)]
+    pub origin_relay: Option<Uuid>,
+    #[serde(default)]
+    pub hops: i32,
+    /// Relays that held the event before the current one, origin first
+    #[serde(default)]
+    pub path: Vec<Uuid>,
+}
+
+impl Provenance {
+    /// Whether `relay` has already held the event
+    pub fn has_visited(&self, relay: Uuid) -> bool {
+        self.origin_relay == Some(relay) || self.path.contains(&relay)
+    }
+
+    /// What the receiving relay should record when `relay`, the current holder, forwards the event
+    pub fn forwarded_by(&self, relay: Uuid) -> Provenance {
+        let mut path = self.path.clone();
+        path.push(relay);
+        Provenance { origin_relay: Some(self.origin_relay.unwrap_or(relay)), hops: self.hops + 1, path }
+    }
+}
+
+/// Replication wire format: the signed event with its provenance fields alongside.
+/// Plain event arrays from older relays still parse, with empty provenance.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct ReplicatedEvent {
+    #[serde(flatten)]
+    pub event: EventInput,
+    #[serde(flatten)]
+    pub provenance: Provenance,
+}
+
+impl ReplicatedEvent {
+    /// An event pushed to this relay by a client
+    pub fn local(event: EventInput) -> Self {
+        ReplicatedEvent { event, provenance: Provenance::default() }
+    }
+}
+
+impl EventInput {
+    /// The stored form of this event once it has been assigned `server_seq`
+    pub fn to_event(&self, server_seq: i64) -> Event {
+        Event {
+            event_id: self.event_id,
+            server_seq,
+            author_pubkey: self.author_pubkey.clone(),
+            signature: self.signature.clone(),
+            payload_hash: self.payload_hash.clone(),
+            device_id: self.device_id.clone(),
+            author_id: self.author_id.clone(),
+            content_id: self.content_id.clone(),
+            event_type: self.event_type.clone(),
+            payload_json: self.payload_json.clone(),
+            occurred_at: self.occurred_at,
+            lamport: self.lamport,
+            refs: self.refs.clone(),
+            tags: self.tags.clone(),
+            sig_scheme: self.sig_scheme.clone(),
+        }
+    }
+}
+
+impl Event {
+    /// Converts a stored event back into the signed wire form (used for replication)
+    pub fn to_input(&self) -> EventInput {
+        EventInput {
+            event_id: self.event_id,
+            author_pubkey: self.author_pubkey.clone(),
+            signature: self.signature.clone(),
+            payload_hash: self.payload_hash.clone(),
+            device_id: self.device_id.clone(),
+            author_id: self.author_id.clone(),
+            content_id: self.content_id.clone(),
+            event_type: self.event_type.clone(),
+            payload_json: self.payload_json.clone(),
+            occurred_at: self.occurred_at,
+            lamport: self.lamport,
+            refs: self.refs.clone(),
+            tags: self.tags.clone(),
+            sig_scheme: self.sig_scheme.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, sqlx::FromRow)]
 pub struct Peer {
     pub peer_id: Uuid,
     pub url: String,
+    #[serde(skip_serializing)]
     pub shared_secret: String,
-    pub last_cursor_time: DateTime<Utc>,
-    pub last_cursor_id: Uuid,
+    /// Last `repl_seq` pushed to the peer
+    pub last_cursor_seq: i64,
+    /// The peer's own relay id, once it has told us
+    pub remote_relay_id: Option<Uuid>,
+    /// 'unknown', 'healthy', 'degraded' or 'down', see `PEER_HEALTH_*`
     pub health: String,
+    /// 'push', 'pull', 'both' or 'none'
+    pub direction: String,
+    /// Peer's replication log position we have pulled up to
+    pub remote_cursor: i64,
+    pub consecutive_failures: i32,
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    /// Backoff: nothing is sent to or requested from the peer before this
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    /// Events per outbound batch
+    pub batch_size: i32,
+    /// JSON size budget per outbound batch
+    pub max_batch_bytes: i32,
+    /// Which of our events the peer is sent
+    pub outbound_filter: Json<OutboundFilter>,
+    /// What we accept from the peer
+    pub inbound_policy: Json<InboundPolicy>,
+    /// Inbound policy violations within the current hour
+    pub policy_violations: i32,
+    pub last_violation: Option<String>,
+    pub last_violation_at: Option<DateTime<Utc>>,
+    /// Set once too many violations piled up; nothing is exchanged with a suspended peer
+    pub suspended_at: Option<DateTime<Utc>>,
+}
+
+/// Operator-controlled peer configuration
+#[derive(Debug, Clone)]
+pub struct PeerSettings {
+    /// 'push', 'pull', 'both' or 'none'
+    pub direction: String,
+    pub batch_size: i32,
+    pub max_batch_bytes: i32,
+    pub outbound_filter: OutboundFilter,
+    pub inbound_policy: InboundPolicy,
+}
+
+impl Default for PeerSettings {
+    fn default() -> Self {
+        PeerSettings {
+            direction: "push".to_string(),
+            batch_size: 50,
+            max_batch_bytes: 1024 * 1024,
+            outbound_filter: OutboundFilter::default(),
+            inbound_policy: InboundPolicy::default(),
+        }
+    }
+}
+
+pub const PEER_HEALTH_UNKNOWN: &str = "unknown";
+pub const PEER_HEALTH_HEALTHY: &str = "healthy";
+/// Failing, retried with backoff
+pub const PEER_HEALTH_DEGRADED: &str = "degraded";
+/// Failed too often; only probed on `/health` until it answers
+pub const PEER_HEALTH_DOWN: &str = "down";
+
+impl Peer {
+    pub fn pushes(&self) -> bool {
+        matches!(self.direction.as_str(), "push" | "both")
+    }
+
+    /// Read-only peers are never pulled from, whatever their direction
+    pub fn pulls(&self) -> bool {
+        matches!(self.direction.as_str(), "pull" | "both") && !self.inbound_policy.read_only
+    }
+
+    pub fn is_suspended(&self) -> bool {
+        self.suspended_at.is_some()
+    }
+
+    pub fn is_down(&self) -> bool {
+        self.health == PEER_HEALTH_DOWN
+    }
+
+    /// Whether the peer is still backing off at `now`
+    pub fn backing_off(&self, now: DateTime<Utc>) -> bool {
+        self.next_attempt_at.is_some_and(|at| at > now)
+    }
+
+    pub fn settings(&self) -> PeerSettings {
+        PeerSettings {
+            direction: self.direction.clone(),
+            batch_size: self.batch_size,
+            max_batch_bytes: self.max_batch_bytes,
+            outbound_filter: self.outbound_filter.0.clone(),
+            inbound_policy: self.inbound_policy.0.clone(),
+        }
+    }
+}
+
+/// Backoff after a peer failure: `base` doubling per consecutive failure, capped at `max`
+#[derive(Debug, Clone, Copy)]
+pub struct PeerBackoff {
+    pub base_secs: i64,
+    pub max_secs: i64,
+    /// Consecutive failures after which a peer is marked down
+    pub down_after: i32,
+}
+
+const PEER_COLUMNS: &str = "peer_id, url, shared_secret, last_cursor_seq, remote_relay_id, health, direction, remote_cursor, \
+    consecutive_failures, last_success_at, last_error_at, last_error, next_attempt_at, batch_size, max_batch_bytes, outbound_filter, \
+    inbound_policy, policy_violations, last_violation, last_violation_at, suspended_at";
+
+const EVENT_COLUMNS: &str = "event_id, server_seq, author_pubkey, signature, payload_hash, device_id, author_id, content_id, event_type, payload_json, occurred_at, lamport, refs, tags, sig_scheme";
+
+fn event_from_row(row: &PgRow) -> Event {
+    Event {
+        event_id: row.get::<Uuid, _>("event_id"),
+        server_seq: row.get::<i64, _>("server_seq"),
+        author_pubkey: row.get::<String, _>("author_pubkey"),
+        signature: row.get::<String, _>("signature"),
+        payload_hash: row.get::<String, _>("payload_hash"),
+        device_id: row.get::<Option<String>, _>("device_id"),
+        author_id: row.get::<Option<String>, _>("author_id"),
+        content_id: row.get::<Option<String>, _>("content_id"),
+        event_type: row.get::<Option<String>, _>("event_type"),
+        payload_json: row.get::<Option<serde_json::Value>, _>("payload_json"),
+        occurred_at: row.get::<Option<DateTime<Utc>>, _>("occurred_at"),
+        lamport: row.get::<Option<i64>, _>("lamport"),
+        refs: row
+            .get::<Option<Json<Vec<EventRef>>>, _>("refs")
+            .map(|r| r.0)
+            .unwrap_or_default(),
+        tags: row
+            .get::<Option<Json<Vec<Vec<String>>>>, _>("tags")
+            .map(|t| t.0)
+            .unwrap_or_default(),
+        sig_scheme: row.get::<String, _>("sig_scheme"),
+    }
 }
 
 pub async fn insert_events(pool: &PgPool, events: &[EventInput]) -> Result<Vec<i64>, sqlx::Error> {
     let mut inserted = Vec::new();
 
     for ev in events {
-        let row = sqlx::query("INSERT INTO events (event_id, author_pubkey, signature, payload_hash, device_id, author_id, content_id, event_type, payload_json, occurred_at, lamport) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11) ON CONFLICT (event_id) DO NOTHING RETURNING server_seq")
-            .bind(ev.event_id)
-            .bind(&ev.author_pubkey)
-            .bind(&ev.signature)
-            .bind(&ev.payload_hash)
-            .bind(&ev.device_id)
-            .bind(&ev.author_id)
-            .bind(&ev.content_id)
-            .bind(&ev.event_type)
-            .bind(&ev.payload_json)
-            .bind(&ev.occurred_at)
-            .bind(&ev.lamport)
-            .fetch_optional(pool)
-            .await?;
-
-        if let Some(r) = row {
-            let seq: i64 = r.get("server_seq");
+        if let Some(seq) = insert_event(pool, ev).await? {
             inserted.push(seq);
         }
     }
@@ -78,68 +318,477 @@ pub async fn insert_events(pool: &PgPool, events: &[EventInput]) -> Result<Vec<i
     Ok(inserted)
 }
 
+/// Inserts one event with its reference edges and tag index rows. Returns `None` if the event already existed.
+pub async fn insert_event(pool: &PgPool, ev: &EventInput) -> Result<Option<i64>, sqlx::Error> {
+    insert_event_with_provenance(pool, ev, &Provenance::default()).await
+}
+
+/// `insert_event` for an event received from another relay
+pub async fn insert_event_with_provenance(pool: &PgPool, ev: &EventInput, provenance: &Provenance) -> Result<Option<i64>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let refs_json = if ev.refs.is_empty() { None } else { Some(Json(&ev.refs)) };
+    let tags_json = if ev.tags.is_empty() { None } else { Some(Json(&ev.tags)) };
+    let row = sqlx::query("INSERT INTO events (event_id, author_pubkey, signature, payload_hash, device_id, author_id, content_id, event_type, payload_json, occurred_at, lamport, refs, tags, sig_scheme, origin_relay, hops, relay_path) VALUES ($1,$2,$3,$4,$5,$6,$7,$8,$9,$10,$11,$12,$13,$14,$15,$16,$17) ON CONFLICT (event_id) DO NOTHING RETURNING server_seq")
+        .bind(ev.event_id)
+        .bind(&ev.author_pubkey)
+        .bind(&ev.signature)
+        .bind(&ev.payload_hash)
+        .bind(&ev.device_id)
+        .bind(&ev.author_id)
+        .bind(&ev.content_id)
+        .bind(&ev.event_type)
+        .bind(&ev.payload_json)
+        .bind(&ev.occurred_at)
+        .bind(&ev.lamport)
+        .bind(refs_json)
+        .bind(tags_json)
+        .bind(&ev.sig_scheme)
+        .bind(provenance.origin_relay)
+        .bind(provenance.hops)
+        .bind(&provenance.path)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let Some(r) = row else {
+        return Ok(None);
+    };
+    let seq: i64 = r.get("server_seq");
+
+    for rf in &ev.refs {
+        sqlx::query("INSERT INTO event_refs (event_id, target_id, kind) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+            .bind(ev.event_id)
+            .bind(rf.event_id)
+            .bind(&rf.kind)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    // Only `[key, value, ...]` tags are indexed; extra elements stay in the JSON column
+    for tag in ev.tags.iter().filter(|t| t.len() >= 2) {
+        sqlx::query("INSERT INTO event_tags (event_id, key, value) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+            .bind(ev.event_id)
+            .bind(&tag[0])
+            .bind(&tag[1])
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(Some(seq))
+}
+
 pub async fn fetch_events_since(pool: &PgPool, since: i64, limit: i64) -> Result<(Vec<Event>, i64), sqlx::Error> {
-    let rows = sqlx::query("SELECT event_id, server_seq, author_pubkey, signature, payload_hash, device_id, author_id, content_id, event_type, payload_json, occurred_at, lamport FROM events WHERE server_seq > $1 ORDER BY server_seq ASC LIMIT $2")
+    let rows = sqlx::query(&format!("SELECT {} FROM events WHERE server_seq > $1 ORDER BY server_seq ASC LIMIT $2", EVENT_COLUMNS))
         .bind(since)
         .bind(limit)
         .fetch_all(pool)
         .await?;
 
-    let mut events = Vec::with_capacity(rows.len());
-    for row in rows.iter() {
-        let event = Event {
-            event_id: row.get::<Uuid, _>("event_id"),
-            server_seq: row.get::<i64, _>("server_seq"),
-            author_pubkey: row.get::<String, _>("author_pubkey"),
-            signature: row.get::<String, _>("signature"),
-            payload_hash: row.get::<String, _>("payload_hash"),
-            device_id: row.get::<Option<String>, _>("device_id"),
-            author_id: row.get::<Option<String>, _>("author_id"),
-            content_id: row.get::<Option<String>, _>("content_id"),
-            event_type: row.get::<Option<String>, _>("event_type"),
-            payload_json: row.get::<Option<serde_json::Value>, _>("payload_json"),
-            occurred_at: row.get::<Option<DateTime<Utc>>, _>("occurred_at"),
-            lamport: row.get::<Option<i64>, _>("lamport"),
-        };
-        events.push(event);
-    }
+    let events: Vec<Event> = rows.iter().map(event_from_row).collect();
 
     let next_cursor = events.last().map(|e| e.server_seq).unwrap_or(since);
     Ok((events, next_cursor))
 }
 
+pub struct EventPage {
+    /// Events in display order
+    pub events: Vec<Event>,
+    /// Whether more events exist past this page in the direction of travel
+    pub has_more: bool,
+}
+
+/// Fetches one page of events ordered by `server_seq`.
+///
+/// `after` is an exclusive bound in the direction of travel. When `backward` is set the
+/// page walks against `order` (towards the previous page) and is flipped back before returning.
+/// With `include_payload` unset, `payload_json` is not read from storage (header-only sync).
+pub async fn fetch_events_page(
+    pool: &PgPool,
+    filter: &EventFilter,
+    order: Order,
+    after: Option<i64>,
+    backward: bool,
+    limit: i64,
+    include_payload: bool,
+) -> Result<EventPage, sqlx::Error> {
+    let ascending = (order == Order::Asc) != backward;
+
+    let columns = if include_payload {
+        EVENT_COLUMNS.to_string()
+    } else {
+        EVENT_COLUMNS.replace("payload_json", "NULL::jsonb AS payload_json")
+    };
+    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(format!("SELECT {} FROM events WHERE TRUE", columns));
+    if let Some(seq) = after {
+        qb.push(if ascending { " AND server_seq > " } else { " AND server_seq < " });
+        qb.push_bind(seq);
+    }
+    filter.push_conditions(&mut qb);
+    qb.push(if ascending { " ORDER BY server_seq ASC" } else { " ORDER BY server_seq DESC" });
+    // Fetch one extra row to learn whether another page exists
+    qb.push(" LIMIT ").push_bind(limit + 1);
+
+    let rows = qb.build().fetch_all(pool).await?;
+    let has_more = rows.len() as i64 > limit;
+    let mut events: Vec<Event> = rows.iter().take(limit as usize).map(event_from_row).collect();
+    if backward {
+        events.reverse();
+    }
+
+    Ok(EventPage { events, has_more })
+}
+
+/// The `limit` matching events that occurred last, newest first (ties by `server_seq`)
+pub async fn fetch_latest_events(pool: &PgPool, filter: &EventFilter, limit: i64) -> Result<Vec<Event>, sqlx::Error> {
+    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(format!("SELECT {} FROM events WHERE TRUE", EVENT_COLUMNS));
+    filter.push_conditions(&mut qb);
+    qb.push(" ORDER BY occurred_at DESC, server_seq DESC LIMIT ").push_bind(limit);
+
+    let rows = qb.build().fetch_all(pool).await?;
+    Ok(rows.iter().map(event_from_row).collect())
+}
+
+pub async fn fetch_event(pool: &PgPool, event_id: Uuid) -> Result<Option<Event>, sqlx::Error> {
+    let row = sqlx::query(&format!("SELECT {} FROM events WHERE event_id = $1", EVENT_COLUMNS))
+        .bind(event_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.as_ref().map(event_from_row))
+}
+
+pub const MAX_FETCH_IDS: usize = 500;
+
+/// Fetches events by ID, in `server_seq` order. Unknown IDs are skipped.
+pub async fn fetch_events_by_ids(pool: &PgPool, event_ids: &[Uuid]) -> Result<Vec<Event>, sqlx::Error> {
+    let rows = sqlx::query(&format!("SELECT {} FROM events WHERE event_id = ANY($1) ORDER BY server_seq ASC", EVENT_COLUMNS))
+        .bind(event_ids)
+        .fetch_all(pool)
+        .await?;
+    Ok(rows.iter().map(event_from_row).collect())
+}
+
+/// An event reached while walking reference edges, with its distance from the root
+#[derive(Debug, Serialize, Clone)]
+pub struct ThreadEntry {
+    pub depth: i32,
+    pub event: Event,
+}
+
+pub struct Thread {
+    pub ancestors: Vec<ThreadEntry>,
+    pub descendants: Vec<ThreadEntry>,
+}
+
+const MAX_THREAD_EVENTS: i64 = 1000;
+
+/// Walks `event_refs` up (events the root points at) and down (events pointing at the root)
+/// to at most `max_depth` hops. Targets that haven't reached this relay yet are skipped.
+pub async fn fetch_thread(pool: &PgPool, root: Uuid, max_depth: i32) -> Result<Thread, sqlx::Error> {
+    // UNION plus the depth bound keeps cyclic references from recursing forever
+    let ancestors_sql = format!(
+        "WITH RECURSIVE walk(id, depth) AS (
+            SELECT target_id, 1 FROM event_refs WHERE event_id = $1
+            UNION
+            SELECT r.target_id, w.depth + 1 FROM event_refs r JOIN walk w ON r.event_id = w.id WHERE w.depth < $2
+        )
+        SELECT w.depth, {cols} FROM (SELECT id, MIN(depth) AS depth FROM walk WHERE id <> $1 GROUP BY id) w
+        JOIN events e ON e.event_id = w.id ORDER BY w.depth ASC, e.server_seq ASC LIMIT $3",
+        cols = prefixed_event_columns("e")
+    );
+    let descendants_sql = format!(
+        "WITH RECURSIVE walk(id, depth) AS (
+            SELECT event_id, 1 FROM event_refs WHERE target_id = $1
+            UNION
+            SELECT r.event_id, w.depth + 1 FROM event_refs r JOIN walk w ON r.target_id = w.id WHERE w.depth < $2
+        )
+        SELECT w.depth, {cols} FROM (SELECT id, MIN(depth) AS depth FROM walk WHERE id <> $1 GROUP BY id) w
+        JOIN events e ON e.event_id = w.id ORDER BY w.depth ASC, e.server_seq ASC LIMIT $3",
+        cols = prefixed_event_columns("e")
+    );
+
+    let mut walks = Vec::with_capacity(2);
+    for sql in [ancestors_sql, descendants_sql] {
+        let rows = sqlx::query(&sql)
+            .bind(root)
+            .bind(max_depth)
+            .bind(MAX_THREAD_EVENTS)
+            .fetch_all(pool)
+            .await?;
+        walks.push(
+            rows.iter()
+                .map(|row| ThreadEntry { depth: row.get::<i32, _>("depth"), event: event_from_row(row) })
+                .collect::<Vec<_>>(),
+        );
+    }
+
+    let descendants = walks.pop().unwrap_or_default();
+    let ancestors = walks.pop().unwrap_or_default();
+    Ok(Thread { ancestors, descendants })
+}
+
+fn prefixed_event_columns(alias: &str) -> String {
+    EVENT_COLUMNS
+        .split(", ")
+        .map(|c| format!("{}.{}", alias, c))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// ----- STATS QUERIES -----
+
+/// Columns that may be used in a stats GROUP BY. Anything else is rejected before reaching SQL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsDimension {
+    EventType,
+    Author,
+    AuthorId,
+    ContentId,
+    DeviceId,
+}
+
+impl StatsDimension {
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "event_type" => Some(StatsDimension::EventType),
+            "author" => Some(StatsDimension::Author),
+            "author_id" => Some(StatsDimension::AuthorId),
+            "content_id" => Some(StatsDimension::ContentId),
+            "device_id" => Some(StatsDimension::DeviceId),
+            _ => None,
+        }
+    }
+
+    /// Name used in the response rows (matches the query parameter)
+    pub fn name(self) -> &'static str {
+        match self {
+            StatsDimension::EventType => "event_type",
+            StatsDimension::Author => "author",
+            StatsDimension::AuthorId => "author_id",
+            StatsDimension::ContentId => "content_id",
+            StatsDimension::DeviceId => "device_id",
+        }
+    }
+
+    fn column(self) -> &'static str {
+        match self {
+            StatsDimension::EventType => "event_type",
+            StatsDimension::Author => "author_pubkey",
+            StatsDimension::AuthorId => "author_id",
+            StatsDimension::ContentId => "content_id",
+            StatsDimension::DeviceId => "device_id",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StatsBucket {
+    Minute,
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+impl StatsBucket {
+    fn unit(self) -> &'static str {
+        match self {
+            StatsBucket::Minute => "minute",
+            StatsBucket::Hour => "hour",
+            StatsBucket::Day => "day",
+            StatsBucket::Week => "week",
+            StatsBucket::Month => "month",
+        }
+    }
+
+    /// Upper bound of one bucket's width, used to cap the number of buckets per query
+    pub fn width(self) -> chrono::Duration {
+        match self {
+            StatsBucket::Minute => chrono::Duration::minutes(1),
+            StatsBucket::Hour => chrono::Duration::hours(1),
+            StatsBucket::Day => chrono::Duration::days(1),
+            StatsBucket::Week => chrono::Duration::weeks(1),
+            StatsBucket::Month => chrono::Duration::days(31),
+        }
+    }
+}
+
+pub struct StatsQuery {
+    pub group_by: Vec<StatsDimension>,
+    pub bucket: Option<StatsBucket>,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub filter: EventFilter,
+    pub max_groups: i64,
+}
+
+pub struct StatsResult {
+    /// One JSON object per group: the dimensions, optional `bucket` and `count`
+    pub rows: Vec<serde_json::Value>,
+    /// Set when more than `max_groups` groups matched; with a bucket, the oldest groups are
+    /// the ones left out
+    pub truncated: bool,
+}
+
+/// Counts events over `[from, to)` grouped by whitelisted dimensions and an optional time bucket.
+/// Runs under a statement timeout so a pathological group-by can't pin a connection.
+pub async fn fetch_event_stats(pool: &PgPool, q: &StatsQuery) -> Result<StatsResult, sqlx::Error> {
+    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new("SELECT ");
+    let mut group_cols = 0;
+    if let Some(bucket) = q.bucket {
+        // unit comes from a closed enum, so it's safe to inline
+        qb.push(format!("date_trunc('{}', occurred_at) AS bucket, ", bucket.unit()));
+        group_cols += 1;
+    }
+    for dim in &q.group_by {
+        qb.push(format!("{} AS {}, ", dim.column(), dim.name()));
+        group_cols += 1;
+    }
+    qb.push("COUNT(*) AS count FROM events WHERE occurred_at >= ");
+    qb.push_bind(q.from);
+    qb.push(" AND occurred_at < ");
+    qb.push_bind(q.to);
+    q.filter.push_conditions(&mut qb);
+
+    if group_cols > 0 {
+        let ordinals: Vec<String> = (1..=group_cols).map(|i| i.to_string()).collect();
+        qb.push(format!(" GROUP BY {}", ordinals.join(", ")));
+    }
+    // Newest buckets first, so the cap leaves out the oldest ones
+    if q.bucket.is_some() {
+        qb.push(" ORDER BY bucket DESC, count DESC");
+    } else {
+        qb.push(" ORDER BY count DESC");
+    }
+    qb.push(" LIMIT ").push_bind(q.max_groups + 1);
+
+    let mut tx = pool.begin().await?;
+    sqlx::query("SET LOCAL statement_timeout = '10s'").execute(&mut *tx).await?;
+    let rows = qb.build().fetch_all(&mut *tx).await?;
+    tx.commit().await?;
+
+    let truncated = rows.len() as i64 > q.max_groups;
+    let mut rows: Vec<&PgRow> = rows.iter().take(q.max_groups as usize).collect();
+    if q.bucket.is_some() {
+        // Listed oldest first; the sort is stable, so groups within a bucket stay by count
+        rows.sort_by_key(|row| row.get::<DateTime<Utc>, _>("bucket"));
+    }
+    let rows = rows
+        .into_iter()
+        .map(|row| {
+            let mut obj = serde_json::Map::new();
+            if q.bucket.is_some() {
+                obj.insert("bucket".into(), serde_json::json!(row.get::<DateTime<Utc>, _>("bucket")));
+            }
+            for dim in &q.group_by {
+                obj.insert(dim.name().into(), serde_json::json!(row.get::<Option<String>, _>(dim.name())));
+            }
+            obj.insert("count".into(), serde_json::json!(row.get::<i64, _>("count")));
+            serde_json::Value::Object(obj)
+        })
+        .collect();
+
+    Ok(StatsResult { rows, truncated })
+}
+
 // ----- PEER & REPLICATION QUERIES -----
 
-// Fetch all healthy peers
+// Fetch all peers that are neither down nor suspended
 pub async fn fetch_healthy_peers(pool: &PgPool) -> Result<Vec<Peer>, sqlx::Error> {
-    sqlx::query_as::<_, Peer>("SELECT peer_id, url, shared_secret, last_cursor_time, last_cursor_id, health FROM peers WHERE health = 'healthy' OR health = 'unknown'")
+    sqlx::query_as::<_, Peer>(&format!("SELECT {} FROM peers WHERE health <> 'down' AND suspended_at IS NULL", PEER_COLUMNS))
         .fetch_all(pool)
         .await
 }
 
 // Fetch all peers (for admin listing)
 pub async fn fetch_all_peers(pool: &PgPool) -> Result<Vec<Peer>, sqlx::Error> {
-    sqlx::query_as::<_, Peer>("SELECT peer_id, url, shared_secret, last_cursor_time, last_cursor_id, health FROM peers")
+    sqlx::query_as::<_, Peer>(&format!("SELECT {} FROM peers", PEER_COLUMNS))
         .fetch_all(pool)
         .await
 }
 
 // Add a new peer
-pub async fn add_peer(pool: &PgPool, url: String, shared_secret: String) -> Result<Uuid, sqlx::Error> {
+pub async fn add_peer(pool: &PgPool, url: String, shared_secret: String, settings: &PeerSettings) -> Result<Uuid, sqlx::Error> {
     let peer_id = Uuid::new_v4();
-    // Default to UNIX epoch to avoid 'infinity' parsing issues in chrono
-    let default_time = DateTime::parse_from_rfc3339("1970-01-01T00:00:00Z").unwrap().with_timezone(&Utc);
-    
-    sqlx::query("INSERT INTO peers (peer_id, url, shared_secret, last_cursor_time) VALUES ($1, $2, $3, $4)")
+    // last_cursor_seq starts at 0: a new peer receives the full history
+    sqlx::query(
+        "INSERT INTO peers (peer_id, url, shared_secret, direction, batch_size, max_batch_bytes, outbound_filter, inbound_policy) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")
         .bind(peer_id)
         .bind(url)
         .bind(shared_secret)
-        .bind(default_time)
+        .bind(&settings.direction)
+        .bind(settings.batch_size)
+        .bind(settings.max_batch_bytes)
+        .bind(Json(&settings.outbound_filter))
+        .bind(Json(&settings.inbound_policy))
         .execute(pool)
         .await?;
     Ok(peer_id)
 }
 
+// Replace a peer's settings; the push cursor stays where it is
+pub async fn update_peer_settings(pool: &PgPool, peer_id: Uuid, settings: &PeerSettings) -> Result<Option<Peer>, sqlx::Error> {
+    sqlx::query_as::<_, Peer>(&format!(
+        "UPDATE peers SET direction = $2, batch_size = $3, max_batch_bytes = $4, outbound_filter = $5, inbound_policy = $6, \
+         updated_at = NOW() WHERE peer_id = $1 RETURNING {}",
+        PEER_COLUMNS
+    ))
+    .bind(peer_id)
+    .bind(&settings.direction)
+    .bind(settings.batch_size)
+    .bind(settings.max_batch_bytes)
+    .bind(Json(&settings.outbound_filter))
+    .bind(Json(&settings.inbound_policy))
+    .fetch_optional(pool)
+    .await
+}
+
+/// A peer's violation count including one more now: violations older than an hour are forgotten
+const VIOLATIONS_WITH_NEW: &str = "CASE WHEN last_violation_at > NOW() - INTERVAL '1 hour' THEN policy_violations + 1 ELSE 1 END";
+
+// Count an inbound policy violation; reaching `suspend_after` within the hour suspends the
+// peer (0 never does)
+pub async fn record_peer_violation(pool: &PgPool, peer_id: Uuid, reason: &str, suspend_after: i32) -> Result<Peer, sqlx::Error> {
+    // SET expressions see the old policy_violations and last_violation_at
+    sqlx::query_as::<_, Peer>(&format!(
+        "UPDATE peers SET \
+             policy_violations = {violations}, \
+             last_violation = $2, \
+             last_violation_at = NOW(), \
+             suspended_at = CASE WHEN suspended_at IS NULL AND $3 > 0 AND {violations} >= $3 THEN NOW() ELSE suspended_at END, \
+             updated_at = NOW() \
+         WHERE peer_id = $1 RETURNING {columns}",
+        violations = VIOLATIONS_WITH_NEW,
+        columns = PEER_COLUMNS
+    ))
+    .bind(peer_id)
+    .bind(reason)
+    .bind(suspend_after)
+    .fetch_one(pool)
+    .await
+}
+
+// Lift a suspension and forget past violations
+pub async fn unsuspend_peer(pool: &PgPool, peer_id: Uuid) -> Result<Option<Peer>, sqlx::Error> {
+    sqlx::query_as::<_, Peer>(&format!(
+        "UPDATE peers SET suspended_at = NULL, policy_violations = 0, updated_at = NOW() WHERE peer_id = $1 RETURNING {}",
+        PEER_COLUMNS
+    ))
+    .bind(peer_id)
+    .fetch_optional(pool)
+    .await
+}
+
+// Fetch one peer
+pub async fn fetch_peer(pool: &PgPool, peer_id: Uuid) -> Result<Option<Peer>, sqlx::Error> {
+    sqlx::query_as::<_, Peer>(&format!("SELECT {} FROM peers WHERE peer_id = $1", PEER_COLUMNS))
+        .bind(peer_id)
+        .fetch_optional(pool)
+        .await
+}
+
 // Remove a peer
 pub async fn remove_peer(pool: &PgPool, peer_id: Uuid) -> Result<bool, sqlx::Error> {
     let result = sqlx::query("DELETE FROM peers WHERE peer_id = $1")
@@ -151,54 +800,912 @@ pub async fn remove_peer(pool: &PgPool, peer_id: Uuid) -> Result<bool, sqlx::Err
 
 // Validate a peer token (returns the Peer if found and authorized)
 pub async fn validate_peer_token(pool: &PgPool, token: &str) -> Result<Option<Peer>, sqlx::Error> {
-    sqlx::query_as::<_, Peer>("SELECT peer_id, url, shared_secret, last_cursor_time, last_cursor_id, health FROM peers WHERE shared_secret = $1")
+    sqlx::query_as::<_, Peer>(&format!("SELECT {} FROM peers WHERE shared_secret = $1", PEER_COLUMNS))
         .bind(token)
         .fetch_optional(pool)
         .await
 }
 
-// Update cursor for a peer
-pub async fn update_peer_cursor(pool: &PgPool, peer_id: Uuid, last_time: DateTime<Utc>, last_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query("UPDATE peers SET last_cursor_time = $1, last_cursor_id = $2, updated_at = NOW() WHERE peer_id = $3")
-        .bind(last_time)
-        .bind(last_id)
+// Update the push cursor for a peer; skip entries at or below it are no longer needed
+pub async fn update_peer_cursor(pool: &PgPool, peer_id: Uuid, last_seq: i64) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE peers SET last_cursor_seq = $1, updated_at = NOW() WHERE peer_id = $2")
+        .bind(last_seq)
+        .bind(peer_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM peer_replication_skips WHERE peer_id = $1 AND repl_seq <= $2")
+        .bind(peer_id)
+        .bind(last_seq)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+// Remember the relay id a peer identified itself with
+pub async fn update_peer_relay_id(pool: &PgPool, peer_id: Uuid, relay_id: Uuid) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE peers SET remote_relay_id = $1, updated_at = NOW() WHERE peer_id = $2")
+        .bind(relay_id)
         .bind(peer_id)
         .execute(pool)
         .await?;
     Ok(())
 }
 
-// Fetch events for replication (since time,id)
-pub async fn fetch_replication_batch(pool: &PgPool, last_time: DateTime<Utc>, last_id: Uuid, limit: i64) -> Result<Vec<Event>, sqlx::Error> {
-    // Composite cursor Logic:
-    // (occurred_at, event_id) > (last_time, last_id)
-    // equiv to: occurred_at > last_time OR (occurred_at = last_time AND event_id > last_id)
-    
-    let rows = sqlx::query("SELECT event_id, server_seq, author_pubkey, signature, payload_hash, device_id, author_id, content_id, event_type, payload_json, occurred_at, lamport FROM events WHERE (occurred_at > $1) OR (occurred_at = $1 AND event_id > $2) ORDER BY occurred_at ASC, event_id ASC LIMIT $3")
-        .bind(last_time)
-        .bind(last_id)
+// A request to the peer succeeded: clear failures and backoff
+pub async fn record_peer_success(pool: &PgPool, peer_id: Uuid) -> Result<Peer, sqlx::Error> {
+    sqlx::query_as::<_, Peer>(&format!(
+        "UPDATE peers SET health = 'healthy', consecutive_failures = 0, last_success_at = NOW(), next_attempt_at = NULL, updated_at = NOW() \
+         WHERE peer_id = $1 RETURNING {}",
+        PEER_COLUMNS
+    ))
+    .bind(peer_id)
+    .fetch_one(pool)
+    .await
+}
+
+// A request to the peer failed: count it, back off, and mark the peer degraded or down
+pub async fn record_peer_failure(pool: &PgPool, peer_id: Uuid, error: &str, backoff: PeerBackoff) -> Result<Peer, sqlx::Error> {
+    // SET expressions see the old consecutive_failures; the exponent is clamped so the
+    // power can't overflow before LEAST applies the cap
+    sqlx::query_as::<_, Peer>(&format!(
+        "UPDATE peers SET \
+             consecutive_failures = consecutive_failures + 1, \
+             health = CASE WHEN consecutive_failures + 1 >= $3 THEN 'down' ELSE 'degraded' END, \
+             last_error_at = NOW(), \
+             last_error = $2, \
+             next_attempt_at = NOW() + make_interval(secs => LEAST($4 * power(2, LEAST(consecutive_failures, 30)), $5)), \
+             updated_at = NOW() \
+         WHERE peer_id = $1 RETURNING {}",
+        PEER_COLUMNS
+    ))
+    .bind(peer_id)
+    .bind(error)
+    .bind(backoff.down_after)
+    .bind(backoff.base_secs as f64)
+    .bind(backoff.max_secs as f64)
+    .fetch_one(pool)
+    .await
+}
+
+// A down peer answered its /health probe: let replication try again right away. It is
+// left one failure short of down, so it only counts as healthy after a real exchange.
+pub async fn record_peer_probe_ok(pool: &PgPool, peer_id: Uuid, backoff: PeerBackoff) -> Result<Peer, sqlx::Error> {
+    sqlx::query_as::<_, Peer>(&format!(
+        "UPDATE peers SET health = 'degraded', consecutive_failures = $2, next_attempt_at = NULL, updated_at = NOW() \
+         WHERE peer_id = $1 RETURNING {}",
+        PEER_COLUMNS
+    ))
+    .bind(peer_id)
+    .bind((backoff.down_after - 1).max(0))
+    .fetch_one(pool)
+    .await
+}
+
+// Update the pull cursor for a peer
+pub async fn update_peer_remote_cursor(pool: &PgPool, peer_id: Uuid, remote_cursor: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE peers SET remote_cursor = $1, updated_at = NOW() WHERE peer_id = $2")
+        .bind(remote_cursor)
+        .bind(peer_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Advisory lock key serializing `sequence_replication_log` across relay instances
+const REPL_LOG_LOCK_KEY: i64 = 0x7469_7361_6e65_726c;
+/// Events given a `repl_seq` per sequencer transaction
+const REPL_LOG_CHUNK: i64 = 1000;
+
+// Assign repl_seq to committed events that don't have one yet, oldest server_seq first.
+// Only one sequencer runs at a time and each commits before the next starts, so every
+// snapshot sees repl_seq values as a gap-free prefix: a reader that has seen N will never
+// later find a committed event below N. Returns how many events were sequenced.
+pub async fn sequence_replication_log(pool: &PgPool) -> Result<u64, sqlx::Error> {
+    let mut total = 0;
+    loop {
+        let mut tx = pool.begin().await?;
+        sqlx::query("SELECT pg_advisory_xact_lock($1)")
+            .bind(REPL_LOG_LOCK_KEY)
+            .execute(&mut *tx)
+            .await?;
+        let result = sqlx::query(
+            "UPDATE events e SET repl_seq = s.repl_seq \
+             FROM (SELECT event_id, nextval('events_repl_seq') AS repl_seq \
+                     FROM (SELECT event_id FROM events WHERE repl_seq IS NULL ORDER BY server_seq LIMIT $1) pending) s \
+             WHERE e.event_id = s.event_id",
+        )
+        .bind(REPL_LOG_CHUNK)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        total += result.rows_affected();
+        if (result.rows_affected() as i64) < REPL_LOG_CHUNK {
+            return Ok(total);
+        }
+    }
+}
+
+/// Highest `repl_seq` assigned so far (0 when nothing is sequenced)
+pub async fn fetch_replication_log_head(pool: &PgPool) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("SELECT COALESCE(MAX(repl_seq), 0) FROM events")
+        .fetch_one(pool)
+        .await
+}
+
+/// Replication log position of the event stored as `server_seq` (None when it is unknown
+/// or not sequenced yet)
+pub async fn fetch_repl_seq(pool: &PgPool, server_seq: i64) -> Result<Option<i64>, sqlx::Error> {
+    let repl_seq: Option<Option<i64>> = sqlx::query_scalar("SELECT repl_seq FROM events WHERE server_seq = $1")
+        .bind(server_seq)
+        .fetch_optional(pool)
+        .await?;
+    Ok(repl_seq.flatten())
+}
+
+/// A sequenced event with its position in the replication log
+pub struct ReplicationEntry {
+    pub repl_seq: i64,
+    pub event: Event,
+    pub provenance: Provenance,
+}
+
+/// A page of the replication log as seen through a peer's filter
+pub struct ReplicationBatch {
+    pub entries: Vec<ReplicationEntry>,
+    /// Log position up to which every event was examined. Past the last entry when the
+    /// batch came back short, so events the filter left out are not scanned again.
+    pub scanned_to: i64,
+}
+
+// Fetch sequenced events after `after_seq` that pass `filter`, in repl_seq order. With a
+// peer, events it already received under its pre-migration cursor are left out.
+pub async fn fetch_replication_batch(
+    pool: &PgPool,
+    peer_id: Option<Uuid>,
+    filter: &OutboundFilter,
+    after_seq: i64,
+    limit: i64,
+) -> Result<ReplicationBatch, sqlx::Error> {
+    // Read the head first: everything at or below it is visible to the batch query too,
+    // since sequenced positions only ever form a prefix
+    let head = fetch_replication_log_head(pool).await?;
+
+    let mut qb = QueryBuilder::<Postgres>::new(format!("SELECT {}, repl_seq, origin_relay, hops, relay_path FROM events WHERE repl_seq > ", EVENT_COLUMNS));
+    qb.push_bind(after_seq);
+    qb.push(" AND NOT EXISTS (SELECT 1 FROM peer_replication_skips s WHERE s.peer_id = ")
+        .push_bind(peer_id)
+        .push(" AND s.repl_seq = events.repl_seq)");
+    filter.push_conditions(&mut qb);
+    qb.push(" ORDER BY repl_seq ASC LIMIT ").push_bind(limit);
+    let rows = qb.build().fetch_all(pool).await?;
+
+    let entries: Vec<ReplicationEntry> = rows
+        .iter()
+        .map(|row| ReplicationEntry {
+            repl_seq: row.get("repl_seq"),
+            event: event_from_row(row),
+            provenance: provenance_from_row(row),
+        })
+        .collect();
+    let last = entries.last().map_or(after_seq, |e| e.repl_seq);
+    let scanned_to = if (entries.len() as i64) < limit { last.max(head) } else { last };
+    Ok(ReplicationBatch { entries, scanned_to })
+}
+
+/// One batch of events for a peer, cut from a page of the replication log
+pub struct OutboundBatch {
+    pub events: Vec<ReplicatedEvent>,
+    /// Log position of each event in `events`
+    pub seqs: Vec<i64>,
+    /// Replication cursor covering every entry consumed, including ones not sent to the peer
+    pub cursor: i64,
+    /// Entries consumed; fewer than fetched when the byte budget cut the batch short
+    pub consumed: usize,
+    /// Entries in the page the batch was cut from
+    pub fetched: usize,
+}
+
+impl OutboundBatch {
+    /// Whether the log probably holds more for the peer: the page was full, or the byte
+    /// budget cut the batch short. An empty follow-up is harmless.
+    pub fn has_more(&self, limit: i64) -> bool {
+        self.fetched as i64 == limit || self.consumed < self.fetched
+    }
+}
+
+impl ReplicationBatch {
+    /// Builds the batch for `peer` from this (already filtered) page after `after`, with
+    /// provenance extended by `relay_id`. Leaves out events the peer (or its relay) has
+    /// already held and events that would exceed `max_hops`, and stops at the peer's byte
+    /// budget.
+    pub fn into_outbound(self, relay_id: Uuid, max_hops: i32, peer: &Peer, after: i64) -> OutboundBatch {
+        let max_bytes = peer.max_batch_bytes.max(0) as usize;
+        let fetched = self.entries.len();
+        let mut batch = OutboundBatch { events: Vec::new(), seqs: Vec::new(), cursor: after, consumed: 0, fetched };
+        let mut bytes = 2;
+
+        for entry in self.entries {
+            let forward = entry.provenance.hops < max_hops
+                && peer.remote_relay_id.is_none_or(|rid| !entry.provenance.has_visited(rid));
+            if forward {
+                let ev = ReplicatedEvent { event: entry.event.to_input(), provenance: entry.provenance.forwarded_by(relay_id) };
+                let size = serde_json::to_vec(&ev).map(|v| v.len() + 1).unwrap_or(0);
+                // An event over the budget on its own still goes out alone, or it would block the peer
+                if !batch.events.is_empty() && bytes + size > max_bytes {
+                    break;
+                }
+                bytes += size;
+                batch.events.push(ev);
+                batch.seqs.push(entry.repl_seq);
+            }
+            batch.cursor = entry.repl_seq;
+            batch.consumed += 1;
+        }
+        // With the whole page consumed, the cursor also passes events the peer's filter left out
+        if batch.consumed == fetched {
+            batch.cursor = batch.cursor.max(self.scanned_to);
+        }
+        batch
+    }
+}
+
+fn provenance_from_row(row: &PgRow) -> Provenance {
+    Provenance {
+        origin_relay: row.get("origin_relay"),
+        hops: row.get("hops"),
+        path: row.get("relay_path"),
+    }
+}
+
+// ----- REPLICATION QUARANTINE QUERIES -----
+
+pub const QUARANTINE_OUTBOUND: &str = "outbound";
+pub const QUARANTINE_INBOUND: &str = "inbound";
+
+/// An event a peer refused (or we refused from a peer) for good
+#[derive(Debug, Serialize, sqlx::FromRow)]
+pub struct QuarantinedEvent {
+    pub peer_id: Uuid,
+    pub event_id: Uuid,
+    /// `QUARANTINE_OUTBOUND` or `QUARANTINE_INBOUND`
+    pub direction: String,
+    pub error: String,
+    pub event: Json<ReplicatedEvent>,
+    pub quarantined_at: DateTime<Utc>,
+}
+
+// Quarantine an event; a repeat refusal only updates the error
+pub async fn quarantine_event(pool: &PgPool, peer_id: Uuid, direction: &str, ev: &ReplicatedEvent, error: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO replication_quarantine (peer_id, event_id, direction, error, event) VALUES ($1, $2, $3, $4, $5) \
+         ON CONFLICT (peer_id, event_id, direction) DO UPDATE SET error = EXCLUDED.error, quarantined_at = NOW()")
+        .bind(peer_id)
+        .bind(ev.event.event_id)
+        .bind(direction)
+        .bind(error)
+        .bind(Json(ev))
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// Which of `event_ids` are quarantined for `peer_id` in `direction`
+pub async fn fetch_quarantined_ids(pool: &PgPool, peer_id: Uuid, direction: &str, event_ids: &[Uuid]) -> Result<Vec<Uuid>, sqlx::Error> {
+    sqlx::query_scalar("SELECT event_id FROM replication_quarantine WHERE peer_id = $1 AND direction = $2 AND event_id = ANY($3)")
+        .bind(peer_id)
+        .bind(direction)
+        .bind(event_ids)
+        .fetch_all(pool)
+        .await
+}
+
+// Newest quarantined events first, optionally for one peer
+pub async fn fetch_quarantine(pool: &PgPool, peer_id: Option<Uuid>, limit: i64) -> Result<Vec<QuarantinedEvent>, sqlx::Error> {
+    sqlx::query_as::<_, QuarantinedEvent>(
+        "SELECT peer_id, event_id, direction, error, event, quarantined_at FROM replication_quarantine \
+         WHERE $1::uuid IS NULL OR peer_id = $1 ORDER BY quarantined_at DESC LIMIT $2")
+        .bind(peer_id)
         .bind(limit)
         .fetch_all(pool)
+        .await
+}
+
+// ----- PEER STATUS QUERIES -----
+
+/// Pending events are counted up to this many
+pub const PENDING_COUNT_CAP: i64 = 100_000;
+
+/// How far replication with a peer has got, for operators
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PeerStatus {
+    pub peer_id: Uuid,
+    pub url: String,
+    pub direction: String,
+    pub health: String,
+    pub suspended_at: Option<DateTime<Utc>>,
+    /// Last `repl_seq` pushed to the peer, and the newest one in our log
+    pub push_cursor: i64,
+    pub log_head: i64,
+    /// Events after the push cursor that pass the peer's filter (at most `PENDING_COUNT_CAP`);
+    /// `None` for peers we don't push to
+    pub pending_events: Option<i64>,
+    /// Seconds since the oldest pending event was stored here
+    pub lag_secs: Option<i64>,
+    /// Position in the peer's log we have pulled up to
+    pub pull_cursor: i64,
+    pub last_success_at: Option<DateTime<Utc>>,
+    pub last_error_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub consecutive_failures: i32,
+    pub next_attempt_at: Option<DateTime<Utc>>,
+    pub backing_off: bool,
+    pub sent_last_5m: i64,
+    pub received_last_5m: i64,
+    pub sent_last_hour: i64,
+    pub received_last_hour: i64,
+    /// Events in quarantine for this peer, both directions
+    pub quarantined: i64,
+}
+
+// Count events exchanged with a peer in the current minute, and forget minutes older than a day
+pub async fn record_peer_traffic(pool: &PgPool, peer_id: Uuid, sent: i64, received: i64) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO peer_traffic (peer_id, minute, events_sent, events_received) \
+         VALUES ($1, date_trunc('minute', NOW()), $2, $3) \
+         ON CONFLICT (peer_id, minute) DO UPDATE SET \
+             events_sent = peer_traffic.events_sent + EXCLUDED.events_sent, \
+             events_received = peer_traffic.events_received + EXCLUDED.events_received")
+        .bind(peer_id)
+        .bind(sent)
+        .bind(received)
+        .execute(pool)
         .await?;
+    sqlx::query("DELETE FROM peer_traffic WHERE peer_id = $1 AND minute < NOW() - INTERVAL '1 day'")
+        .bind(peer_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn fetch_peer_status(pool: &PgPool, peer: &Peer) -> Result<PeerStatus, sqlx::Error> {
+    let log_head = fetch_replication_log_head(pool).await?;
+
+    let (pending_events, lag_secs) = if peer.pushes() {
+        // Events not sequenced yet are pending too; they get a position within moments
+        let mut qb = QueryBuilder::<Postgres>::new("SELECT COUNT(*) AS pending, EXTRACT(EPOCH FROM NOW() - MIN(received_at))::bigint AS lag_secs FROM (SELECT received_at FROM events WHERE (repl_seq > ");
+        qb.push_bind(peer.last_cursor_seq);
+        qb.push(" OR repl_seq IS NULL) AND NOT EXISTS (SELECT 1 FROM peer_replication_skips s WHERE s.peer_id = ")
+            .push_bind(peer.peer_id)
+            .push(" AND s.repl_seq = events.repl_seq)");
+        peer.outbound_filter.push_conditions(&mut qb);
+        // In log order, so the capped page still holds the oldest pending event
+        qb.push(" ORDER BY repl_seq ASC NULLS LAST LIMIT ").push_bind(PENDING_COUNT_CAP).push(") pending");
+        let row = qb.build().fetch_one(pool).await?;
+        (Some(row.get::<i64, _>("pending")), row.get::<Option<i64>, _>("lag_secs"))
+    } else {
+        (None, None)
+    };
+
+    let traffic = sqlx::query(
+        "SELECT \
+             COALESCE(SUM(events_sent) FILTER (WHERE minute >= NOW() - INTERVAL '5 minutes'), 0)::bigint AS sent_5m, \
+             COALESCE(SUM(events_received) FILTER (WHERE minute >= NOW() - INTERVAL '5 minutes'), 0)::bigint AS received_5m, \
+             COALESCE(SUM(events_sent), 0)::bigint AS sent_1h, \
+             COALESCE(SUM(events_received), 0)::bigint AS received_1h \
+         FROM peer_traffic WHERE peer_id = $1 AND minute >= NOW() - INTERVAL '1 hour'")
+        .bind(peer.peer_id)
+        .fetch_one(pool)
+        .await?;
+
+    let quarantined: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM replication_quarantine WHERE peer_id = $1")
+        .bind(peer.peer_id)
+        .fetch_one(pool)
+        .await?;
+
+    Ok(PeerStatus {
+        peer_id: peer.peer_id,
+        url: peer.url.clone(),
+        direction: peer.direction.clone(),
+        health: peer.health.clone(),
+        suspended_at: peer.suspended_at,
+        push_cursor: peer.last_cursor_seq,
+        log_head,
+        pending_events,
+        lag_secs,
+        pull_cursor: peer.remote_cursor,
+        last_success_at: peer.last_success_at,
+        last_error_at: peer.last_error_at,
+        last_error: peer.last_error.clone(),
+        consecutive_failures: peer.consecutive_failures,
+        next_attempt_at: peer.next_attempt_at,
+        backing_off: peer.backing_off(Utc::now()),
+        sent_last_5m: traffic.get("sent_5m"),
+        received_last_5m: traffic.get("received_5m"),
+        sent_last_hour: traffic.get("sent_1h"),
+        received_last_hour: traffic.get("received_1h"),
+        quarantined,
+    })
+}
+
+// ----- RECONCILE QUERIES -----
+
+/// Per-event hash summed into range fingerprints: the first 64 bits of md5(event_id)
+const EVENT_ID_HASH: &str = "('x' || substr(md5(event_id::text), 1, 16))::bit(64)::bigint";
+
+/// A half-open range of event IDs, `[lower, upper)`; `upper: None` is unbounded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdRange {
+    pub lower: Uuid,
+    pub upper: Option<Uuid>,
+}
+
+impl IdRange {
+    pub const ALL: IdRange = IdRange { lower: Uuid::nil(), upper: None };
+}
+
+/// Summary of the events in a range. Equal summaries mean (with overwhelming
+/// probability) equal sets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RangeSummary {
+    pub count: i64,
+    /// XOR of the events' ID hashes
+    pub fingerprint: i64,
+}
+
+/// Restricts a query on `events` that already has a WHERE clause to `range`
+fn push_id_range(qb: &mut QueryBuilder<'_, Postgres>, range: IdRange) {
+    qb.push(" AND event_id >= ").push_bind(range.lower);
+    if let Some(upper) = range.upper {
+        qb.push(" AND event_id < ").push_bind(upper);
+    }
+}
+
+/// Summary of the events in `range` that pass `filter`
+pub async fn summarize_range(pool: &PgPool, range: IdRange, filter: &OutboundFilter) -> Result<RangeSummary, sqlx::Error> {
+    let mut qb = QueryBuilder::<Postgres>::new(format!(
+        "SELECT COUNT(*) AS count, COALESCE(BIT_XOR({}), 0) AS fingerprint FROM events WHERE TRUE",
+        EVENT_ID_HASH
+    ));
+    push_id_range(&mut qb, range);
+    filter.push_conditions(&mut qb);
+    let row = qb.build().fetch_one(pool).await?;
+    Ok(RangeSummary { count: row.get("count"), fingerprint: row.get("fingerprint") })
+}
 
-        let mut events = Vec::with_capacity(rows.len());
-        for row in rows.iter() {
-            let event = Event {
-                event_id: row.get::<Uuid, _>("event_id"),
-                server_seq: row.get::<i64, _>("server_seq"),
-                author_pubkey: row.get::<String, _>("author_pubkey"),
-                signature: row.get::<String, _>("signature"),
-                payload_hash: row.get::<String, _>("payload_hash"),
-                device_id: row.get::<Option<String>, _>("device_id"),
-                author_id: row.get::<Option<String>, _>("author_id"),
-                content_id: row.get::<Option<String>, _>("content_id"),
-                event_type: row.get::<Option<String>, _>("event_type"),
-                payload_json: row.get::<Option<serde_json::Value>, _>("payload_json"),
-                occurred_at: row.get::<Option<DateTime<Utc>>, _>("occurred_at"),
-                lamport: row.get::<Option<i64>, _>("lamport"),
+/// Splits `range` into up to `parts` sub-ranges holding about the same number of events
+/// passing `filter`, with their summaries. The sub-ranges cover `range` exactly.
+pub async fn split_range(pool: &PgPool, range: IdRange, parts: i32, filter: &OutboundFilter) -> Result<Vec<(IdRange, RangeSummary)>, sqlx::Error> {
+    let mut qb = QueryBuilder::<Postgres>::new("SELECT MIN(event_id) AS first, COUNT(*) AS count, BIT_XOR(h) AS fingerprint FROM (SELECT event_id, ");
+    qb.push(EVENT_ID_HASH).push(" AS h, ntile(").push_bind(parts).push(") OVER (ORDER BY event_id) AS bucket FROM events WHERE TRUE");
+    push_id_range(&mut qb, range);
+    filter.push_conditions(&mut qb);
+    qb.push(") t GROUP BY bucket ORDER BY first");
+    let rows = qb.build().fetch_all(pool).await?;
+
+    // Each bucket runs from its first ID to the next bucket's; the ends stay those of `range`
+    let firsts: Vec<Uuid> = rows.iter().map(|r| r.get("first")).collect();
+    Ok(rows
+        .iter()
+        .enumerate()
+        .map(|(i, row)| {
+            let sub = IdRange {
+                lower: if i == 0 { range.lower } else { firsts[i] },
+                upper: firsts.get(i + 1).copied().or(range.upper),
             };
-            events.push(event);
-        }
+            (sub, RangeSummary { count: row.get("count"), fingerprint: row.get("fingerprint") })
+        })
+        .collect())
+}
+
+/// IDs of the events in `range` that pass `filter`, in order
+pub async fn fetch_ids_in_range(pool: &PgPool, range: IdRange, filter: &OutboundFilter) -> Result<Vec<Uuid>, sqlx::Error> {
+    let mut qb = QueryBuilder::<Postgres>::new("SELECT event_id FROM events WHERE TRUE");
+    push_id_range(&mut qb, range);
+    filter.push_conditions(&mut qb);
+    qb.push(" ORDER BY event_id");
+    qb.build_query_scalar().fetch_all(pool).await
+}
+
+/// Which of `event_ids` pass `filter`
+pub async fn fetch_ids_passing(pool: &PgPool, filter: &OutboundFilter, event_ids: &[Uuid]) -> Result<Vec<Uuid>, sqlx::Error> {
+    let mut qb = QueryBuilder::<Postgres>::new("SELECT event_id FROM events WHERE event_id = ANY(");
+    qb.push_bind(event_ids.to_vec()).push(")");
+    filter.push_conditions(&mut qb);
+    qb.build_query_scalar().fetch_all(pool).await
+}
+
+/// Events by ID with their provenance, for handing them to another relay
+pub async fn fetch_events_with_provenance(pool: &PgPool, event_ids: &[Uuid]) -> Result<Vec<(Event, Provenance)>, sqlx::Error> {
+    let rows = sqlx::query(&format!(
+        "SELECT {}, origin_relay, hops, relay_path FROM events WHERE event_id = ANY($1) ORDER BY server_seq ASC",
+        EVENT_COLUMNS
+    ))
+    .bind(event_ids)
+    .fetch_all(pool)
+    .await?;
+    Ok(rows.iter().map(|row| (event_from_row(row), provenance_from_row(row))).collect())
+}
+
+// ----- WEBHOOK QUERIES -----
+
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Webhook {
+    pub webhook_id: Uuid,
+    pub url: String,
+    /// 'hmac' (HMAC-SHA256 with `secret`) or 'ed25519' (relay signing key)
+    pub signing: String,
+    pub secret: Option<String>,
+    pub filter: Json<EventFilter>,
+    /// Enqueue cursor over `repl_seq`
+    pub last_seq: i64,
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct WebhookDelivery {
+    pub delivery_id: i64,
+    pub webhook_id: Uuid,
+    pub event_id: Uuid,
+    pub status: String,
+    pub attempts: i32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: Option<String>,
+}
+
+/// Attempts before a webhook delivery is dead-lettered
+pub const MAX_DELIVERY_ATTEMPTS: i32 = 10;
+const BASE_DELIVERY_BACKOFF_SECS: i64 = 10;
+const MAX_DELIVERY_BACKOFF_SECS: i64 = 3600;
+
+/// Wait before retrying a delivery that has failed `attempts` times:
+/// 10s, 20s, 40s, ... capped at an hour
+pub fn delivery_backoff_secs(attempts: i32) -> i64 {
+    let shift = attempts.clamp(1, 20) as u32 - 1;
+    BASE_DELIVERY_BACKOFF_SECS.saturating_mul(1i64 << shift).min(MAX_DELIVERY_BACKOFF_SECS)
+}
+
+// Register a webhook; it receives events sequenced from now on
+pub async fn add_webhook(pool: &PgPool, url: String, signing: String, secret: Option<String>, filter: &EventFilter) -> Result<Uuid, sqlx::Error> {
+    let webhook_id = Uuid::new_v4();
+    sqlx::query("INSERT INTO webhooks (webhook_id, url, signing, secret, filter, last_seq) VALUES ($1, $2, $3, $4, $5, (SELECT COALESCE(MAX(repl_seq), 0) FROM events))")
+        .bind(webhook_id)
+        .bind(url)
+        .bind(signing)
+        .bind(secret)
+        .bind(Json(filter))
+        .execute(pool)
+        .await?;
+    Ok(webhook_id)
+}
+
+pub async fn fetch_all_webhooks(pool: &PgPool) -> Result<Vec<Webhook>, sqlx::Error> {
+    sqlx::query_as::<_, Webhook>("SELECT webhook_id, url, signing, secret, filter, last_seq, active FROM webhooks ORDER BY created_at")
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn fetch_webhook(pool: &PgPool, webhook_id: Uuid) -> Result<Option<Webhook>, sqlx::Error> {
+    sqlx::query_as::<_, Webhook>("SELECT webhook_id, url, signing, secret, filter, last_seq, active FROM webhooks WHERE webhook_id = $1")
+        .bind(webhook_id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn remove_webhook(pool: &PgPool, webhook_id: Uuid) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM webhooks WHERE webhook_id = $1")
+        .bind(webhook_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Queues deliveries for events after the webhook's cursor that match its filter.
+/// The cursor walks the replication log, so only sequenced events are seen and an event
+/// committing late is still picked up. The webhook row is locked so concurrent relay
+/// instances don't enqueue the same range.
+/// Returns how many events were scanned (0 if another instance holds the lock).
+pub async fn enqueue_webhook_deliveries(pool: &PgPool, webhook_id: Uuid, limit: i64) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let row = sqlx::query("SELECT filter, last_seq FROM webhooks WHERE webhook_id = $1 AND active FOR UPDATE SKIP LOCKED")
+        .bind(webhook_id)
+        .fetch_optional(&mut *tx)
+        .await?;
+    let Some(row) = row else {
+        return Ok(0);
+    };
+    let filter = row.get::<Json<EventFilter>, _>("filter").0;
+    let last_seq = row.get::<i64, _>("last_seq");
+
+    // Scan a bounded range and filter in memory, so a rare filter still moves the cursor
+    let rows = sqlx::query(&format!("SELECT {}, repl_seq FROM events WHERE repl_seq > $1 ORDER BY repl_seq ASC LIMIT $2", EVENT_COLUMNS))
+        .bind(last_seq)
+        .bind(limit)
+        .fetch_all(&mut *tx)
+        .await?;
+    let Some(scanned_to) = rows.last().map(|row| row.get::<i64, _>("repl_seq")) else {
+        return Ok(0);
+    };
+    let events: Vec<Event> = rows.iter().map(event_from_row).collect();
+
+    let scanned = events.len();
+    let matched: Vec<Uuid> = events.iter().filter(|e| filter.matches(e)).map(|e| e.event_id).collect();
+    if !matched.is_empty() {
+        sqlx::query("INSERT INTO webhook_deliveries (webhook_id, event_id) SELECT $1, UNNEST($2::uuid[]) ON CONFLICT DO NOTHING")
+            .bind(webhook_id)
+            .bind(&matched)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    sqlx::query("UPDATE webhooks SET last_seq = $1 WHERE webhook_id = $2")
+        .bind(scanned_to)
+        .bind(webhook_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(scanned)
+}
+
+/// Claims due deliveries by pushing their next attempt `lease_secs` into the future,
+/// so a crashed sender's claims become due again instead of being lost.
+pub async fn claim_due_deliveries(pool: &PgPool, limit: i64, lease_secs: i64) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as::<_, WebhookDelivery>(
+        "UPDATE webhook_deliveries SET next_attempt_at = NOW() + make_interval(secs => $2)
+         WHERE delivery_id IN (
+             SELECT delivery_id FROM webhook_deliveries
+             WHERE status = 'pending' AND next_attempt_at <= NOW()
+             ORDER BY next_attempt_at
+             LIMIT $1
+             FOR UPDATE SKIP LOCKED
+         )
+         RETURNING delivery_id, webhook_id, event_id, status, attempts, next_attempt_at, last_error")
+        .bind(limit)
+        .bind(lease_secs as f64)
+        .fetch_all(pool)
+        .await
+}
+
+pub async fn mark_delivery_delivered(pool: &PgPool, delivery_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE webhook_deliveries SET status = 'delivered', attempts = attempts + 1, delivered_at = NOW(), last_error = NULL WHERE delivery_id = $1")
+        .bind(delivery_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Records a failed attempt: schedules a retry after `retry_in_secs`, or dead-letters the
+/// delivery when `dead` is set.
+pub async fn mark_delivery_failed(pool: &PgPool, delivery_id: i64, error: &str, retry_in_secs: i64, dead: bool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE webhook_deliveries SET status = CASE WHEN $4 THEN 'dead' ELSE 'pending' END, attempts = attempts + 1, last_error = $2, next_attempt_at = NOW() + make_interval(secs => $3) WHERE delivery_id = $1")
+        .bind(delivery_id)
+        .bind(error)
+        .bind(retry_in_secs as f64)
+        .bind(dead)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn fetch_dead_deliveries(pool: &PgPool, webhook_id: Option<Uuid>, limit: i64) -> Result<Vec<WebhookDelivery>, sqlx::Error> {
+    sqlx::query_as::<_, WebhookDelivery>(
+        "SELECT delivery_id, webhook_id, event_id, status, attempts, next_attempt_at, last_error FROM webhook_deliveries
+         WHERE status = 'dead' AND ($1::uuid IS NULL OR webhook_id = $1)
+         ORDER BY delivery_id DESC LIMIT $2")
+        .bind(webhook_id)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+}
+
+/// Moves dead deliveries back into the queue with a fresh attempt budget.
+/// Pass a `delivery_id` for one delivery, or a `webhook_id` to requeue all of its dead letters.
+pub async fn redeliver(pool: &PgPool, delivery_id: Option<i64>, webhook_id: Option<Uuid>) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE webhook_deliveries SET status = 'pending', attempts = 0, next_attempt_at = NOW(), last_error = NULL
+         WHERE status = 'dead' AND ($1::bigint IS NULL OR delivery_id = $1) AND ($2::uuid IS NULL OR webhook_id = $2)")
+        .bind(delivery_id)
+        .bind(webhook_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+// ----- CONSUMER QUERIES -----
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Consumer {
+    pub name: String,
+    pub filter: Json<EventFilter>,
+    pub acked_seq: i64,
+    pub delivered_seq: i64,
+    pub ack_timeout_secs: i32,
+}
+
+/// Events leased to one worker of a consumer until `expires_at`
+#[derive(Debug, Serialize)]
+pub struct ConsumerClaim {
+    pub claim_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub events: Vec<Event>,
+}
+
+pub async fn fetch_consumer(pool: &PgPool, name: &str) -> Result<Option<Consumer>, sqlx::Error> {
+    sqlx::query_as::<_, Consumer>("SELECT name, filter, acked_seq, delivered_seq, ack_timeout_secs FROM consumers WHERE name = $1")
+        .bind(name)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Creates a consumer, or updates its filter and ack timeout. `start_seq` (re)positions it;
+/// repositioning drops in-flight claims, whose acks will then be refused.
+pub async fn upsert_consumer(pool: &PgPool, name: &str, filter: &EventFilter, ack_timeout_secs: i32, start_seq: Option<i64>) -> Result<Consumer, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let consumer = sqlx::query_as::<_, Consumer>(
+        "INSERT INTO consumers (name, filter, ack_timeout_secs, acked_seq, delivered_seq)
+         VALUES ($1, $2, $3, COALESCE($4, 0), COALESCE($4, 0))
+         ON CONFLICT (name) DO UPDATE SET
+             filter = EXCLUDED.filter,
+             ack_timeout_secs = EXCLUDED.ack_timeout_secs,
+             acked_seq = COALESCE($4, consumers.acked_seq),
+             delivered_seq = COALESCE($4, consumers.delivered_seq),
+             updated_at = NOW()
+         RETURNING name, filter, acked_seq, delivered_seq, ack_timeout_secs")
+        .bind(name)
+        .bind(Json(filter))
+        .bind(ack_timeout_secs)
+        .bind(start_seq)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    if start_seq.is_some() {
+        sqlx::query("DELETE FROM consumer_claims WHERE consumer = $1")
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(consumer)
+}
+
+pub async fn remove_consumer(pool: &PgPool, name: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM consumers WHERE name = $1")
+        .bind(name)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Hands out the next batch of a consumer to one worker.
+///
+/// Positions are replication log positions (`repl_seq`), so events are handed out in
+/// commit order and one committing late is never passed over.
+/// An expired claim is re-issued first (under a new `claim_id`, so the previous holder's
+/// ack is refused); otherwise events after `delivered_seq` are claimed. The consumer row
+/// lock serializes concurrent workers, so no range is ever leased twice at the same time.
+/// Returns `None` when there is nothing to process (or the consumer doesn't exist).
+pub async fn claim_consumer_batch(pool: &PgPool, name: &str, limit: i64) -> Result<Option<ConsumerClaim>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let Some(consumer) = sqlx::query_as::<_, Consumer>("SELECT name, filter, acked_seq, delivered_seq, ack_timeout_secs FROM consumers WHERE name = $1 FOR UPDATE")
+        .bind(name)
+        .fetch_optional(&mut *tx)
+        .await?
+    else {
+        return Ok(None);
+    };
+    let claim_id = Uuid::new_v4();
+
+    let expired = sqlx::query("SELECT claim_id, from_seq, to_seq FROM consumer_claims WHERE consumer = $1 AND expires_at < NOW() ORDER BY from_seq LIMIT 1")
+        .bind(name)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+    let (from_seq, to_seq, events) = if let Some(row) = expired {
+        let from_seq = row.get::<i64, _>("from_seq");
+        let to_seq = row.get::<i64, _>("to_seq");
+        sqlx::query("DELETE FROM consumer_claims WHERE claim_id = $1")
+            .bind(row.get::<Uuid, _>("claim_id"))
+            .execute(&mut *tx)
+            .await?;
+
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(format!("SELECT {} FROM events WHERE repl_seq > ", EVENT_COLUMNS));
+        qb.push_bind(from_seq).push(" AND repl_seq <= ").push_bind(to_seq);
+        consumer.filter.0.push_conditions(&mut qb);
+        qb.push(" ORDER BY repl_seq ASC");
+        let rows = qb.build().fetch_all(&mut *tx).await?;
+        (from_seq, to_seq, rows.iter().map(event_from_row).collect::<Vec<_>>())
+    } else {
+        let mut qb: QueryBuilder<Postgres> = QueryBuilder::new(format!("SELECT {}, repl_seq FROM events WHERE repl_seq > ", EVENT_COLUMNS));
+        qb.push_bind(consumer.delivered_seq);
+        consumer.filter.0.push_conditions(&mut qb);
+        qb.push(" ORDER BY repl_seq ASC LIMIT ").push_bind(limit);
+        let rows = qb.build().fetch_all(&mut *tx).await?;
+        let Some(last) = rows.last().map(|row| row.get::<i64, _>("repl_seq")) else {
+            return Ok(None);
+        };
+        let events: Vec<Event> = rows.iter().map(event_from_row).collect();
+
+        sqlx::query("UPDATE consumers SET delivered_seq = $1, updated_at = NOW() WHERE name = $2")
+            .bind(last)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        (consumer.delivered_seq, last, events)
+    };
+
+    let expires_at: DateTime<Utc> = sqlx::query("INSERT INTO consumer_claims (claim_id, consumer, from_seq, to_seq, expires_at) VALUES ($1, $2, $3, $4, NOW() + make_interval(secs => $5)) RETURNING expires_at")
+        .bind(claim_id)
+        .bind(name)
+        .bind(from_seq)
+        .bind(to_seq)
+        .bind(consumer.ack_timeout_secs as f64)
+        .fetch_one(&mut *tx)
+        .await?
+        .get("expires_at");
+
+    tx.commit().await?;
+    Ok(Some(ConsumerClaim { claim_id, expires_at, events }))
+}
+
+/// Completes a claim and moves `acked_seq` up to just before the oldest claim still in flight.
+/// Returns the new `acked_seq`, or `None` if the claim is unknown or was re-issued after expiring.
+pub async fn ack_consumer_claim(pool: &PgPool, name: &str, claim_id: Uuid) -> Result<Option<i64>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("SELECT 1 FROM consumers WHERE name = $1 FOR UPDATE")
+        .bind(name)
+        .execute(&mut *tx)
+        .await?;
+
+    let deleted = sqlx::query("DELETE FROM consumer_claims WHERE claim_id = $1 AND consumer = $2")
+        .bind(claim_id)
+        .bind(name)
+        .execute(&mut *tx)
+        .await?;
+    if deleted.rows_affected() == 0 {
+        return Ok(None);
+    }
+
+    let acked: i64 = sqlx::query(
+        "UPDATE consumers SET
+             acked_seq = COALESCE((SELECT MIN(from_seq) FROM consumer_claims WHERE consumer = $1), delivered_seq),
+             updated_at = NOW()
+         WHERE name = $1 RETURNING acked_seq")
+        .bind(name)
+        .fetch_one(&mut *tx)
+        .await?
+        .get("acked_seq");
+
+    tx.commit().await?;
+    Ok(Some(acked))
+}
+
+pub struct ConsumerLag {
+    /// Matching events after `acked_seq`, including committed ones not sequenced yet
+    pub events: i64,
+    pub in_flight_claims: i64,
+    /// Replication log head
+    pub head_seq: i64,
+}
+
+pub async fn fetch_consumer_lag(pool: &PgPool, consumer: &Consumer) -> Result<ConsumerLag, sqlx::Error> {
+    let mut qb: QueryBuilder<Postgres> = QueryBuilder::new("SELECT COUNT(*) AS lag FROM events WHERE (repl_seq > ");
+    qb.push_bind(consumer.acked_seq);
+    qb.push(" OR repl_seq IS NULL)");
+    consumer.filter.0.push_conditions(&mut qb);
+    let events: i64 = qb.build().fetch_one(pool).await?.get("lag");
+
+    let in_flight: i64 = sqlx::query("SELECT COUNT(*) AS n FROM consumer_claims WHERE consumer = $1")
+        .bind(&consumer.name)
+        .fetch_one(pool)
+        .await?
+        .get("n");
 
-    Ok(events)
+    Ok(ConsumerLag { events, in_flight_claims: in_flight, head_seq: fetch_replication_log_head(pool).await? })
 }
@@ -0,0 +1,165 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+use axum::http::StatusCode;
+use sqlx::PgPool;
+
+use crate::db::{Event, EventInput};
+use crate::ingest::Rejection;
+use crate::nostr::EVENT_TYPE_PREFIX;
+
+/// NOTIFY channel carrying ephemeral events (as JSON) between relay instances
+pub const EPHEMERAL_CHANNEL: &str = "tisane_ephemeral";
+/// Postgres caps NOTIFY payloads at 8000 bytes
+pub const MAX_EPHEMERAL_BYTES: usize = 7500;
+/// Ephemeral events have no position in storage
+pub const EPHEMERAL_SEQ: i64 = 0;
+/// NIP-01 ephemeral kind range
+const NOSTR_EPHEMERAL_KINDS: std::ops::Range<u32> = 20000..30000;
+/// Idle buckets are dropped once this many authors are tracked
+const MAX_TRACKED_AUTHORS: usize = 10_000;
+
+/// Per-key token bucket, refilled continuously
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<String, (f64, Instant)>>,
+}
+
+impl RateLimiter {
+    pub fn per_minute(limit: u32) -> Self {
+        RateLimiter {
+            capacity: limit as f64,
+            refill_per_sec: limit as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes `n` tokens from `key`'s bucket; `false` (and nothing taken) if there aren't enough.
+    pub fn check_n(&self, key: &str, n: u32) -> bool {
+        self.take(key, n, false) == n
+    }
+
+    /// Takes as many of `n` tokens as `key`'s bucket holds; returns how many it took.
+    pub fn take_up_to(&self, key: &str, n: u32) -> u32 {
+        self.take(key, n, true)
+    }
+
+    /// Takes `n` tokens from each key's bucket, or nothing at all if any of them is short.
+    pub fn check_all(&self, wants: &HashMap<&str, u32>) -> bool {
+        let now = Instant::now();
+        let mut buckets = self.lock(now);
+        if !wants.iter().all(|(key, n)| *self.refill(&mut buckets, key, now) >= *n as f64) {
+            return false;
+        }
+        for (key, n) in wants {
+            *self.refill(&mut buckets, key, now) -= *n as f64;
+        }
+        true
+    }
+
+    fn take(&self, key: &str, n: u32, partial: bool) -> u32 {
+        let now = Instant::now();
+        let mut buckets = self.lock(now);
+        let tokens = self.refill(&mut buckets, key, now);
+        let taken = if *tokens >= n as f64 {
+            n
+        } else if partial {
+            *tokens as u32
+        } else {
+            0
+        };
+        *tokens -= taken as f64;
+        taken
+    }
+
+    fn lock(&self, now: Instant) -> MutexGuard<'_, HashMap<String, (f64, Instant)>> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() > MAX_TRACKED_AUTHORS {
+            let (capacity, rate) = (self.capacity, self.refill_per_sec);
+            buckets.retain(|_, (tokens, at)| *tokens + now.duration_since(*at).as_secs_f64() * rate < capacity);
+        }
+        buckets
+    }
+
+    /// `key`'s tokens, topped up for the time since they were last looked at
+    fn refill<'a>(&self, buckets: &'a mut HashMap<String, (f64, Instant)>, key: &str, now: Instant) -> &'a mut f64 {
+        let (tokens, at) = buckets.entry(key.to_string()).or_insert((self.capacity, now));
+        *tokens = (*tokens + now.duration_since(*at).as_secs_f64() * self.refill_per_sec).min(self.capacity);
+        *at = now;
+        tokens
+    }
+
+    pub fn check(&self, key: &str) -> bool {
+        self.check_n(key, 1)
+    }
+}
+
+/// Event types that are fanned out to live subscribers and never stored or replicated
+pub struct Ephemeral {
+    event_types: HashSet<String>,
+    limiter: RateLimiter,
+}
+
+impl Ephemeral {
+    pub fn new(event_types: impl IntoIterator<Item = String>, per_author_per_minute: u32) -> Self {
+        Ephemeral {
+            event_types: event_types
+                .into_iter()
+                .map(|t| t.trim().to_string())
+                .filter(|t| !t.is_empty())
+                .collect(),
+            limiter: RateLimiter::per_minute(per_author_per_minute),
+        }
+    }
+
+    /// Configured types, plus Nostr kinds 20000-29999
+    pub fn is_ephemeral(&self, ev: &EventInput) -> bool {
+        let Some(event_type) = ev.event_type.as_deref() else { return false };
+        self.event_types.contains(event_type)
+            || event_type
+                .strip_prefix(EVENT_TYPE_PREFIX)
+                .and_then(|kind| kind.parse::<u32>().ok())
+                .is_some_and(|kind| NOSTR_EPHEMERAL_KINDS.contains(&kind))
+    }
+
+    /// Size and rate checks for an already validated ephemeral event. Counts against the
+    /// author's budget, which is separate from any limit on stored events.
+    pub fn admit(&self, ev: &EventInput) -> Result<(), Rejection> {
+        self.admit_all([ev])
+    }
+
+    /// `admit` for a batch that is accepted or refused as a whole: the budget is only
+    /// charged if every event passes.
+    pub fn admit_all<'a>(&self, events: impl IntoIterator<Item = &'a EventInput>) -> Result<(), Rejection> {
+        let mut wants: HashMap<&str, u32> = HashMap::new();
+        for ev in events {
+            let size = serde_json::to_vec(&ev.to_event(EPHEMERAL_SEQ)).map(|v| v.len()).unwrap_or(usize::MAX);
+            if size > MAX_EPHEMERAL_BYTES {
+                return Err((StatusCode::PAYLOAD_TOO_LARGE, format!("ephemeral events are limited to {} bytes", MAX_EPHEMERAL_BYTES)));
+            }
+            *wants.entry(ev.author_pubkey.as_str()).or_default() += 1;
+        }
+        if !self.limiter.check_all(&wants) {
+            return Err((StatusCode::TOO_MANY_REQUESTS, "ephemeral rate limit exceeded".to_string()));
+        }
+        Ok(())
+    }
+}
+
+/// Hands an ephemeral event to every relay instance's hub (including this one) via NOTIFY.
+pub async fn publish(pool: &PgPool, ev: &EventInput) -> Result<(), sqlx::Error> {
+    let payload = serde_json::to_string(&ev.to_event(EPHEMERAL_SEQ)).map_err(|e| sqlx::Error::Protocol(e.to_string()))?;
+    sqlx::query("SELECT pg_notify($1, $2)")
+        .bind(EPHEMERAL_CHANNEL)
+        .bind(payload)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Parses a NOTIFY payload from `publish`.
+pub fn decode(payload: &str) -> Option<Event> {
+    serde_json::from_str(payload).ok()
+}
@@ -0,0 +1,206 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{Postgres, QueryBuilder};
+use uuid::Uuid;
+
+use crate::db::{Event, EventInput};
+
+/// Event filter shared by pull queries, cursors and live subscriptions.
+/// Empty lists mean "no restriction" on that column.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EventFilter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub event_types: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub content_ids: Vec<String>,
+    /// Tag key -> accepted values. Values of one key are OR-ed, distinct keys are AND-ed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub event_ids: Vec<Uuid>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sig_schemes: Vec<String>,
+    /// Inclusive bounds on `occurred_at`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+}
+
+const TAG_PARAM_PREFIX: &str = "tag.";
+
+/// Splits a comma-separated query value (e.g. `event_type=a,b`) into a list.
+pub fn split_list(value: Option<&str>) -> Vec<String> {
+    value
+        .map(|v| {
+            v.split(',')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+impl EventFilter {
+    pub fn from_query(author: Option<&str>, event_type: Option<&str>, content_id: Option<&str>) -> Self {
+        EventFilter {
+            authors: split_list(author),
+            event_types: split_list(event_type),
+            content_ids: split_list(content_id),
+            ..Default::default()
+        }
+    }
+
+    /// Adds `tag.<key>=<v1>,<v2>` query parameters to the filter.
+    pub fn with_tag_params(mut self, params: &[(String, String)]) -> Self {
+        for (name, value) in params {
+            if let Some(key) = name.strip_prefix(TAG_PARAM_PREFIX) {
+                if key.is_empty() {
+                    continue;
+                }
+                let values = split_list(Some(value));
+                if !values.is_empty() {
+                    self.tags.entry(key.to_string()).or_default().extend(values);
+                }
+            }
+        }
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.authors.is_empty()
+            && self.event_types.is_empty()
+            && self.content_ids.is_empty()
+            && self.tags.is_empty()
+            && self.event_ids.is_empty()
+            && self.sig_schemes.is_empty()
+            && self.since.is_none()
+            && self.until.is_none()
+    }
+
+    /// In-memory equivalent of `push_conditions`, for events delivered live.
+    pub fn matches(&self, ev: &Event) -> bool {
+        fn allowed(list: &[String], value: Option<&str>) -> bool {
+            list.is_empty() || value.is_some_and(|v| list.iter().any(|x| x == v))
+        }
+
+        allowed(&self.authors, Some(&ev.author_pubkey))
+            && allowed(&self.event_types, ev.event_type.as_deref())
+            && allowed(&self.content_ids, ev.content_id.as_deref())
+            && self.tags.iter().all(|(key, values)| {
+                ev.tags.iter().any(|t| t.len() >= 2 && &t[0] == key && values.contains(&t[1]))
+            })
+            && (self.event_ids.is_empty() || self.event_ids.contains(&ev.event_id))
+            && allowed(&self.sig_schemes, Some(&ev.sig_scheme))
+            && self.since.is_none_or(|t| ev.occurred_at.is_some_and(|at| at >= t))
+            && self.until.is_none_or(|t| ev.occurred_at.is_some_and(|at| at <= t))
+    }
+
+    /// Appends the filter as `AND ...` conditions to a query that already has a WHERE clause.
+    pub fn push_conditions(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        if !self.authors.is_empty() {
+            qb.push(" AND author_pubkey = ANY(").push_bind(self.authors.clone()).push(")");
+        }
+        if !self.event_types.is_empty() {
+            qb.push(" AND event_type = ANY(").push_bind(self.event_types.clone()).push(")");
+        }
+        if !self.content_ids.is_empty() {
+            qb.push(" AND content_id = ANY(").push_bind(self.content_ids.clone()).push(")");
+        }
+        for (key, values) in &self.tags {
+            qb.push(" AND EXISTS (SELECT 1 FROM event_tags t WHERE t.event_id = events.event_id AND t.key = ")
+                .push_bind(key.clone())
+                .push(" AND t.value = ANY(")
+                .push_bind(values.clone())
+                .push("))");
+        }
+        if !self.event_ids.is_empty() {
+            qb.push(" AND event_id = ANY(").push_bind(self.event_ids.clone()).push(")");
+        }
+        if !self.sig_schemes.is_empty() {
+            qb.push(" AND sig_scheme = ANY(").push_bind(self.sig_schemes.clone()).push(")");
+        }
+        if let Some(since) = self.since {
+            qb.push(" AND occurred_at >= ").push_bind(since);
+        }
+        if let Some(until) = self.until {
+            qb.push(" AND occurred_at <= ").push_bind(until);
+        }
+    }
+}
+
+/// What a peer is sent: an event filter plus restrictions only replication needs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboundFilter {
+    /// Allowed event types, authors (allow list) and tags
+    #[serde(flatten)]
+    pub filter: EventFilter,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_authors: Vec<String>,
+    /// Events that occurred longer ago than this when sent are withheld
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_secs: Option<i64>,
+}
+
+impl OutboundFilter {
+    pub fn is_empty(&self) -> bool {
+        self.filter.is_empty() && self.deny_authors.is_empty() && self.max_age_secs.is_none()
+    }
+
+    /// In-memory equivalent of `push_conditions`, with `now` standing in for `NOW()`.
+    pub fn matches(&self, ev: &Event, now: DateTime<Utc>) -> bool {
+        self.filter.matches(ev)
+            && !self.deny_authors.contains(&ev.author_pubkey)
+            && self.max_age_secs.is_none_or(|secs| {
+                ev.occurred_at.is_some_and(|at| at >= now - chrono::Duration::seconds(secs))
+            })
+    }
+
+    /// Appends the filter as `AND ...` conditions to a query that already has a WHERE clause.
+    pub fn push_conditions(&self, qb: &mut QueryBuilder<'_, Postgres>) {
+        self.filter.push_conditions(qb);
+        if !self.deny_authors.is_empty() {
+            qb.push(" AND author_pubkey <> ALL(").push_bind(self.deny_authors.clone()).push(")");
+        }
+        if let Some(secs) = self.max_age_secs {
+            qb.push(" AND occurred_at >= NOW() - make_interval(secs => ")
+                .push_bind(secs as f64)
+                .push(")");
+        }
+    }
+}
+
+/// What we accept from a peer. Empty lists mean "no restriction".
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InboundPolicy {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub event_types: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    /// Events per replicate request
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_events: Option<u32>,
+    /// Events per minute over all replicate requests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_events_per_min: Option<u32>,
+    /// The peer may pull from us, but nothing is accepted from it
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub read_only: bool,
+}
+
+impl InboundPolicy {
+    pub fn is_empty(&self) -> bool {
+        *self == InboundPolicy::default()
+    }
+
+    /// Whether the event's type and author are allowed
+    pub fn admits(&self, ev: &EventInput) -> bool {
+        (self.event_types.is_empty() || ev.event_type.as_ref().is_some_and(|t| self.event_types.contains(t)))
+            && (self.authors.is_empty() || self.authors.contains(&ev.author_pubkey))
+    }
+}
@@ -0,0 +1,152 @@
+// ----- RANGE FINGERPRINTS -----
+//
+// Both sides of the range-fingerprint comparison behind POST /relay/reconcile. Each side
+// only fingerprints what its outbound filter lets through to the other, so events a
+// filter keeps back never count as a difference.
+
+use std::collections::HashSet;
+use std::future::Future;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::{self, IdRange, RangeSummary};
+use crate::filter::OutboundFilter;
+
+/// A peer holding at most this many events in a differing range lists their IDs
+const ID_LIST_MAX: i64 = 128;
+/// Sub-ranges per split
+const SPLIT_PARTS: i32 = 16;
+pub const MAX_RANGES_PER_REQUEST: usize = 256;
+/// Splitting by 16 reaches single events in any realistic table well within this
+const MAX_ROUNDS: usize = 12;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RangeFingerprint {
+    pub range: IdRange,
+    pub summary: RangeSummary,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReconcileRequest {
+    pub ranges: Vec<RangeFingerprint>,
+}
+
+/// The responder's answer for one requested range
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "lowercase")]
+pub enum RangeReply {
+    Match,
+    /// Every event ID the responder holds in the range
+    Ids { ids: Vec<Uuid> },
+    /// The range cut into sub-ranges, with the responder's summaries
+    Split { ranges: Vec<RangeFingerprint> },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReconcileResponse {
+    /// One reply per requested range, in order
+    pub ranges: Vec<RangeReply>,
+}
+
+/// Outcome of comparing our events with a peer's
+#[derive(Debug, Default)]
+pub struct Divergence {
+    /// Events the peer has and we don't
+    pub missing_local: Vec<Uuid>,
+    /// Events we have and the peer doesn't
+    pub missing_remote: Vec<Uuid>,
+    pub rounds: usize,
+    /// Ranges still differing when `MAX_ROUNDS` ran out
+    pub unresolved: usize,
+}
+
+impl Divergence {
+    pub fn is_empty(&self) -> bool {
+        self.missing_local.is_empty() && self.missing_remote.is_empty() && self.unresolved == 0
+    }
+}
+
+/// Answers a peer's request, comparing with our events that pass `filter` (what we send
+/// that peer).
+pub async fn answer(pool: &PgPool, filter: &OutboundFilter, req: ReconcileRequest) -> Result<ReconcileResponse, sqlx::Error> {
+    let mut replies = Vec::with_capacity(req.ranges.len());
+    for theirs in req.ranges {
+        let ours = db::summarize_range(pool, theirs.range, filter).await?;
+        let reply = if ours == theirs.summary {
+            RangeReply::Match
+        } else if ours.count <= ID_LIST_MAX {
+            RangeReply::Ids { ids: db::fetch_ids_in_range(pool, theirs.range, filter).await? }
+        } else {
+            let parts = db::split_range(pool, theirs.range, SPLIT_PARTS, filter).await?;
+            RangeReply::Split { ranges: parts.into_iter().map(|(range, summary)| RangeFingerprint { range, summary }).collect() }
+        };
+        replies.push(reply);
+    }
+    Ok(ReconcileResponse { ranges: replies })
+}
+
+/// Compares our events passing `filter` (what we send the peer) with what the peer sends
+/// us, without changing either side. `ask` carries one request to the peer's `answer`.
+pub async fn compare<F, Fut>(pool: &PgPool, filter: &OutboundFilter, mut ask: F) -> Result<Divergence, String>
+where
+    F: FnMut(ReconcileRequest) -> Fut,
+    Fut: Future<Output = Result<ReconcileResponse, String>>,
+{
+    let mut divergence = Divergence::default();
+    let whole = db::summarize_range(pool, IdRange::ALL, filter).await.map_err(|e| e.to_string())?;
+    let mut pending = vec![RangeFingerprint { range: IdRange::ALL, summary: whole }];
+
+    while !pending.is_empty() && divergence.rounds < MAX_ROUNDS {
+        divergence.rounds += 1;
+        let mut next = Vec::new();
+
+        for chunk in pending.chunks(MAX_RANGES_PER_REQUEST) {
+            let resp = ask(ReconcileRequest { ranges: chunk.to_vec() }).await?;
+            if resp.ranges.len() != chunk.len() {
+                return Err("reconcile response does not match the request".to_string());
+            }
+
+            for (ours, reply) in chunk.iter().zip(resp.ranges) {
+                match reply {
+                    RangeReply::Match => {},
+                    RangeReply::Ids { ids } => {
+                        let local = db::fetch_ids_in_range(pool, ours.range, filter).await.map_err(|e| e.to_string())?;
+                        let theirs: HashSet<Uuid> = ids.iter().copied().collect();
+                        let mine: HashSet<Uuid> = local.iter().copied().collect();
+                        divergence.missing_local.extend(ids.into_iter().filter(|id| !mine.contains(id)));
+                        divergence.missing_remote.extend(local.into_iter().filter(|id| !theirs.contains(id)));
+                    },
+                    RangeReply::Split { ranges } => {
+                        for theirs in ranges {
+                            let mine = db::summarize_range(pool, theirs.range, filter).await.map_err(|e| e.to_string())?;
+                            if mine == theirs.summary {
+                                continue;
+                            }
+                            if theirs.summary.count == 0 {
+                                let local = db::fetch_ids_in_range(pool, theirs.range, filter).await.map_err(|e| e.to_string())?;
+                                divergence.missing_remote.extend(local);
+                            } else {
+                                next.push(RangeFingerprint { range: theirs.range, summary: mine });
+                            }
+                        }
+                    },
+                }
+            }
+        }
+        pending = next;
+    }
+
+    divergence.unresolved = pending.len();
+    // The peer's filter for us can let through events we hold but don't send it
+    if !divergence.missing_local.is_empty() {
+        let held: HashSet<Uuid> = db::fetch_ids_passing(pool, &OutboundFilter::default(), &divergence.missing_local)
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .collect();
+        divergence.missing_local.retain(|id| !held.contains(id));
+    }
+    Ok(divergence)
+}
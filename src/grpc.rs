@@ -0,0 +1,135 @@
+// ----- GRPC API -----
+//
+// tonic service from proto/relay.proto, served on --grpc-port next to the HTTP API.
+// Messages are converted by `proto`; pushes go through ingest::ingest_each like WebSocket
+// pushes.
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use futures::{stream, Stream};
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+use tracing::error;
+
+use tisane_relay::cursor::Order;
+use tisane_relay::db;
+use tisane_relay::hub::{self, Delivery, FollowError};
+use tisane_relay::ingest;
+use tisane_relay::proto::pb::relay_server::{Relay, RelayServer};
+use tisane_relay::proto::{event_to_pb, filter_from_pb, input_from_pb, outcome_to_pb, parse_uuid, pb};
+
+use crate::{AppState, MAX_PULL_LIMIT};
+
+const SUBSCRIBE_BUFFER: usize = 256;
+
+pub async fn serve(state: AppState, addr: SocketAddr) {
+    let result = tonic::transport::Server::builder()
+        .add_service(RelayServer::new(RelayService { state }))
+        .serve(addr)
+        .await;
+    if let Err(e) = result {
+        error!("gRPC server error: {}", e);
+    }
+}
+
+struct RelayService {
+    state: AppState,
+}
+
+fn internal(e: sqlx::Error) -> Status {
+    error!("gRPC storage error: {}", e);
+    Status::internal(e.to_string())
+}
+
+type SubscribeItem = Result<pb::SubscribeResponse, Status>;
+
+#[tonic::async_trait]
+impl Relay for RelayService {
+    async fn push(&self, request: Request<pb::PushRequest>) -> Result<Response<pb::PushResponse>, Status> {
+        let events = request
+            .into_inner()
+            .events
+            .into_iter()
+            .map(input_from_pb)
+            .collect::<Result<Vec<_>, Status>>()?;
+        let outcomes = ingest::ingest_each(&self.state.pool, &self.state.ephemeral, events).await.map_err(internal)?;
+        Ok(Response::new(pb::PushResponse { results: outcomes.into_iter().map(outcome_to_pb).collect() }))
+    }
+
+    async fn pull(&self, request: Request<pb::PullRequest>) -> Result<Response<pb::PullResponse>, Status> {
+        let req = request.into_inner();
+        let limit = if req.limit <= 0 { 100 } else { req.limit.min(MAX_PULL_LIMIT) };
+
+        let (order, backward, after, filter) = match req.cursor.as_deref() {
+            Some(token) => {
+                let c = self.state.cursors.decode(token).map_err(|e| Status::invalid_argument(e.to_string()))?;
+                (c.order, c.backward, Some(c.seq), c.filter)
+            },
+            None => (
+                if req.descending { Order::Desc } else { Order::Asc },
+                false,
+                req.since,
+                filter_from_pb(req.filter),
+            ),
+        };
+
+        let page = db::fetch_events_page(&self.state.pool, &filter, order, after, backward, limit, true)
+            .await
+            .map_err(internal)?;
+        let (next_cursor, prev_cursor) = self.state.cursors.page_cursors(order, backward, after, &filter, &page);
+        Ok(Response::new(pb::PullResponse {
+            events: page.events.iter().map(event_to_pb).collect(),
+            next_cursor,
+            prev_cursor,
+            has_more: page.has_more,
+        }))
+    }
+
+    async fn get(&self, request: Request<pb::GetRequest>) -> Result<Response<pb::Event>, Status> {
+        let event_id = parse_uuid(&request.into_inner().event_id, "event_id")?;
+        match db::fetch_event(&self.state.pool, event_id).await.map_err(internal)? {
+            Some(ev) => Ok(Response::new(event_to_pb(&ev))),
+            None => Err(Status::not_found("event not found")),
+        }
+    }
+
+    type SubscribeStream = Pin<Box<dyn Stream<Item = SubscribeItem> + Send + 'static>>;
+
+    async fn subscribe(&self, request: Request<pb::SubscribeRequest>) -> Result<Response<Self::SubscribeStream>, Status> {
+        let req = request.into_inner();
+        let (filter, after) = match req.cursor.as_deref() {
+            Some(token) => match self.state.cursors.decode(token) {
+                Ok(c) if c.order == Order::Asc && !c.backward => (c.filter, Some(c.seq)),
+                Ok(_) => return Err(Status::invalid_argument("subscriptions need an ascending forward cursor")),
+                Err(e) => return Err(Status::invalid_argument(e.to_string())),
+            },
+            // Without a start position only live events are sent
+            None => (filter_from_pb(req.filter), req.since),
+        };
+
+        let (tx, rx) = mpsc::channel::<SubscribeItem>(SUBSCRIBE_BUFFER);
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            let result = hub::follow(&state.pool, &state.hub, &filter, after, &tx, |delivery| {
+                let item = match delivery {
+                    Delivery::Event(ev) | Delivery::Ephemeral(ev) => pb::subscribe_response::Item::Event(event_to_pb(&ev)),
+                    Delivery::EndOfStored => pb::subscribe_response::Item::EndOfStored(pb::EndOfStored {}),
+                };
+                Ok(pb::SubscribeResponse { item: Some(item) })
+            })
+            .await;
+
+            let status = match result {
+                Ok(()) => Status::unavailable("relay shutting down"),
+                Err(FollowError::Closed) => return,
+                Err(FollowError::SlowConsumer) => Status::resource_exhausted("subscriber too slow"),
+                Err(e) => Status::internal(e.to_string()),
+            };
+            let _ = tx.send(Err(status)).await;
+        });
+
+        let stream = stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
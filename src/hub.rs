@@ -0,0 +1,351 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sqlx::postgres::PgListener;
+use sqlx::PgPool;
+use tokio::sync::{broadcast, mpsc, watch};
+use tracing::{error, info, warn};
+
+use crate::cursor::Order;
+use crate::db::{self, Event};
+use crate::ephemeral::{self, EPHEMERAL_CHANNEL};
+use crate::filter::{EventFilter, OutboundFilter};
+
+const HUB_CAPACITY: usize = 1024;
+const BACKFILL_BATCH: i64 = 500;
+/// How many delivered `server_seq`s a follower remembers to drop duplicates
+/// when an event shows up both in the backfill and on the hub.
+const SEEN_WINDOW: usize = 4096;
+
+/// NOTIFY channel written by the `events_notify_insert` trigger
+pub const EVENTS_CHANNEL: &str = "tisane_events";
+/// Safety net for missed notifications: catch up from storage at least this often
+const CATCH_UP_INTERVAL_SECS: u64 = 30;
+const LISTENER_RETRY_SECS: u64 = 5;
+
+/// A stored event as published by the hub, with its replication log position
+#[derive(Debug, Clone)]
+pub struct Published {
+    pub repl_seq: i64,
+    pub event: Arc<Event>,
+}
+
+/// In-process fan-out of newly stored events to live subscribers.
+/// Fed by `run_listener`, so it sees inserts from every relay instance sharing the database.
+/// Events are published in replication log order, which is commit order.
+#[derive(Clone)]
+pub struct EventHub {
+    tx: broadcast::Sender<Published>,
+    head: watch::Sender<i64>,
+    /// Ephemeral events, which have no `server_seq` and can't be caught up on
+    ephemeral: broadcast::Sender<Arc<Event>>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventHub {
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(HUB_CAPACITY);
+        let (head, _) = watch::channel(0);
+        let (ephemeral, _) = broadcast::channel(HUB_CAPACITY);
+        EventHub { tx, head, ephemeral }
+    }
+
+    pub fn publish(&self, repl_seq: i64, event: Event) {
+        // No receivers is fine: nobody is listening right now
+        let _ = self.tx.send(Published { repl_seq, event: Arc::new(event) });
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Published> {
+        self.tx.subscribe()
+    }
+
+    pub fn publish_ephemeral(&self, event: Event) {
+        let _ = self.ephemeral.send(Arc::new(event));
+    }
+
+    pub fn subscribe_ephemeral(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.ephemeral.subscribe()
+    }
+
+    /// Replication log position published so far; changes whenever new events are stored.
+    pub fn watch_head(&self) -> watch::Receiver<i64> {
+        self.head.subscribe()
+    }
+}
+
+/// Listens for insert notifications and publishes the new events to the hub.
+///
+/// Notifications are only wakeups: new events are sequenced into the replication log and
+/// read back after the last published position, so dropped notifications, reconnects and
+/// transactions committing out of `server_seq` order lose nothing.
+/// Ephemeral events travel inside their notification and are published as they come.
+pub async fn run_listener(pool: PgPool, hub: EventHub) {
+    let mut published = match db::fetch_replication_log_head(&pool).await {
+        Ok(seq) => seq,
+        Err(e) => {
+            error!("hub: failed to read head: {}", e);
+            0
+        }
+    };
+    hub.head.send_replace(published);
+
+    loop {
+        let mut listener = match PgListener::connect_with(&pool).await {
+            Ok(l) => l,
+            Err(e) => {
+                warn!("hub: listener connect failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(LISTENER_RETRY_SECS)).await;
+                continue;
+            }
+        };
+        if let Err(e) = listener.listen_all([EVENTS_CHANNEL, EPHEMERAL_CHANNEL]).await {
+            warn!("hub: LISTEN failed: {}", e);
+            tokio::time::sleep(Duration::from_secs(LISTENER_RETRY_SECS)).await;
+            continue;
+        }
+        info!("hub: listening on {} and {}", EVENTS_CHANNEL, EPHEMERAL_CHANNEL);
+
+        'listen: loop {
+            // Covers anything inserted before LISTEN took effect, too
+            catch_up(&pool, &hub, &mut published).await;
+
+            loop {
+                match tokio::time::timeout(Duration::from_secs(CATCH_UP_INTERVAL_SECS), listener.try_recv()).await {
+                    Ok(Ok(Some(n))) if n.channel() == EPHEMERAL_CHANNEL => {
+                        match ephemeral::decode(n.payload()) {
+                            Some(ev) => hub.publish_ephemeral(ev),
+                            None => warn!("hub: undecodable ephemeral event"),
+                        }
+                    },
+                    // An insert, a periodic tick, or a dropped connection that sqlx re-establishes
+                    Ok(Ok(_)) | Err(_) => break,
+                    Ok(Err(e)) => {
+                        warn!("hub: listener error: {}", e);
+                        break 'listen;
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn catch_up(pool: &PgPool, hub: &EventHub, published: &mut i64) {
+    // A transaction can commit after one holding a higher server_seq; the log orders
+    // events by when they became visible, so reading past the last position skips none
+    if let Err(e) = db::sequence_replication_log(pool).await {
+        error!("hub: sequencing failed: {}", e);
+        return;
+    }
+    loop {
+        let batch = match db::fetch_replication_batch(pool, None, &OutboundFilter::default(), *published, BACKFILL_BATCH).await {
+            Ok(batch) => batch,
+            Err(e) => {
+                error!("hub: catch-up failed: {}", e);
+                return;
+            }
+        };
+        let full = batch.entries.len() as i64 == BACKFILL_BATCH;
+        for entry in batch.entries {
+            *published = entry.repl_seq;
+            hub.publish(entry.repl_seq, entry.event);
+        }
+        let position = *published;
+        hub.head.send_if_modified(|head| std::mem::replace(head, position) != position);
+        if !full {
+            return;
+        }
+    }
+}
+
+/// What a follower hands to its subscriber
+#[derive(Debug, Clone)]
+pub enum Delivery {
+    Event(Arc<Event>),
+    /// Live-only event with `server_seq` 0; never part of a backfill
+    Ephemeral(Arc<Event>),
+    /// All stored events up to now have been sent; what follows is live
+    EndOfStored,
+}
+
+#[derive(Debug)]
+pub enum FollowError {
+    Db(sqlx::Error),
+    /// The subscriber went away
+    Closed,
+    /// The subscriber's buffer filled up with live events
+    SlowConsumer,
+}
+
+impl std::fmt::Display for FollowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FollowError::Db(e) => write!(f, "storage error: {}", e),
+            FollowError::Closed => write!(f, "subscriber closed"),
+            FollowError::SlowConsumer => write!(f, "subscriber too slow"),
+        }
+    }
+}
+
+impl From<sqlx::Error> for FollowError {
+    fn from(e: sqlx::Error) -> Self {
+        FollowError::Db(e)
+    }
+}
+
+struct Seen {
+    seqs: BTreeSet<i64>,
+}
+
+impl Seen {
+    fn insert(&mut self, seq: i64) -> bool {
+        let fresh = self.seqs.insert(seq);
+        if self.seqs.len() > SEEN_WINDOW {
+            self.seqs.pop_first();
+        }
+        fresh
+    }
+}
+
+/// Where a resumed stream continues from: the `server_seq` of the last event a reconnecting
+/// client got (SSE `Last-Event-ID`) wins over the position it asked for. IDs that aren't a
+/// `server_seq` are ignored.
+pub fn resume_position(requested: Option<i64>, last_event_id: Option<&str>) -> Option<i64> {
+    last_event_id.and_then(|id| id.trim().parse::<i64>().ok()).or(requested)
+}
+
+/// Streams stored events matching `filter` after the `server_seq` `after`, then live ones
+/// from the hub. Without `after` only live events are sent, from the hub's current head.
+///
+/// The hub is subscribed before reading storage, so nothing committed in between is lost:
+/// whatever the backfill can't see yet is published afterwards, even below `after`.
+/// Events below `after` that committed after the event at `after` (a resumed stream) are
+/// replayed from the log. Backfill waits for the subscriber; live delivery does not, and a
+/// full buffer ends the subscription with `SlowConsumer` instead of stalling the hub.
+/// Matching ephemeral events are interleaved once live; they are dropped rather than queued
+/// when the buffer is full.
+pub async fn follow<T>(
+    pool: &PgPool,
+    hub: &EventHub,
+    filter: &EventFilter,
+    after: Option<i64>,
+    out: &mpsc::Sender<T>,
+    wrap: impl Fn(Delivery) -> T,
+) -> Result<(), FollowError> {
+    // Read before subscribing: everything published from here on lies past this position
+    let mut position = *hub.head.borrow();
+    let mut rx = hub.subscribe();
+    let mut seen = Seen { seqs: BTreeSet::new() };
+
+    match after {
+        Some(after) => {
+            backfill(pool, filter, after, &mut seen, out, &wrap).await?;
+            // server_seq is taken at insert, so a transaction holding a lower one can commit
+            // after the subscriber got `after`; the log has it past that event
+            if let Some(mut from) = db::fetch_repl_seq(pool, after).await? {
+                replay_log(pool, filter, &mut from, Some(after), &mut seen, out, &wrap).await?;
+            }
+        },
+        None => {
+            if position == 0 {
+                // The listener hasn't published anything yet, so there is no head to go by
+                position = db::fetch_replication_log_head(pool).await?;
+            }
+            // Published between reading the head and subscribing
+            replay_log(pool, filter, &mut position, None, &mut seen, out, &wrap).await?;
+        },
+    }
+    out.send(wrap(Delivery::EndOfStored)).await.map_err(|_| FollowError::Closed)?;
+    let mut ephemeral_rx = hub.subscribe_ephemeral();
+
+    loop {
+        let received = tokio::select! {
+            // Notice a vanished subscriber even when no matching events arrive
+            _ = out.closed() => return Err(FollowError::Closed),
+            received = rx.recv() => received,
+            Ok(ev) = ephemeral_rx.recv() => {
+                if filter.matches(&ev) {
+                    if let Err(mpsc::error::TrySendError::Closed(_)) = out.try_send(wrap(Delivery::Ephemeral(ev))) {
+                        return Err(FollowError::Closed);
+                    }
+                }
+                continue;
+            },
+        };
+        match received {
+            Ok(Published { repl_seq, event: ev }) => {
+                position = position.max(repl_seq);
+                if !filter.matches(&ev) || !seen.insert(ev.server_seq) {
+                    continue;
+                }
+                match out.try_send(wrap(Delivery::Event(ev))) {
+                    Ok(()) => {},
+                    Err(mpsc::error::TrySendError::Full(_)) => return Err(FollowError::SlowConsumer),
+                    Err(mpsc::error::TrySendError::Closed(_)) => return Err(FollowError::Closed),
+                }
+            },
+            // We fell behind the hub: the log still has everything past the last event we got
+            Err(broadcast::error::RecvError::Lagged(_)) => {
+                replay_log(pool, filter, &mut position, None, &mut seen, out, &wrap).await?;
+            },
+            Err(broadcast::error::RecvError::Closed) => return Ok(()),
+        }
+    }
+}
+
+async fn backfill<T>(
+    pool: &PgPool,
+    filter: &EventFilter,
+    mut after: i64,
+    seen: &mut Seen,
+    out: &mpsc::Sender<T>,
+    wrap: &impl Fn(Delivery) -> T,
+) -> Result<(), FollowError> {
+    loop {
+        let page = db::fetch_events_page(pool, filter, Order::Asc, Some(after), false, BACKFILL_BATCH, true).await?;
+        for ev in page.events {
+            after = ev.server_seq;
+            if seen.insert(ev.server_seq) {
+                out.send(wrap(Delivery::Event(Arc::new(ev)))).await.map_err(|_| FollowError::Closed)?;
+            }
+        }
+        if !page.has_more {
+            return Ok(());
+        }
+    }
+}
+
+/// Sends matching events published after log `position` that haven't been delivered yet,
+/// leaving out those with a `server_seq` above `up_to`
+async fn replay_log<T>(
+    pool: &PgPool,
+    filter: &EventFilter,
+    position: &mut i64,
+    up_to: Option<i64>,
+    seen: &mut Seen,
+    out: &mpsc::Sender<T>,
+    wrap: &impl Fn(Delivery) -> T,
+) -> Result<(), FollowError> {
+    let filter = OutboundFilter { filter: filter.clone(), ..Default::default() };
+    loop {
+        let batch = db::fetch_replication_batch(pool, None, &filter, *position, BACKFILL_BATCH).await?;
+        let full = batch.entries.len() as i64 == BACKFILL_BATCH;
+        for entry in batch.entries {
+            *position = entry.repl_seq;
+            if up_to.is_some_and(|seq| entry.event.server_seq > seq) {
+                continue;
+            }
+            if seen.insert(entry.event.server_seq) {
+                out.send(wrap(Delivery::Event(Arc::new(entry.event)))).await.map_err(|_| FollowError::Closed)?;
+            }
+        }
+        if !full {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use axum::http::StatusCode;
+use ed25519_dalek::VerifyingKey;
+use infusion::infusion::sign;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::db::{self, EventInput, ReplicatedEvent};
+use crate::ephemeral::{self, Ephemeral};
+use crate::nostr;
+use crate::utils::{compute_payload_hash, signing_bytes};
+
+pub const MAX_REFS_PER_EVENT: usize = 64;
+pub const MAX_TAGS_PER_EVENT: usize = 100;
+/// Nostr lists (follows, mutes, relays, ...) carry one tag per entry
+pub const MAX_NOSTR_TAGS_PER_EVENT: usize = 10_000;
+pub const MAX_TAG_VALUE_LEN: usize = 1024;
+
+/// Why an event was refused, with the HTTP status the push endpoint answers with
+pub type Rejection = (StatusCode, String);
+
+/// Fills in `payload_hash` and checks structure and signature of a single event.
+pub fn validate_event(ev: &mut EventInput) -> Result<(), Rejection> {
+    // 1. Calculate payload_hash via Infusion (canonical hash)
+    ev.payload_hash = compute_payload_hash(&ev.payload_json);
+
+    if ev.refs.len() > MAX_REFS_PER_EVENT {
+        return Err((StatusCode::BAD_REQUEST, format!("too many refs (max {})", MAX_REFS_PER_EVENT)));
+    }
+    for r in &ev.refs {
+        if r.kind.is_empty() || r.kind.len() > 64 {
+            return Err((StatusCode::BAD_REQUEST, "ref kind must be 1-64 characters".to_string()));
+        }
+        if r.event_id == ev.event_id {
+            return Err((StatusCode::BAD_REQUEST, "event cannot reference itself".to_string()));
+        }
+    }
+
+    let max_tags = if ev.sig_scheme == db::SIG_SCHEME_NOSTR { MAX_NOSTR_TAGS_PER_EVENT } else { MAX_TAGS_PER_EVENT };
+    if ev.tags.len() > max_tags {
+        return Err((StatusCode::BAD_REQUEST, format!("too many tags (max {})", max_tags)));
+    }
+    for t in &ev.tags {
+        match t.first() {
+            Some(key) if !key.is_empty() && key.len() <= 64 => {},
+            _ => return Err((StatusCode::BAD_REQUEST, "tag key must be 1-64 characters".to_string())),
+        }
+        if t.iter().any(|v| v.len() > MAX_TAG_VALUE_LEN) {
+            return Err((StatusCode::BAD_REQUEST, format!("tag values are limited to {} bytes", MAX_TAG_VALUE_LEN)));
+        }
+    }
+
+    // 2. Validate signature with the event's scheme
+    match ev.sig_scheme.as_str() {
+        db::SIG_SCHEME_ED25519 => verify_ed25519(ev),
+        db::SIG_SCHEME_NOSTR => nostr::verify_input(ev).map_err(|msg| (StatusCode::UNAUTHORIZED, msg)),
+        other => Err((StatusCode::BAD_REQUEST, format!("unknown sig_scheme: {}", other))),
+    }
+}
+
+/// Native events: Ed25519 via Infusion over `signing_bytes`
+fn verify_ed25519(ev: &EventInput) -> Result<(), Rejection> {
+    let pubkey_bytes = hex::decode(&ev.author_pubkey)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "invalid author_pubkey hex".to_string()))?;
+    let sig_bytes = hex::decode(&ev.signature)
+        .map_err(|_| (StatusCode::BAD_REQUEST, "invalid signature hex".to_string()))?;
+
+    let vk = VerifyingKey::from_bytes(&pubkey_bytes.try_into().unwrap_or([0u8; 32]))
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "invalid public key".to_string()))?;
+
+    let sig_array: [u8; 64] = sig_bytes.try_into()
+        .map_err(|_| (StatusCode::UNAUTHORIZED, "invalid signature length".to_string()))?;
+
+    let payload_bytes = signing_bytes(&ev.payload_json, &ev.refs, &ev.tags);
+
+    if sign::verify(&vk, &payload_bytes, &sig_array).is_err() {
+        return Err((StatusCode::UNAUTHORIZED, "invalid signature".to_string()));
+    }
+
+    Ok(())
+}
+
+/// Inserts already validated events. Live subscribers learn about them through the
+/// insert trigger's NOTIFY, which also covers events inserted by other relay instances.
+/// Returns the `server_seq` of each event, or `None` for duplicates.
+pub async fn insert_validated(pool: &PgPool, events: &[ReplicatedEvent]) -> Result<Vec<Option<i64>>, sqlx::Error> {
+    let mut seqs = Vec::with_capacity(events.len());
+    for ev in events {
+        seqs.push(db::insert_event_with_provenance(pool, &ev.event, &ev.provenance).await?);
+    }
+    Ok(seqs)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutcomeStatus {
+    Accepted,
+    Duplicate,
+    Rejected,
+    /// Delivered to live subscribers only, not stored
+    Ephemeral,
+}
+
+/// Per-event result for endpoints that don't fail a whole batch on one bad event
+#[derive(Debug, Clone, Serialize)]
+pub struct EventOutcome {
+    pub event_id: Uuid,
+    pub status: OutcomeStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_seq: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// A rejection that may succeed later (e.g. a rate limit), unlike an invalid event
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub retryable: bool,
+}
+
+impl EventOutcome {
+    pub fn rejected(event_id: Uuid, error: String) -> Self {
+        EventOutcome { event_id, status: OutcomeStatus::Rejected, server_seq: None, error: Some(error), retryable: false }
+    }
+
+    fn refused(event_id: Uuid, (code, error): Rejection) -> Self {
+        let retryable = code == StatusCode::TOO_MANY_REQUESTS || code.is_server_error();
+        EventOutcome { retryable, ..EventOutcome::rejected(event_id, error) }
+    }
+
+    fn of(event_id: Uuid, status: OutcomeStatus, server_seq: Option<i64>) -> Self {
+        EventOutcome { event_id, status, server_seq, error: None, retryable: false }
+    }
+}
+
+/// Validates and inserts each event independently, reporting one outcome per input event.
+/// Ephemeral events are fanned out instead of stored. Only storage errors abort the batch.
+pub async fn ingest_each(pool: &PgPool, ephemeral: &Ephemeral, events: Vec<EventInput>) -> Result<Vec<EventOutcome>, sqlx::Error> {
+    ingest_each_replicated(pool, ephemeral, events.into_iter().map(ReplicatedEvent::local).collect()).await
+}
+
+/// `ingest_each` for events that keep the provenance they arrived with.
+pub async fn ingest_each_replicated(pool: &PgPool, ephemeral: &Ephemeral, events: Vec<ReplicatedEvent>) -> Result<Vec<EventOutcome>, sqlx::Error> {
+    let mut outcomes = Vec::with_capacity(events.len());
+    for mut ev in events {
+        let event_id = ev.event.event_id;
+        if let Err(rejection) = validate_event(&mut ev.event) {
+            outcomes.push(EventOutcome::refused(event_id, rejection));
+            continue;
+        }
+        if ephemeral.is_ephemeral(&ev.event) {
+            if let Err(rejection) = ephemeral.admit(&ev.event) {
+                outcomes.push(EventOutcome::refused(event_id, rejection));
+                continue;
+            }
+            ephemeral::publish(pool, &ev.event).await?;
+            outcomes.push(EventOutcome::of(event_id, OutcomeStatus::Ephemeral, None));
+            continue;
+        }
+        let seq = db::insert_event_with_provenance(pool, &ev.event, &ev.provenance).await?;
+        let status = if seq.is_some() { OutcomeStatus::Accepted } else { OutcomeStatus::Duplicate };
+        outcomes.push(EventOutcome::of(event_id, status, seq));
+    }
+    Ok(outcomes)
+}
+
+/// What became of one replicated event at the receiving relay
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplicateStatus {
+    Stored,
+    Duplicate,
+    /// Not stored on purpose (a loop, the hop limit, or an ephemeral event); nothing to retry
+    Skipped,
+    /// Refused for good (invalid, or outside the inbound policy); the sender quarantines it
+    Rejected,
+    /// Refused for now (e.g. rate limited); the sender tries again later
+    Retry,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicateOutcome {
+    pub event_id: Uuid,
+    pub status: ReplicateStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl From<EventOutcome> for ReplicateOutcome {
+    fn from(o: EventOutcome) -> Self {
+        let status = match o.status {
+            OutcomeStatus::Accepted => ReplicateStatus::Stored,
+            OutcomeStatus::Duplicate => ReplicateStatus::Duplicate,
+            OutcomeStatus::Ephemeral => ReplicateStatus::Skipped,
+            OutcomeStatus::Rejected if o.retryable => ReplicateStatus::Retry,
+            OutcomeStatus::Rejected => ReplicateStatus::Rejected,
+        };
+        ReplicateOutcome { event_id: o.event_id, status, error: o.error }
+    }
+}
+
+/// A sender's reading of the outcomes a peer reported for one pushed batch
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Acknowledgement {
+    /// Events the peer refused for good, with its reason; they count as delivered
+    pub rejected: Vec<(Uuid, String)>,
+    /// Log position of the first event still to be delivered (refused for now, or
+    /// without an outcome), and why
+    pub held_back: Option<(i64, String)>,
+}
+
+impl Acknowledgement {
+    /// Where the cursor may move after a batch that covered the log up to `covered`
+    pub fn cursor(&self, covered: i64) -> i64 {
+        self.held_back.as_ref().map_or(covered, |(seq, _)| seq - 1)
+    }
+}
+
+/// Matches `outcomes` against the sent events, given as (event id, log position) in log
+/// order. Relays that predate per-event outcomes send none; their 2xx covers the batch.
+pub fn acknowledge(sent: &[(Uuid, i64)], outcomes: Option<Vec<ReplicateOutcome>>) -> Acknowledgement {
+    let mut ack = Acknowledgement::default();
+    let Some(outcomes) = outcomes else { return ack };
+    let outcomes: HashMap<Uuid, ReplicateOutcome> = outcomes.into_iter().map(|o| (o.event_id, o)).collect();
+    for &(event_id, seq) in sent {
+        let Some(outcome) = outcomes.get(&event_id) else {
+            ack.held_back = Some((seq, format!("no outcome for event {}", event_id)));
+            break;
+        };
+        let error = outcome.error.clone().unwrap_or_else(|| "no reason given".to_string());
+        match outcome.status {
+            ReplicateStatus::Retry => {
+                ack.held_back = Some((seq, format!("event {} deferred: {}", event_id, error)));
+                break;
+            },
+            ReplicateStatus::Rejected => ack.rejected.push((event_id, error)),
+            ReplicateStatus::Stored | ReplicateStatus::Duplicate | ReplicateStatus::Skipped => {},
+        }
+    }
+    ack
+}
@@ -1,2 +1,13 @@
+pub mod cursor;
 pub mod db;
+pub mod ephemeral;
+pub mod filter;
+pub mod fingerprints;
+pub mod hub;
+pub mod ingest;
+pub mod ndjson;
+pub mod nostr;
+pub mod projection;
+pub mod proto;
+pub mod supervisor;
 pub mod utils;
@@ -1,25 +1,39 @@
 use std::net::SocketAddr;
-use std::time::Duration;
 use std::sync::Arc;
+use std::time::Duration;
 
 use axum::{
-    extract::{State, Query}, 
-    routing::{get, post}, 
+    extract::{DefaultBodyLimit, Path, State, Query}, 
+    routing::{get, post, put}, 
     Json, Router, response::IntoResponse, 
     http::{StatusCode, HeaderMap}
 };
 use clap::{Parser, Subcommand};
-use infusion::infusion::sign;
-use infusion::infusion::cid::cid_blake3;
-use ed25519_dalek::VerifyingKey;
-use hex;
+use ed25519_dalek::SigningKey;
 use serde::{Deserialize, Serialize};
 use sqlx::PgPool;
 use tracing::{info, error, warn};
 use uuid::Uuid;
 
+use tisane_relay::cursor::{CursorCodec, Order};
 use tisane_relay::db;
-use tisane_relay::utils::compute_payload_hash;
+use tisane_relay::ephemeral::{self, Ephemeral};
+use tisane_relay::filter::{EventFilter, InboundPolicy, OutboundFilter};
+use tisane_relay::hub::{self, EventHub};
+use tisane_relay::ingest::{self, ReplicateOutcome, ReplicateStatus};
+use tisane_relay::ndjson;
+use tisane_relay::projection::Projection;
+
+mod admin;
+mod consumers;
+mod grpc;
+mod nostr_ws;
+mod peer_policy;
+mod reconcile;
+mod replication;
+mod sse;
+mod webhooks;
+mod ws;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -28,22 +42,161 @@ struct Args {
     command: Commands,
 }
 
+#[derive(clap::Args, Debug)]
+struct ServeArgs {
+    /// Port to bind to (or use PORT env var)
+    #[arg(long, env = "PORT", default_value_t = 8080)]
+    port: u16,
+
+    /// Postgres database URL (or use DATABASE_URL env var)
+    #[arg(long, env = "DATABASE_URL")]
+    database_url: String,
+
+    /// Unique ID for this relay (if not provided, one is generated randomly)
+    #[arg(long, env = "RELAY_ID")]
+    relay_id: Option<Uuid>,
+
+    /// Secret used to sign pull cursors (if not provided, one is generated and cursors don't survive restarts)
+    #[arg(long, env = "CURSOR_SECRET")]
+    cursor_secret: Option<String>,
+
+    /// Hex-encoded Ed25519 seed (32 bytes) the relay signs with, e.g. ed25519 webhooks
+    #[arg(long, env = "RELAY_SIGNING_KEY")]
+    signing_key: Option<String>,
+
+    /// Comma-separated event types that are relayed live but never stored (Nostr kinds 20000-29999 always are)
+    #[arg(long, env = "EPHEMERAL_EVENT_TYPES", value_delimiter = ',')]
+    ephemeral_types: Vec<String>,
+
+    /// Ephemeral events accepted per author and minute
+    #[arg(long, env = "EPHEMERAL_RATE_PER_MIN", default_value_t = 120)]
+    ephemeral_rate: u32,
+
+    /// Also serve the gRPC API on this port (disabled if not set)
+    #[arg(long, env = "GRPC_PORT")]
+    grpc_port: Option<u16>,
+
+    /// Relay-to-relay transfers an event may take; events at the limit are stored but not forwarded
+    #[arg(long, env = "MAX_HOPS", default_value_t = 3)]
+    max_hops: i32,
+
+    /// Batches exchanged with peers at the same time
+    #[arg(long, env = "REPLICATION_CONCURRENCY", default_value_t = 8)]
+    replication_concurrency: usize,
+
+    /// Timeout in seconds for each request to a peer
+    #[arg(long, env = "PEER_TIMEOUT_SECS", default_value_t = 30)]
+    peer_timeout_secs: u64,
+
+    /// Seconds between anti-entropy reconciliations with each peer (0 disables them)
+    #[arg(long, env = "RECONCILE_INTERVAL_SECS", default_value_t = 3600)]
+    reconcile_interval_secs: u64,
+
+    /// Inbound policy violations within an hour after which a peer is suspended (0 never suspends)
+    #[arg(long, env = "PEER_VIOLATION_LIMIT", default_value_t = 20)]
+    peer_violation_limit: i32,
+
+    /// Bearer token for the /admin API (disabled if not set)
+    #[arg(long, env = "ADMIN_TOKEN")]
+    admin_token: Option<String>,
+}
+
+/// Which of our events a peer is sent
+#[derive(clap::Args, Debug)]
+struct PeerFilterArgs {
+    /// Comma-separated event types to send (default: all)
+    #[arg(long)]
+    event_types: Option<String>,
+    /// Comma-separated author public keys to send (default: all)
+    #[arg(long)]
+    authors: Option<String>,
+    /// Comma-separated author public keys never to send
+    #[arg(long)]
+    deny_authors: Option<String>,
+    /// Tag filter as key=value (repeatable)
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// Only send events that occurred within this many seconds (0: no limit)
+    #[arg(long, value_parser = clap::value_parser!(i64).range(0..))]
+    max_age_secs: Option<i64>,
+}
+
+impl PeerFilterArgs {
+    /// Applies the flags that were given to `filter`, leaving the other parts as they are.
+    /// An empty list (e.g. `--event-types ""`) lifts that restriction.
+    fn apply(self, filter: &mut OutboundFilter) -> anyhow::Result<()> {
+        if let Some(event_types) = self.event_types {
+            filter.filter.event_types = tisane_relay::filter::split_list(Some(&event_types));
+        }
+        if let Some(authors) = self.authors {
+            filter.filter.authors = tisane_relay::filter::split_list(Some(&authors));
+        }
+        if let Some(deny_authors) = self.deny_authors {
+            filter.deny_authors = tisane_relay::filter::split_list(Some(&deny_authors));
+        }
+        if !self.tags.is_empty() {
+            filter.filter.tags = filter_from_flags(None, None, self.tags)?.tags;
+        }
+        if let Some(secs) = self.max_age_secs {
+            filter.max_age_secs = (secs > 0).then_some(secs);
+        }
+        Ok(())
+    }
+}
+
+/// What we accept from a peer
+#[derive(clap::Args, Debug)]
+struct PeerPolicyArgs {
+    /// Comma-separated event types accepted from the peer (default: all)
+    #[arg(long)]
+    accept_event_types: Option<String>,
+    /// Comma-separated author public keys accepted from the peer (default: all)
+    #[arg(long)]
+    accept_authors: Option<String>,
+    /// Most events per replicate request from the peer (0: no limit)
+    #[arg(long)]
+    max_inbound_batch: Option<u32>,
+    /// Most events per minute from the peer (0: no limit); at least the batch limit.
+    /// Events over the budget are handed back for the peer to retry.
+    #[arg(long)]
+    max_inbound_per_min: Option<u32>,
+    /// Let the peer pull from us but accept nothing from it
+    #[arg(long)]
+    read_only: Option<bool>,
+}
+
+impl PeerPolicyArgs {
+    /// Applies the flags that were given to `policy`, leaving the other parts as they are.
+    fn apply(self, policy: &mut InboundPolicy) -> anyhow::Result<()> {
+        if let Some(event_types) = self.accept_event_types {
+            policy.event_types = tisane_relay::filter::split_list(Some(&event_types));
+        }
+        if let Some(authors) = self.accept_authors {
+            policy.authors = tisane_relay::filter::split_list(Some(&authors));
+        }
+        if let Some(max) = self.max_inbound_batch {
+            policy.max_batch_events = (max > 0).then_some(max);
+        }
+        if let Some(rate) = self.max_inbound_per_min {
+            policy.max_events_per_min = (rate > 0).then_some(rate);
+        }
+        if let Some(read_only) = self.read_only {
+            policy.read_only = read_only;
+        }
+        // A full batch must fit in the budget, or the peer could never catch up
+        if let (Some(max), Some(rate)) = (policy.max_batch_events, policy.max_events_per_min) {
+            if rate < max {
+                anyhow::bail!("the inbound rate ({} per minute) must be at least the inbound batch limit ({})", rate, max);
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Subcommand, Debug)]
 enum Commands {
     /// Start the relay server
-    Serve {
-        /// Port to bind to (or use PORT env var)
-        #[arg(long, env = "PORT", default_value_t = 8080)]
-        port: u16,
-
-        /// Postgres database URL (or use DATABASE_URL env var)
-        #[arg(long, env = "DATABASE_URL")]
-        database_url: String,
-
-        /// Unique ID for this relay (if not provided, one is generated randomly)
-        #[arg(long, env = "RELAY_ID")]
-        relay_id: Option<Uuid>,
-    },
+    Serve(ServeArgs),
     /// Add a new peer
     AddPeer {
         /// Peer URL (e.g., http://peer-relay:8080)
@@ -52,6 +205,72 @@ enum Commands {
         /// Shared secret for authentication
         #[arg(long)]
         secret: String,
+        /// push, pull (for peers we can reach but that can't reach us), both, or none (inbound only)
+        #[arg(long, default_value = "push", value_parser = ["push", "pull", "both", "none"])]
+        direction: String,
+        /// Events per replication batch
+        #[arg(long, default_value_t = 50, value_parser = clap::value_parser!(i32).range(1..=1000))]
+        batch_size: i32,
+        /// JSON size budget per batch in bytes (a single larger event is still sent alone)
+        #[arg(long, default_value_t = 1024 * 1024, value_parser = clap::value_parser!(i32).range(1024..=MAX_BATCH_BYTES))]
+        max_batch_bytes: i32,
+        #[command(flatten)]
+        filter: PeerFilterArgs,
+        #[command(flatten)]
+        policy: PeerPolicyArgs,
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+    /// Change a peer's settings; options not given keep their current value
+    UpdatePeer {
+        #[arg(long)]
+        peer_id: Uuid,
+        #[arg(long, value_parser = ["push", "pull", "both", "none"])]
+        direction: Option<String>,
+        #[arg(long, value_parser = clap::value_parser!(i32).range(1..=1000))]
+        batch_size: Option<i32>,
+        #[arg(long, value_parser = clap::value_parser!(i32).range(1024..=MAX_BATCH_BYTES))]
+        max_batch_bytes: Option<i32>,
+        /// Drop the current outbound filter before applying the filter options
+        #[arg(long)]
+        clear_filter: bool,
+        #[command(flatten)]
+        filter: PeerFilterArgs,
+        /// Drop the current inbound policy before applying the policy options
+        #[arg(long)]
+        clear_policy: bool,
+        #[command(flatten)]
+        policy: PeerPolicyArgs,
+        /// Lift a suspension and reset the violation count
+        #[arg(long)]
+        unsuspend: bool,
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+    /// Show how far replication with each peer has got
+    PeerStatus {
+        /// Only this peer (default: all)
+        #[arg(long)]
+        peer_id: Option<Uuid>,
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+    /// Show events a peer rejected for good (or we rejected from it), newest first
+    Quarantine {
+        #[arg(long)]
+        peer_id: Option<Uuid>,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+    /// Compare our events with a peer's and report the difference (nothing is transferred)
+    Reconcile {
+        #[arg(long)]
+        peer_id: Uuid,
+        /// This relay's ID, sent to the peer (optional)
+        #[arg(long, env = "RELAY_ID")]
+        relay_id: Option<Uuid>,
         #[arg(long, env = "DATABASE_URL")]
         database_url: String,
     },
@@ -68,80 +287,242 @@ enum Commands {
         #[arg(long, env = "DATABASE_URL")]
         database_url: String,
     },
+    /// Register a webhook that receives new events
+    AddWebhook {
+        /// Endpoint URL events are POSTed to
+        #[arg(long)]
+        url: String,
+        /// Signing scheme: hmac (needs --secret) or ed25519 (relay signing key)
+        #[arg(long, default_value = "hmac")]
+        signing: String,
+        /// HMAC secret
+        #[arg(long)]
+        secret: Option<String>,
+        /// Comma-separated event types to deliver (default: all)
+        #[arg(long)]
+        event_types: Option<String>,
+        /// Comma-separated author public keys to deliver (default: all)
+        #[arg(long)]
+        authors: Option<String>,
+        /// Tag filter as key=value (repeatable)
+        #[arg(long = "tag")]
+        tags: Vec<String>,
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+    /// List webhooks
+    ListWebhooks {
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+    /// Remove a webhook and its delivery queue
+    RemoveWebhook {
+        #[arg(long)]
+        webhook_id: Uuid,
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+    /// Show dead-lettered webhook deliveries
+    DeadLetters {
+        #[arg(long)]
+        webhook_id: Option<Uuid>,
+        #[arg(long, default_value_t = 50)]
+        limit: i64,
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
+    /// Requeue a dead delivery, or all dead deliveries of a webhook
+    Redeliver {
+        #[arg(long, required_unless_present = "webhook_id")]
+        delivery_id: Option<i64>,
+        #[arg(long)]
+        webhook_id: Option<Uuid>,
+        #[arg(long, env = "DATABASE_URL")]
+        database_url: String,
+    },
 }
 
 #[derive(Clone)]
 struct AppState {
     pool: PgPool,
     relay_id: Uuid,
+    cursors: CursorCodec,
+    hub: EventHub,
+    signing_key: Option<SigningKey>,
+    ephemeral: Arc<Ephemeral>,
+    max_hops: i32,
+    peer_rates: Arc<peer_policy::PeerRateLimits>,
+    /// Policy violations within an hour that suspend a peer (0: never)
+    violation_limit: i32,
+    /// Bearer token for the admin API; disabled when unset
+    admin_token: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct PullQuery {
+    /// Legacy numeric cursor (ascending `server_seq`)
     since: Option<i64>,
+    /// Opaque cursor from a previous response; carries order and filters
+    cursor: Option<String>,
+    order: Option<Order>,
     limit: Option<i64>,
+    author: Option<String>,
+    event_type: Option<String>,
+    content_id: Option<String>,
+    /// Comma-separated projection, e.g. `event_id,event_type,payload_hash`
+    fields: Option<String>,
+    /// Long-poll: seconds to wait for new events when the page would be empty
+    wait: Option<u64>,
 }
 
 #[derive(Serialize)]
-struct PullResp {
-    events: Vec<db::Event>,
-    next_cursor: i64,
+#[serde(untagged)]
+enum PullEvents {
+    Full(Vec<db::Event>),
+    Projected(Vec<serde_json::Value>),
 }
 
-async fn health() -> impl IntoResponse {
-    (StatusCode::OK, Json(serde_json::json!({"status":"ok"})))
+#[derive(Serialize)]
+struct PullResp {
+    events: PullEvents,
+    next_cursor: Option<String>,
+    prev_cursor: Option<String>,
+    has_more: bool,
 }
 
-// Reusable logic to validate and insert events
-async fn validate_and_insert(pool: &PgPool, mut events: Vec<db::EventInput>) -> Result<Vec<i64>, (StatusCode, String)> {
-    for ev in &mut events {
-        // 1. Calculate payload_hash via Infusion (canonical hash)
-        ev.payload_hash = compute_payload_hash(&ev.payload_json);
+const MAX_PULL_LIMIT: i64 = 1000;
+const MAX_PULL_WAIT_SECS: u64 = 60;
 
-        // 2. Validate signature using Infusion
-        let pubkey_bytes = hex::decode(&ev.author_pubkey)
-            .map_err(|_| (StatusCode::BAD_REQUEST, "invalid author_pubkey hex".to_string()))?;
-        let sig_bytes = hex::decode(&ev.signature)
-            .map_err(|_| (StatusCode::BAD_REQUEST, "invalid signature hex".to_string()))?;
+#[derive(Deserialize)]
+struct ThreadQuery {
+    depth: Option<i32>,
+}
 
-        let vk = VerifyingKey::from_bytes(&pubkey_bytes.try_into().unwrap_or([0u8; 32]))
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "invalid public key".to_string()))?;
+const MAX_THREAD_DEPTH: i32 = 50;
 
-        let sig_array: [u8; 64] = sig_bytes.try_into()
-            .map_err(|_| (StatusCode::UNAUTHORIZED, "invalid signature length".to_string()))?;
+#[derive(Deserialize)]
+struct StatsParams {
+    /// Comma-separated dimensions: event_type, author, author_id, content_id, device_id
+    group_by: Option<String>,
+    bucket: Option<db::StatsBucket>,
+    from: Option<chrono::DateTime<chrono::Utc>>,
+    to: Option<chrono::DateTime<chrono::Utc>>,
+    limit: Option<i64>,
+    author: Option<String>,
+    event_type: Option<String>,
+    content_id: Option<String>,
+}
 
-        let payload_bytes = if let Some(p) = ev.payload_json.as_ref() {
-            p.to_string().into_bytes()
-        } else {
-            vec![]
-        };
+const MAX_STATS_GROUPS: i64 = 1000;
+const MAX_STATS_BUCKETS: i32 = 1000;
+const DEFAULT_STATS_WINDOW_DAYS: i64 = 30;
 
-        if let Err(_) = sign::verify(&vk, &payload_bytes, &sig_array) {
-           return Err((StatusCode::UNAUTHORIZED, "invalid signature".to_string()));
-        }
+async fn health() -> impl IntoResponse {
+    (StatusCode::OK, Json(serde_json::json!({"status":"ok"})))
+}
+
+// Reusable logic to validate and insert events; ephemeral events are fanned out instead
+async fn validate_and_insert(state: &AppState, mut events: Vec<db::ReplicatedEvent>) -> Result<Vec<i64>, (StatusCode, String)> {
+    for ev in &mut events {
+        ingest::validate_event(&mut ev.event)?;
     }
+    let (transient, events): (Vec<_>, Vec<_>) = events.into_iter().partition(|ev| state.ephemeral.is_ephemeral(&ev.event));
+    // All or nothing, so a refused batch doesn't use up the authors' budgets
+    state.ephemeral.admit_all(transient.iter().map(|ev| &ev.event))?;
 
-    match db::insert_events(pool, &events).await {
-        Ok(inserted) => Ok(inserted),
+    let seqs = match ingest::insert_validated(&state.pool, &events).await {
+        Ok(seqs) => seqs.into_iter().flatten().collect(),
         Err(e) => {
             error!("insert error: {}", e);
-            Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
+        }
+    };
+    for ev in &transient {
+        if let Err(e) = ephemeral::publish(&state.pool, &ev.event).await {
+            error!("ephemeral publish error: {}", e);
+            return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()));
         }
     }
+    Ok(seqs)
 }
 
 async fn push_handler(State(state): State<AppState>, Json(events): Json<Vec<db::EventInput>>) -> impl IntoResponse {
-    match validate_and_insert(&state.pool, events).await {
+    match validate_and_insert(&state, events.into_iter().map(db::ReplicatedEvent::local).collect()).await {
         Ok(inserted) => (StatusCode::OK, Json(serde_json::json!({"inserted": inserted.len()}))).into_response(),
         Err((code, msg)) => (code, Json(serde_json::json!({"error": msg}))).into_response(),
     }
 }
 
-async fn pull_handler(State(state): State<AppState>, Query(q): Query<PullQuery>) -> impl IntoResponse {
-    let since = q.since.unwrap_or(0);
-    let limit = q.limit.unwrap_or(100);
-    match db::fetch_events_since(&state.pool, since, limit).await {
-        Ok((events, next_cursor)) => (StatusCode::OK, Json(PullResp{ events, next_cursor })).into_response(),
+async fn push_ndjson_handler(State(state): State<AppState>, body: axum::body::Body) -> impl IntoResponse {
+    ndjson::push_response(state.pool.clone(), state.ephemeral.clone(), body)
+}
+
+async fn pull_handler(
+    State(state): State<AppState>,
+    Query(q): Query<PullQuery>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> impl IntoResponse {
+    let limit = q.limit.unwrap_or(100).clamp(1, MAX_PULL_LIMIT);
+
+    let projection = match q.fields.as_deref().map(Projection::parse).transpose() {
+        Ok(p) => p,
+        Err(msg) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": msg}))).into_response(),
+    };
+    let include_payload = projection.as_ref().is_none_or(|p| p.includes("payload_json"));
+
+    // A cursor pins order and filters; otherwise they come from the query string
+    let (order, backward, after, filter) = match q.cursor.as_deref() {
+        Some(token) => match state.cursors.decode(token) {
+            Ok(c) => (c.order, c.backward, Some(c.seq), c.filter),
+            Err(e) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+        },
+        None => (
+            q.order.unwrap_or_default(),
+            false,
+            q.since,
+            EventFilter::from_query(q.author.as_deref(), q.event_type.as_deref(), q.content_id.as_deref())
+                .with_tag_params(&params),
+        ),
+    };
+
+    // Only a forward ascending pull has a "new events" direction to wait on
+    let wait = match (order, backward) {
+        (Order::Asc, false) => Duration::from_secs(q.wait.unwrap_or(0).min(MAX_PULL_WAIT_SECS)),
+        _ => Duration::ZERO,
+    };
+    let deadline = tokio::time::Instant::now() + wait;
+    let mut head = state.hub.watch_head();
+
+    let result = loop {
+        // Mark the current head as seen before querying, so an insert racing the query still wakes us
+        head.borrow_and_update();
+        let result = db::fetch_events_page(&state.pool, &filter, order, after, backward, limit, include_payload).await;
+        let empty = matches!(&result, Ok(page) if page.events.is_empty());
+        if !empty || wait.is_zero() {
+            break result;
+        }
+        // Woken by the NOTIFY-driven hub; non-matching inserts just loop back to waiting
+        match tokio::time::timeout_at(deadline, head.changed()).await {
+            Ok(Ok(())) => continue,
+            _ => break result,
+        }
+    };
+
+    match result {
+        Ok(page) => {
+            let (next_cursor, prev_cursor) = state.cursors.page_cursors(order, backward, after, &filter, &page);
+            let events = match &projection {
+                Some(p) => PullEvents::Projected(page.events.iter().map(|e| p.apply(e)).collect()),
+                None => PullEvents::Full(page.events),
+            };
+            let resp = PullResp {
+                next_cursor,
+                prev_cursor,
+                has_more: page.has_more,
+                events,
+            };
+            (StatusCode::OK, Json(resp)).into_response()
+        },
         Err(e) => {
             error!("pull error: {}", e);
             (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response()
@@ -149,145 +530,360 @@ async fn pull_handler(State(state): State<AppState>, Query(q): Query<PullQuery>)
     }
 }
 
-// ----- REPLICATION HANDLERS -----
+#[derive(Deserialize)]
+struct FetchEventsReq {
+    event_ids: Vec<Uuid>,
+}
 
-async fn replicate_handler(
-    State(state): State<AppState>, 
-    headers: HeaderMap, 
-    Json(events): Json<Vec<db::EventInput>>
+async fn event_handler(State(state): State<AppState>, Path(event_id): Path<Uuid>) -> impl IntoResponse {
+    match db::fetch_event(&state.pool, event_id).await {
+        Ok(Some(ev)) => (StatusCode::OK, Json(ev)).into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, Json(serde_json::json!({"error": "event not found"}))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+async fn fetch_events_handler(State(state): State<AppState>, Json(req): Json<FetchEventsReq>) -> impl IntoResponse {
+    if req.event_ids.len() > db::MAX_FETCH_IDS {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": format!("at most {} event_ids per request", db::MAX_FETCH_IDS)}))).into_response();
+    }
+    match db::fetch_events_by_ids(&state.pool, &req.event_ids).await {
+        Ok(events) => (StatusCode::OK, Json(serde_json::json!({"events": events}))).into_response(),
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    }
+}
+
+async fn thread_handler(State(state): State<AppState>, Path(event_id): Path<Uuid>, Query(q): Query<ThreadQuery>) -> impl IntoResponse {
+    let depth = q.depth.unwrap_or(10).clamp(1, MAX_THREAD_DEPTH);
+
+    let root = match db::fetch_event(&state.pool, event_id).await {
+        Ok(ev) => ev,
+        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    };
+
+    match db::fetch_thread(&state.pool, event_id, depth).await {
+        // The root may be unknown while replies to it are already here, so it's not a 404
+        Ok(thread) => (StatusCode::OK, Json(serde_json::json!({
+            "event": root,
+            "ancestors": thread.ancestors,
+            "descendants": thread.descendants,
+        }))).into_response(),
+        Err(e) => {
+            error!("thread error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response()
+        }
+    }
+}
+
+async fn stats_handler(
+    State(state): State<AppState>,
+    Query(q): Query<StatsParams>,
+    Query(params): Query<Vec<(String, String)>>,
 ) -> impl IntoResponse {
+    let bad_request = |msg: String| (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": msg}))).into_response();
+
+    let mut group_by = Vec::new();
+    for name in tisane_relay::filter::split_list(q.group_by.as_deref()) {
+        match db::StatsDimension::parse(&name) {
+            Some(dim) if !group_by.contains(&dim) => group_by.push(dim),
+            Some(_) => {},
+            None => return bad_request(format!("unsupported group_by dimension: {}", name)),
+        }
+    }
+
+    // Cap the scanned window so fine buckets can't explode into millions of groups
+    let to = q.to.unwrap_or_else(chrono::Utc::now);
+    let max_span = q.bucket.map(|b| b.width() * MAX_STATS_BUCKETS);
+    let from = match (q.from, max_span) {
+        (Some(from), _) => from,
+        (None, Some(span)) => to - span.min(chrono::Duration::days(DEFAULT_STATS_WINDOW_DAYS)),
+        (None, None) => to - chrono::Duration::days(DEFAULT_STATS_WINDOW_DAYS),
+    };
+    if from >= to {
+        return bad_request("from must be before to".to_string());
+    }
+    if let Some(span) = max_span {
+        if to - from > span {
+            return bad_request(format!("time range too large for bucket (max {} buckets)", MAX_STATS_BUCKETS));
+        }
+    }
+
+    let query = db::StatsQuery {
+        group_by,
+        bucket: q.bucket,
+        from,
+        to,
+        filter: EventFilter::from_query(q.author.as_deref(), q.event_type.as_deref(), q.content_id.as_deref())
+            .with_tag_params(&params),
+        max_groups: q.limit.unwrap_or(100).clamp(1, MAX_STATS_GROUPS),
+    };
+
+    match db::fetch_event_stats(&state.pool, &query).await {
+        Ok(result) => (StatusCode::OK, Json(serde_json::json!({
+            "from": from,
+            "to": to,
+            "rows": result.rows,
+            "truncated": result.truncated,
+        }))).into_response(),
+        Err(e) => {
+            error!("stats error: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response()
+        }
+    }
+}
+
+// ----- REPLICATION HANDLERS -----
+
+/// Checks X-Peer-Token against the peer table and rejects requests carrying our own relay id.
+async fn authenticate_peer(state: &AppState, headers: &HeaderMap) -> Result<db::Peer, axum::response::Response> {
     // 1. Peer Auth
     let token = match headers.get("X-Peer-Token") {
         Some(v) => v.to_str().unwrap_or(""),
-        None => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "missing X-Peer-Token"}))).into_response(),
+        None => return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "missing X-Peer-Token"}))).into_response()),
     };
 
-    let peer = match db::validate_peer_token(&state.pool, token).await {
+    let mut peer = match db::validate_peer_token(&state.pool, token).await {
         Ok(Some(p)) => p,
-        Ok(None) => return (StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "invalid peer token"}))).into_response(),
-        Err(e) => return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+        Ok(None) => return Err((StatusCode::UNAUTHORIZED, Json(serde_json::json!({"error": "invalid peer token"}))).into_response()),
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response()),
     };
+    if peer.is_suspended() {
+        return Err((StatusCode::FORBIDDEN, Json(serde_json::json!({"error": "peer is suspended"}))).into_response());
+    }
 
     // 2. Loop Prevention
     if let Some(relay_id_val) = headers.get("X-Relay-Id") {
         if let Ok(rid) = relay_id_val.to_str() {
             if rid == state.relay_id.to_string() {
                  warn!("Loop detected from peer {}", peer.peer_id);
-                 return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "loop detected: my own relay id"}))).into_response();
+                 return Err((StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "loop detected: my own relay id"}))).into_response());
             }
         }
     }
 
-    if let Some(hop_val) = headers.get("X-Hop") {
-        if let Ok(hop_str) = hop_val.to_str() {
-            if let Ok(hops) = hop_str.parse::<i32>() {
-                if hops > 3 {
-                    return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "max hops exceeded"}))).into_response();
-                }
-            }
+    // 3. Learn which relay is behind this peer record, for path checks when forwarding
+    let rid = headers.get("X-Relay-Id").and_then(|v| v.to_str().ok()).and_then(|v| Uuid::parse_str(v).ok());
+    if let Some(rid) = rid.filter(|rid| peer.remote_relay_id != Some(*rid)) {
+        if let Err(e) = db::update_peer_relay_id(&state.pool, peer.peer_id, rid).await {
+            error!("Failed to record relay id of peer {}: {}", peer.peer_id, e);
         }
+        peer.remote_relay_id = Some(rid);
     }
 
-    // 3. Process Events
-    match validate_and_insert(&state.pool, events).await {
-        Ok(inserted) => (StatusCode::OK, Json(serde_json::json!({"inserted": inserted.len()}))).into_response(),
-        Err((code, msg)) => (code, Json(serde_json::json!({"error": msg}))).into_response(),
+    Ok(peer)
+}
+
+/// Replicated events from a peer, sorted out by `inbound_events`
+struct InboundEvents {
+    /// Events to store
+    events: Vec<db::ReplicatedEvent>,
+    /// Loops and events over the hop limit
+    skipped: Vec<Uuid>,
+    /// Events outside the peer's inbound policy
+    rejected: Vec<Uuid>,
+}
+
+/// Drops replicated events this relay must not store: ones that already passed through it
+/// (a loop), that exceed the hop limit, or whose type or author the peer's inbound policy
+/// doesn't allow. Events from relays that predate provenance tracking come with none; they
+/// are attributed to the sending peer with `legacy_hops`.
+fn inbound_events(state: &AppState, peer: &db::Peer, events: Vec<db::ReplicatedEvent>, legacy_hops: i32) -> InboundEvents {
+    let (admitted, refused): (Vec<_>, Vec<_>) = events.into_iter().partition(|ev| peer.inbound_policy.admits(&ev.event));
+    let (accepted, skipped): (Vec<_>, Vec<_>) = admitted
+        .into_iter()
+        .map(|mut ev| {
+            if ev.provenance.hops == 0 {
+                ev.provenance = db::Provenance {
+                    origin_relay: peer.remote_relay_id,
+                    hops: legacy_hops.max(1),
+                    path: peer.remote_relay_id.into_iter().collect(),
+                };
+            }
+            ev
+        })
+        .partition(|ev| {
+            ev.provenance.hops <= state.max_hops
+                && ev.provenance.path.len() <= state.max_hops as usize
+                && !ev.provenance.has_visited(state.relay_id)
+        });
+    InboundEvents {
+        events: accepted,
+        skipped: skipped.iter().map(|ev| ev.event.event_id).collect(),
+        rejected: refused.iter().map(|ev| ev.event.event_id).collect(),
     }
 }
 
-async fn peers_handler(State(state): State<AppState>) -> impl IntoResponse {
-    match db::fetch_healthy_peers(&state.pool).await {
-        Ok(peers) => (StatusCode::OK, Json(peers)).into_response(),
-        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+#[derive(Serialize, Deserialize)]
+struct ReplicateResp {
+    inserted: usize,
+    /// Events dropped as loops or for exceeding the hop limit
+    #[serde(default)]
+    skipped: usize,
+    /// Events refused: invalid, or not allowed by our inbound policy for the sender
+    #[serde(default)]
+    rejected: usize,
+    /// Lets the sender learn which relay it is talking to
+    #[serde(default)]
+    relay_id: Option<Uuid>,
+    /// One outcome per event, matched by `event_id`. Relays that predate per-event outcomes
+    /// leave it out and accept or refuse a batch as a whole.
+    #[serde(default)]
+    outcomes: Option<Vec<ReplicateOutcome>>,
+}
+
+async fn replicate_handler(
+    State(state): State<AppState>, 
+    headers: HeaderMap, 
+    Json(mut events): Json<Vec<db::ReplicatedEvent>>
+) -> impl IntoResponse {
+    let peer = match authenticate_peer(&state, &headers).await {
+        Ok(peer) => peer,
+        Err(resp) => return resp,
+    };
+
+    let admitted = match peer_policy::check_batch(&state, &peer, events.len()).await {
+        Ok(admitted) => admitted,
+        Err(resp) => return resp,
+    };
+    // What doesn't fit the peer's batch limit or rate budget is handed back to retry
+    let deferred = events.split_off(admitted);
+
+    // Only relays without per-event provenance still send X-Hop
+    let legacy_hops = headers.get("X-Hop").and_then(|v| v.to_str().ok()).and_then(|v| v.parse().ok()).unwrap_or(1);
+    let InboundEvents { events, skipped, rejected } = inbound_events(&state, &peer, events, legacy_hops);
+    if !rejected.is_empty() {
+        peer_policy::record_violation(&state, &peer, &format!("{} events outside the inbound policy", rejected.len())).await;
+    }
+
+    // One bad event must not hold back the rest: each gets its own outcome
+    let received = events.len() as i64;
+    let stored = match ingest::ingest_each_replicated(&state.pool, &state.ephemeral, events).await {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            error!("replicate insert error: {}", e);
+            return (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response();
+        },
+    };
+    if let Err(e) = db::record_peer_traffic(&state.pool, peer.peer_id, 0, received).await {
+        error!("Failed to record traffic from peer {}: {}", peer.peer_id, e);
     }
+
+    let outcome = |event_id, status, error: Option<&str>| ReplicateOutcome { event_id, status, error: error.map(str::to_string) };
+    let mut outcomes: Vec<ReplicateOutcome> = skipped.iter().map(|id| outcome(*id, ReplicateStatus::Skipped, None)).collect();
+    outcomes.extend(rejected.iter().map(|id| outcome(*id, ReplicateStatus::Rejected, Some("not allowed by the inbound policy"))));
+    outcomes.extend(stored.into_iter().map(ReplicateOutcome::from));
+    outcomes.extend(deferred.iter().map(|ev| outcome(ev.event.event_id, ReplicateStatus::Retry, Some("over the inbound batch or rate limit"))));
+
+    let count = |status| outcomes.iter().filter(|o| o.status == status).count();
+    let resp = ReplicateResp {
+        inserted: count(ReplicateStatus::Stored),
+        skipped: skipped.len(),
+        rejected: count(ReplicateStatus::Rejected),
+        relay_id: Some(state.relay_id),
+        outcomes: Some(outcomes),
+    };
+    (StatusCode::OK, Json(resp)).into_response()
 }
 
-// ----- BACKGROUND WORKER -----
+/// Largest batch budget a peer may be given; `/relay/replicate` accepts bodies up to this size
+const MAX_BATCH_BYTES: i64 = 2 * 1024 * 1024;
 
-async fn replication_worker(state: AppState) {
-    info!("Helper: Replication worker started with Relay ID: {}", state.relay_id);
-    let client = reqwest::Client::new();
+#[derive(Deserialize)]
+struct ReplicatePullQuery {
+    /// Replication log position to continue after (the `next_cursor` of the previous response)
+    after: Option<i64>,
+    limit: Option<i64>,
+}
 
-    loop {
-        tokio::time::sleep(Duration::from_secs(5)).await;
+#[derive(Serialize, Deserialize)]
+struct ReplicatePullResp {
+    events: Vec<db::ReplicatedEvent>,
+    next_cursor: i64,
+    has_more: bool,
+    #[serde(default)]
+    relay_id: Option<Uuid>,
+}
 
-        let peers = match db::fetch_healthy_peers(&state.pool).await {
-            Ok(p) => p,
-            Err(e) => {
-                error!("Worker failed to fetch peers: {}", e);
-                continue;
-            }
-        };
+/// Lets peers that we can't push to (e.g. behind NAT) fetch our events.
+async fn replicate_pull_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<ReplicatePullQuery>,
+) -> impl IntoResponse {
+    let peer = match authenticate_peer(&state, &headers).await {
+        Ok(peer) => peer,
+        Err(resp) => return resp,
+    };
 
-        for peer in peers {
-            // Fetch batch to send
-            let events_to_send = match db::fetch_replication_batch(&state.pool, peer.last_cursor_time, peer.last_cursor_id, 50).await {
-                Ok(evs) => evs,
-                Err(e) => {
-                    error!("Failed to fetch replication batch for {}: {}", peer.peer_id, e);
-                    continue;
-                }
+    let after = q.after.unwrap_or(0);
+    let limit = q.limit.unwrap_or(100).clamp(1, MAX_PULL_LIMIT).min(peer.batch_size.max(1) as i64);
+    // Cursors are repl_seq positions, which only cover events that have been sequenced
+    let log = match db::sequence_replication_log(&state.pool).await {
+        Ok(_) => db::fetch_replication_batch(&state.pool, None, &peer.outbound_filter, after, limit).await,
+        Err(e) => Err(e),
+    };
+    match log {
+        Ok(log) => {
+            let batch = log.into_outbound(state.relay_id, state.max_hops, &peer, after);
+            let resp = ReplicatePullResp {
+                has_more: batch.has_more(limit),
+                next_cursor: batch.cursor,
+                events: batch.events,
+                relay_id: Some(state.relay_id),
             };
+            (StatusCode::OK, Json(resp)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+    }
+}
 
-            if events_to_send.is_empty() {
-                continue;
-            }
+/// What `/relay/peers` tells anyone about a peer; settings, cursors and errors stay with
+/// the admin endpoints
+#[derive(Serialize)]
+struct PeerListing {
+    peer_id: Uuid,
+    url: String,
+    health: String,
+}
 
-            // Convert DB events back to EventInput for transport (simplification for MVP)
-            // Ideally we transfer specific replication DTOs
-            let payload: Vec<db::EventInput> = events_to_send.iter().map(|e| db::EventInput {
-                event_id: e.event_id,
-                author_pubkey: e.author_pubkey.clone(),
-                signature: e.signature.clone(),
-                payload_hash: e.payload_hash.clone(),
-                device_id: e.device_id.clone(),
-                author_id: e.author_id.clone(),
-                content_id: e.content_id.clone(),
-                event_type: e.event_type.clone(),
-                payload_json: e.payload_json.clone(),
-                occurred_at: e.occurred_at,
-                lamport: e.lamport,
-            }).collect();
-
-            // Send via POST
-            let res = client.post(format!("{}/relay/replicate", peer.url))
-                .header("X-Peer-Token", &peer.shared_secret)
-                .header("X-Relay-Id", state.relay_id.to_string())
-                .header("X-Hop", "1")
-                .json(&payload)
-                .send()
-                .await;
-
-            match res {
-                Ok(resp) => {
-                    if resp.status().is_success() {
-                        let last = events_to_send.last().unwrap();
-                        // Update cursor
-                        if let Err(e) = db::update_peer_cursor(
-                            &state.pool, 
-                            peer.peer_id, 
-                            last.occurred_at.unwrap_or(chrono::Utc::now()), 
-                            last.event_id
-                        ).await {
-                            error!("Failed to update cursor for peer {}: {}", peer.peer_id, e);
-                        } else {
-                            info!("Replicated {} events to peer {}", events_to_send.len(), peer.peer_id);
-                        }
-                    } else {
-                        warn!("Replication failed for peer {}: Status {}", peer.peer_id, resp.status());
-                    }
-                },
-                Err(e) => {
-                    warn!("Replication request failed for peer {}: {}", peer.peer_id, e);
-                }
-            }
-        }
+async fn peers_handler(State(state): State<AppState>) -> impl IntoResponse {
+    match db::fetch_healthy_peers(&state.pool).await {
+        Ok(peers) => {
+            let listing: Vec<PeerListing> = peers
+                .into_iter()
+                .map(|p| PeerListing { peer_id: p.peer_id, url: p.url, health: p.health })
+                .collect();
+            (StatusCode::OK, Json(listing)).into_response()
+        },
+        Err(e) => (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
     }
 }
 
-async fn serve_command(port: u16, database_url: String, relay_id_opt: Option<Uuid>) -> anyhow::Result<()> {
+async fn serve_command(args: ServeArgs) -> anyhow::Result<()> {
+    let ServeArgs { port, database_url, relay_id, cursor_secret, signing_key, ephemeral_types, ephemeral_rate, grpc_port, max_hops, replication_concurrency, peer_timeout_secs, reconcile_interval_secs, peer_violation_limit, admin_token } = args;
+
     // Use provided ID or generate random one
-    let relay_id = relay_id_opt.unwrap_or_else(Uuid::new_v4);
+    let relay_id = relay_id.unwrap_or_else(Uuid::new_v4);
+
+    let signing_key = match signing_key {
+        Some(seed_hex) => {
+            let seed: [u8; 32] = hex::decode(seed_hex.trim())?
+                .try_into()
+                .map_err(|_| anyhow::anyhow!("RELAY_SIGNING_KEY must be 32 bytes of hex"))?;
+            let key = SigningKey::from_bytes(&seed);
+            info!("relay signing key: {}", hex::encode(key.verifying_key().to_bytes()));
+            Some(key)
+        },
+        None => None,
+    };
+
+    let cursors = match cursor_secret {
+        Some(secret) => CursorCodec::from_secret(&secret),
+        None => {
+            warn!("CURSOR_SECRET not set; pull cursors will be invalidated on restart");
+            CursorCodec::from_key(rand::random())
+        }
+    };
 
     info!("connecting to database: {}", database_url);
     let pool = PgPool::connect(&database_url).await?;
@@ -297,21 +893,67 @@ async fn serve_command(port: u16, database_url: String, relay_id_opt: Option<Uui
 
     let state = AppState { 
         pool,
-        relay_id
+        relay_id,
+        cursors,
+        hub: EventHub::new(),
+        signing_key,
+        ephemeral: Arc::new(Ephemeral::new(ephemeral_types, ephemeral_rate)),
+        max_hops,
+        peer_rates: Arc::new(peer_policy::PeerRateLimits::default()),
+        violation_limit: peer_violation_limit,
+        admin_token: admin_token.filter(|t| !t.is_empty()),
     };
 
+    // Feed live subscribers and long-polls from insert notifications
+    tokio::spawn(hub::run_listener(state.pool.clone(), state.hub.clone()));
+
     // Spawn replication worker
     let worker_state = state.clone();
+    let worker_config = replication::WorkerConfig {
+        concurrency: replication_concurrency.max(1),
+        request_timeout: Duration::from_secs(peer_timeout_secs.max(1)),
+    };
     tokio::spawn(async move {
-        replication_worker(worker_state).await;
+        replication::replication_worker(worker_state, worker_config).await;
     });
 
+    if reconcile_interval_secs > 0 {
+        let client = reqwest::Client::builder().timeout(Duration::from_secs(peer_timeout_secs.max(1))).build()?;
+        tokio::spawn(reconcile::reconcile_worker(state.clone(), client, Duration::from_secs(reconcile_interval_secs)));
+    }
+
+    tokio::spawn(webhooks::webhook_worker(state.clone()));
+
+    if let Some(grpc_port) = grpc_port {
+        let grpc_addr = SocketAddr::from(([0, 0, 0, 0], grpc_port));
+        info!("gRPC listening on {}", grpc_addr);
+        tokio::spawn(grpc::serve(state.clone(), grpc_addr));
+    }
+
     let app = Router::new()
         .route("/health", get(health))
         .route("/relay/push", post(push_handler))
+        .route("/relay/push/ndjson", post(push_ndjson_handler))
         .route("/relay/pull", get(pull_handler))
-        .route("/relay/replicate", post(replicate_handler))
+        .route("/relay/stats/events", get(stats_handler))
+        .route("/relay/events/fetch", post(fetch_events_handler))
+        .route("/relay/events/:id", get(event_handler))
+        .route("/relay/events/:id/thread", get(thread_handler))
+        .route("/relay/replicate", post(replicate_handler).layer(DefaultBodyLimit::max(MAX_BATCH_BYTES as usize)))
+        .route("/relay/replicate/pull", get(replicate_pull_handler))
+        .route("/relay/reconcile", post(reconcile::reconcile_handler))
+        .route("/relay/reconcile/events", post(reconcile::reconcile_events_handler))
         .route("/relay/peers", get(peers_handler))
+        .route("/admin/peers", get(admin::peers_status_handler))
+        .route("/admin/peers/:peer_id", get(admin::peer_status_handler))
+        .route("/relay/ws", get(ws::ws_handler))
+        .route("/relay/stream", get(sse::stream_handler))
+        .route("/nostr", get(nostr_ws::nostr_handler))
+        .route("/relay/consumers/:name", put(consumers::put_consumer_handler)
+            .get(consumers::get_consumer_handler)
+            .delete(consumers::delete_consumer_handler))
+        .route("/relay/consumers/:name/fetch", post(consumers::fetch_handler))
+        .route("/relay/consumers/:name/ack", post(consumers::ack_handler))
         .with_state(state);
 
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
@@ -325,20 +967,163 @@ async fn serve_command(port: u16, database_url: String, relay_id_opt: Option<Uui
     Ok(())
 }
 
-async fn add_peer_command(url: String, secret: String, database_url: String) -> anyhow::Result<()> {
+async fn add_peer_command(url: String, secret: String, settings: db::PeerSettings, database_url: String) -> anyhow::Result<()> {
     let pool = PgPool::connect(&database_url).await?;
-    let id = db::add_peer(&pool, url.clone(), secret).await?;
-    println!("Added peer {} with ID {}", url, id);
+    let id = db::add_peer(&pool, url.clone(), secret, &settings).await?;
+    println!("Added peer {} ({}) with ID {}", url, settings.direction, id);
+    if !settings.outbound_filter.is_empty() {
+        println!("Outbound filter: {}", serde_json::to_string(&settings.outbound_filter)?);
+    }
+    if !settings.inbound_policy.is_empty() {
+        println!("Inbound policy: {}", serde_json::to_string(&settings.inbound_policy)?);
+    }
+    Ok(())
+}
+
+/// Changes requested through `update-peer`
+struct PeerUpdate {
+    direction: Option<String>,
+    batch_size: Option<i32>,
+    max_batch_bytes: Option<i32>,
+    clear_filter: bool,
+    filter: PeerFilterArgs,
+    clear_policy: bool,
+    policy: PeerPolicyArgs,
+    unsuspend: bool,
+}
+
+async fn update_peer_command(peer_id: Uuid, changes: PeerUpdate, database_url: String) -> anyhow::Result<()> {
+    let pool = PgPool::connect(&database_url).await?;
+    let peer = db::fetch_peer(&pool, peer_id).await?.ok_or_else(|| anyhow::anyhow!("peer {} not found", peer_id))?;
+
+    let mut settings = peer.settings();
+    if let Some(direction) = changes.direction {
+        settings.direction = direction;
+    }
+    if let Some(batch_size) = changes.batch_size {
+        settings.batch_size = batch_size;
+    }
+    if let Some(max_batch_bytes) = changes.max_batch_bytes {
+        settings.max_batch_bytes = max_batch_bytes;
+    }
+    if changes.clear_filter {
+        settings.outbound_filter = OutboundFilter::default();
+    }
+    changes.filter.apply(&mut settings.outbound_filter)?;
+    if changes.clear_policy {
+        settings.inbound_policy = InboundPolicy::default();
+    }
+    changes.policy.apply(&mut settings.inbound_policy)?;
+
+    let mut peer = db::update_peer_settings(&pool, peer_id, &settings).await?.ok_or_else(|| anyhow::anyhow!("peer {} not found", peer_id))?;
+    if changes.unsuspend {
+        peer = db::unsuspend_peer(&pool, peer_id).await?.ok_or_else(|| anyhow::anyhow!("peer {} not found", peer_id))?;
+    }
+    println!("Updated peer {} ({}, batches of {} events / {} bytes)", peer.peer_id, peer.direction, peer.batch_size, peer.max_batch_bytes);
+    if peer.outbound_filter.is_empty() {
+        println!("Outbound filter: none");
+    } else {
+        println!("Outbound filter: {}", serde_json::to_string(&peer.outbound_filter)?);
+    }
+    if peer.inbound_policy.is_empty() {
+        println!("Inbound policy: none");
+    } else {
+        println!("Inbound policy: {}", serde_json::to_string(&peer.inbound_policy)?);
+    }
+    if let Some(at) = peer.suspended_at {
+        println!("Suspended since {} ({} violations, last: {})", at.format("%Y-%m-%d %H:%M:%S"), peer.policy_violations, peer.last_violation.as_deref().unwrap_or("-"));
+    }
     Ok(())
 }
 
 async fn list_peers_command(database_url: String) -> anyhow::Result<()> {
     let pool = PgPool::connect(&database_url).await?;
     let peers = db::fetch_all_peers(&pool).await?;
-    println!("{:<36} | {:<30} | {:<10}", "ID", "URL", "Health");
-    println!("{}", "-".repeat(80));
+    let fmt_time = |t: Option<chrono::DateTime<chrono::Utc>>| t.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "-".to_string());
+    println!("{:<36} | {:<30} | {:<9} | {:<9} | {:<8} | {:<19} | {:<19} | {:<19} | {}",
+        "ID", "URL", "Direction", "Health", "Failures", "Last success", "Last error at", "Next attempt", "Last error");
+    println!("{}", "-".repeat(191));
     for p in peers {
-        println!("{} | {:<30} | {}", p.peer_id, p.url, p.health);
+        println!("{} | {:<30} | {:<9} | {:<9} | {:<8} | {:<19} | {:<19} | {:<19} | {}",
+            p.peer_id, p.url, p.direction, if p.is_suspended() { "suspended" } else { p.health.as_str() }, p.consecutive_failures,
+            fmt_time(p.last_success_at), fmt_time(p.last_error_at), fmt_time(p.next_attempt_at),
+            p.last_error.as_deref().unwrap_or("-"));
+    }
+    Ok(())
+}
+
+async fn peer_status_command(peer_id: Option<Uuid>, database_url: String) -> anyhow::Result<()> {
+    let pool = PgPool::connect(&database_url).await?;
+    let peers = match peer_id {
+        Some(id) => vec![db::fetch_peer(&pool, id).await?.ok_or_else(|| anyhow::anyhow!("peer {} not found", id))?],
+        None => db::fetch_all_peers(&pool).await?,
+    };
+    let fmt_time = |t: Option<chrono::DateTime<chrono::Utc>>| t.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_else(|| "-".to_string());
+    let per_min = |events: i64, minutes: f64| format!("{:.1}/min", events as f64 / minutes);
+
+    for peer in peers {
+        let st = db::fetch_peer_status(&pool, &peer).await?;
+        println!("Peer {} ({})", st.peer_id, st.url);
+        println!("  Direction:     {}", st.direction);
+        let health = match st.suspended_at {
+            Some(at) => format!("suspended since {} ({} violations, last: {})", fmt_time(Some(at)), peer.policy_violations, peer.last_violation.as_deref().unwrap_or("-")),
+            None => format!("{} ({} consecutive failures)", st.health, st.consecutive_failures),
+        };
+        println!("  Health:        {}", health);
+        match st.pending_events {
+            Some(pending) => {
+                let capped = if pending >= db::PENDING_COUNT_CAP { "+" } else { "" };
+                let lag = st.lag_secs.map(|s| format!(", oldest stored {}s ago", s)).unwrap_or_default();
+                println!("  Push cursor:   {} of {} ({}{} pending{})", st.push_cursor, st.log_head, pending, capped, lag);
+            },
+            None => println!("  Push cursor:   {} (not pushing)", st.push_cursor),
+        }
+        println!("  Pull cursor:   {}", st.pull_cursor);
+        println!("  Last success:  {}", fmt_time(st.last_success_at));
+        println!("  Last error:    {} {}", fmt_time(st.last_error_at), st.last_error.as_deref().unwrap_or(""));
+        println!("  Sent:          {} (5m), {} (1h)", per_min(st.sent_last_5m, 5.0), per_min(st.sent_last_hour, 60.0));
+        println!("  Received:      {} (5m), {} (1h)", per_min(st.received_last_5m, 5.0), per_min(st.received_last_hour, 60.0));
+        let backoff = if st.backing_off { format!("until {}", fmt_time(st.next_attempt_at)) } else { "none".to_string() };
+        println!("  Backoff:       {}", backoff);
+        println!("  Quarantined:   {}", st.quarantined);
+    }
+    Ok(())
+}
+
+async fn quarantine_command(peer_id: Option<Uuid>, limit: i64, database_url: String) -> anyhow::Result<()> {
+    let pool = PgPool::connect(&database_url).await?;
+    let quarantined = db::fetch_quarantine(&pool, peer_id, limit).await?;
+    println!("{:<36} | {:<36} | {:<8} | {:<19} | Error", "Peer", "Event", "Dir", "Quarantined at");
+    println!("{}", "-".repeat(130));
+    for q in quarantined {
+        println!("{} | {} | {:<8} | {:<19} | {}", q.peer_id, q.event_id, q.direction, q.quarantined_at.format("%Y-%m-%d %H:%M:%S"), q.error);
+    }
+    Ok(())
+}
+
+/// How many IDs of each kind `reconcile` prints
+const RECONCILE_REPORT_IDS: usize = 20;
+
+async fn reconcile_command(peer_id: Uuid, relay_id: Option<Uuid>, database_url: String) -> anyhow::Result<()> {
+    let pool = PgPool::connect(&database_url).await?;
+    let peer = db::fetch_peer(&pool, peer_id).await?.ok_or_else(|| anyhow::anyhow!("peer {} not found", peer_id))?;
+    let client = reqwest::Client::builder().timeout(Duration::from_secs(60)).build()?;
+
+    let divergence = reconcile::diff(&pool, &client, &peer, relay_id).await.map_err(|e| anyhow::anyhow!(e))?;
+    println!("Compared with {} in {} rounds", peer.url, divergence.rounds);
+    println!("Missing here:       {}", divergence.missing_local.len());
+    for id in divergence.missing_local.iter().take(RECONCILE_REPORT_IDS) {
+        println!("  {}", id);
+    }
+    println!("Missing on peer:    {}", divergence.missing_remote.len());
+    for id in divergence.missing_remote.iter().take(RECONCILE_REPORT_IDS) {
+        println!("  {}", id);
+    }
+    if divergence.unresolved > 0 {
+        println!("Unresolved ranges:  {} (gave up after {} rounds)", divergence.unresolved, divergence.rounds);
+    }
+    if divergence.is_empty() {
+        println!("In sync");
     }
     Ok(())
 }
@@ -353,6 +1138,79 @@ async fn remove_peer_command(peer_id: Uuid, database_url: String) -> anyhow::Res
     Ok(())
 }
 
+/// Builds an event filter from CLI flags; tags are given as repeated `key=value`.
+fn filter_from_flags(event_types: Option<String>, authors: Option<String>, tags: Vec<String>) -> anyhow::Result<EventFilter> {
+    let mut filter = EventFilter::from_query(authors.as_deref(), event_types.as_deref(), None);
+    for tag in tags {
+        let (key, value) = tag
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("tag filter must be key=value, got {}", tag))?;
+        filter.tags.entry(key.to_string()).or_default().push(value.to_string());
+    }
+    Ok(filter)
+}
+
+async fn add_webhook_command(
+    url: String,
+    signing: String,
+    secret: Option<String>,
+    event_types: Option<String>,
+    authors: Option<String>,
+    tags: Vec<String>,
+    database_url: String,
+) -> anyhow::Result<()> {
+    match signing.as_str() {
+        "hmac" if secret.is_none() => anyhow::bail!("--secret is required for hmac signing"),
+        "hmac" | "ed25519" => {},
+        other => anyhow::bail!("unknown signing scheme {} (expected hmac or ed25519)", other),
+    }
+    let filter = filter_from_flags(event_types, authors, tags)?;
+
+    let pool = PgPool::connect(&database_url).await?;
+    let id = db::add_webhook(&pool, url.clone(), signing, secret, &filter).await?;
+    println!("Added webhook {} with ID {}", url, id);
+    Ok(())
+}
+
+async fn list_webhooks_command(database_url: String) -> anyhow::Result<()> {
+    let pool = PgPool::connect(&database_url).await?;
+    let hooks = db::fetch_all_webhooks(&pool).await?;
+    println!("{:<36} | {:<30} | {:<7} | {:<10} | Filter", "ID", "URL", "Signing", "Cursor");
+    println!("{}", "-".repeat(110));
+    for h in hooks {
+        println!("{} | {:<30} | {:<7} | {:<10} | {}", h.webhook_id, h.url, h.signing, h.last_seq, serde_json::to_string(&h.filter.0)?);
+    }
+    Ok(())
+}
+
+async fn remove_webhook_command(webhook_id: Uuid, database_url: String) -> anyhow::Result<()> {
+    let pool = PgPool::connect(&database_url).await?;
+    if db::remove_webhook(&pool, webhook_id).await? {
+        println!("Removed webhook {}", webhook_id);
+    } else {
+        println!("Webhook {} not found", webhook_id);
+    }
+    Ok(())
+}
+
+async fn dead_letters_command(webhook_id: Option<Uuid>, limit: i64, database_url: String) -> anyhow::Result<()> {
+    let pool = PgPool::connect(&database_url).await?;
+    let dead = db::fetch_dead_deliveries(&pool, webhook_id, limit).await?;
+    println!("{:<10} | {:<36} | {:<36} | {:<8} | Last error", "Delivery", "Webhook", "Event", "Attempts");
+    println!("{}", "-".repeat(120));
+    for d in dead {
+        println!("{:<10} | {} | {} | {:<8} | {}", d.delivery_id, d.webhook_id, d.event_id, d.attempts, d.last_error.unwrap_or_default());
+    }
+    Ok(())
+}
+
+async fn redeliver_command(delivery_id: Option<i64>, webhook_id: Option<Uuid>, database_url: String) -> anyhow::Result<()> {
+    let pool = PgPool::connect(&database_url).await?;
+    let requeued = db::redeliver(&pool, delivery_id, webhook_id).await?;
+    println!("Requeued {} dead deliveries", requeued);
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::init();
@@ -360,17 +1218,48 @@ async fn main() -> anyhow::Result<()> {
     let args = Args::parse();
     
     match args.command {
-        Commands::Serve { port, database_url, relay_id } => {
-            serve_command(port, database_url, relay_id).await?;
+        Commands::Serve(serve_args) => {
+            serve_command(serve_args).await?;
+        },
+        Commands::AddPeer { url, secret, direction, batch_size, max_batch_bytes, filter, policy, database_url } => {
+            let mut settings = db::PeerSettings { direction, batch_size, max_batch_bytes, ..Default::default() };
+            filter.apply(&mut settings.outbound_filter)?;
+            policy.apply(&mut settings.inbound_policy)?;
+            add_peer_command(url, secret, settings, database_url).await?;
         },
-        Commands::AddPeer { url, secret, database_url } => {
-            add_peer_command(url, secret, database_url).await?;
+        Commands::UpdatePeer { peer_id, direction, batch_size, max_batch_bytes, clear_filter, filter, clear_policy, policy, unsuspend, database_url } => {
+            let changes = PeerUpdate { direction, batch_size, max_batch_bytes, clear_filter, filter, clear_policy, policy, unsuspend };
+            update_peer_command(peer_id, changes, database_url).await?;
         },
         Commands::ListPeers { database_url } => {
             list_peers_command(database_url).await?;
         },
+        Commands::PeerStatus { peer_id, database_url } => {
+            peer_status_command(peer_id, database_url).await?;
+        },
+        Commands::Quarantine { peer_id, limit, database_url } => {
+            quarantine_command(peer_id, limit, database_url).await?;
+        },
+        Commands::Reconcile { peer_id, relay_id, database_url } => {
+            reconcile_command(peer_id, relay_id, database_url).await?;
+        },
         Commands::RemovePeer { peer_id, database_url } => {
             remove_peer_command(peer_id, database_url).await?;
+        },
+        Commands::AddWebhook { url, signing, secret, event_types, authors, tags, database_url } => {
+            add_webhook_command(url, signing, secret, event_types, authors, tags, database_url).await?;
+        },
+        Commands::ListWebhooks { database_url } => {
+            list_webhooks_command(database_url).await?;
+        },
+        Commands::RemoveWebhook { webhook_id, database_url } => {
+            remove_webhook_command(webhook_id, database_url).await?;
+        },
+        Commands::DeadLetters { webhook_id, limit, database_url } => {
+            dead_letters_command(webhook_id, limit, database_url).await?;
+        },
+        Commands::Redeliver { delivery_id, webhook_id, database_url } => {
+            redeliver_command(delivery_id, webhook_id, database_url).await?;
         }
     }
 
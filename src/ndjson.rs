@@ -0,0 +1,185 @@
+// ----- NDJSON BULK INGEST -----
+//
+// POST /relay/push/ndjson takes one event per line and answers with one result per line,
+// streamed as chunks are processed:
+//   {"line":1,"event_id":"...","status":"accepted","server_seq":42}
+//   {"line":2,"status":"rejected","error":"..."}
+//   ...
+//   {"summary":{"lines":2,"accepted":1,"duplicate":0,"rejected":1}}
+// A fatal problem (unreadable body, oversized line, storage error) ends the log with
+// {"error":"..."}; lines reported before it are committed.
+
+use std::convert::Infallible;
+use std::fmt::Display;
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    http::header,
+    response::{IntoResponse, Response},
+};
+use futures::{stream, Stream, StreamExt};
+use serde_json::{json, Value};
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::db;
+use crate::ephemeral::Ephemeral;
+use crate::ingest::{self, OutcomeStatus};
+
+/// Events validated and inserted per round
+pub const CHUNK_SIZE: usize = 500;
+pub const MAX_LINE_BYTES: usize = 1024 * 1024;
+/// Result lines buffered ahead of the client; a slow reader slows down ingest
+const RESULT_BUFFER: usize = 1024;
+
+#[derive(Default)]
+struct Totals {
+    lines: usize,
+    accepted: usize,
+    duplicate: usize,
+    rejected: usize,
+    ephemeral: usize,
+}
+
+/// The streamed response for a `/relay/push/ndjson` request; ingest runs in its own task.
+pub fn push_response(pool: PgPool, ephemeral: Arc<Ephemeral>, body: Body) -> Response {
+    let (tx, rx) = mpsc::channel::<Value>(RESULT_BUFFER);
+    tokio::spawn(async move { ingest_stream(&pool, &ephemeral, body.into_data_stream(), tx).await });
+
+    let results = stream::unfold(rx, |mut rx| async move {
+        let value = rx.recv().await?;
+        let mut line = value.to_string();
+        line.push('\n');
+        Some((Ok::<_, Infallible>(Bytes::from(line)), rx))
+    });
+    ([(header::CONTENT_TYPE, "application/x-ndjson")], Body::from_stream(results)).into_response()
+}
+
+/// Reads events line by line from `data` and sends one result per line to `out`, then the
+/// summary, or an error that ends the log.
+pub async fn ingest_stream<S, E>(pool: &PgPool, ephemeral: &Ephemeral, mut data: S, out: mpsc::Sender<Value>)
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Display,
+{
+    let mut buf: Vec<u8> = Vec::new();
+    let mut pending: Vec<(usize, Vec<u8>)> = Vec::new();
+    let mut line_no = 0;
+    let mut totals = Totals::default();
+
+    loop {
+        let done = match data.next().await {
+            Some(Ok(bytes)) => {
+                buf.extend_from_slice(&bytes);
+                false
+            },
+            Some(Err(e)) => {
+                let _ = out.send(json!({"error": format!("failed to read body: {}", e)})).await;
+                return;
+            },
+            None => true,
+        };
+
+        let mut start = 0;
+        while let Some(pos) = buf[start..].iter().position(|b| *b == b'\n') {
+            line_no += 1;
+            pending.push((line_no, buf[start..start + pos].to_vec()));
+            start += pos + 1;
+        }
+        buf.drain(..start);
+        let overflow = buf.len() > MAX_LINE_BYTES;
+        if done && !buf.is_empty() {
+            line_no += 1;
+            pending.push((line_no, std::mem::take(&mut buf)));
+        }
+
+        // Everything before an oversized line is still processed and reported
+        while pending.len() >= CHUNK_SIZE || ((done || overflow) && !pending.is_empty()) {
+            let n = pending.len().min(CHUNK_SIZE);
+            let chunk: Vec<_> = pending.drain(..n).collect();
+            if process_chunk(pool, ephemeral, chunk, &out, &mut totals).await.is_err() {
+                return;
+            }
+        }
+
+        if overflow {
+            let _ = out.send(json!({"error": format!("line {} exceeds {} bytes", line_no + 1, MAX_LINE_BYTES)})).await;
+            return;
+        }
+        if done {
+            break;
+        }
+    }
+
+    let _ = out
+        .send(json!({"summary": {
+            "lines": totals.lines,
+            "accepted": totals.accepted,
+            "duplicate": totals.duplicate,
+            "rejected": totals.rejected,
+            "ephemeral": totals.ephemeral,
+        }}))
+        .await;
+}
+
+/// Validates and inserts one chunk, then reports its lines in order.
+/// Errs when the log has ended (storage error or client gone).
+async fn process_chunk(
+    pool: &PgPool,
+    ephemeral: &Ephemeral,
+    chunk: Vec<(usize, Vec<u8>)>,
+    out: &mpsc::Sender<Value>,
+    totals: &mut Totals,
+) -> Result<(), ()> {
+    let mut results: Vec<(usize, Value)> = Vec::with_capacity(chunk.len());
+    let mut lines = Vec::new();
+    let mut events = Vec::new();
+
+    for (line, raw) in chunk {
+        if raw.iter().all(|b| b.is_ascii_whitespace()) {
+            continue;
+        }
+        totals.lines += 1;
+        match serde_json::from_slice::<db::EventInput>(&raw) {
+            Ok(ev) => {
+                lines.push(line);
+                events.push(ev);
+            },
+            Err(e) => {
+                totals.rejected += 1;
+                results.push((line, json!({"line": line, "status": "rejected", "error": format!("invalid JSON: {}", e)})));
+            },
+        }
+    }
+
+    let outcomes = match ingest::ingest_each(pool, ephemeral, events).await {
+        Ok(o) => o,
+        Err(e) => {
+            error!("ndjson insert error: {}", e);
+            let _ = out.send(json!({"error": e.to_string()})).await;
+            return Err(());
+        }
+    };
+
+    for (line, outcome) in lines.into_iter().zip(outcomes) {
+        match outcome.status {
+            OutcomeStatus::Accepted => totals.accepted += 1,
+            OutcomeStatus::Duplicate => totals.duplicate += 1,
+            OutcomeStatus::Rejected => totals.rejected += 1,
+            OutcomeStatus::Ephemeral => totals.ephemeral += 1,
+        }
+        let mut value = serde_json::to_value(&outcome).unwrap_or_default();
+        if let Value::Object(map) = &mut value {
+            map.insert("line".to_string(), json!(line));
+        }
+        results.push((line, value));
+    }
+
+    results.sort_by_key(|(line, _)| *line);
+    for (_, value) in results {
+        out.send(value).await.map_err(|_| ())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+
+use chrono::{TimeZone, Utc};
+use k256::schnorr::{Signature, VerifyingKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::db::{Event, EventInput, EventRef, SIG_SCHEME_NOSTR};
+use crate::filter::EventFilter;
+use crate::ingest::MAX_REFS_PER_EVENT;
+
+/// Nostr events are stored with `event_type = "nostr:<kind>"`
+pub const EVENT_TYPE_PREFIX: &str = "nostr:";
+/// Ref kind used for `["e", <id>]` tags
+pub const EVENT_TAG_REF_KIND: &str = "e";
+
+/// A NIP-01 event as sent by Nostr clients.
+///
+/// Stored events map as follows: `event_id` is the first 16 bytes of `id`, `author_pubkey`
+/// and `signature` are `pubkey` and `sig`, `occurred_at` is `created_at`, `tags` are kept
+/// as-is (`e` tags also become refs) and `payload_json` holds the whole Nostr event, which
+/// is what the signature is checked against.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NostrEvent {
+    pub id: String,
+    pub pubkey: String,
+    pub created_at: i64,
+    pub kind: u32,
+    pub tags: Vec<Vec<String>>,
+    pub content: String,
+    pub sig: String,
+}
+
+fn decode_hex32(value: &str, what: &str) -> Result<[u8; 32], String> {
+    let bytes = hex::decode(value).map_err(|_| format!("invalid {} hex", what))?;
+    bytes.try_into().map_err(|_| format!("{} must be 32 bytes", what))
+}
+
+/// Relay-side id for a Nostr event id (its first 16 bytes)
+pub fn event_id_for(nostr_id: &str) -> Result<Uuid, String> {
+    let bytes = decode_hex32(nostr_id, "id")?;
+    Ok(Uuid::from_slice(&bytes[..16]).expect("16 bytes"))
+}
+
+impl NostrEvent {
+    /// sha256 of `[0, pubkey, created_at, kind, tags, content]` serialized without whitespace
+    pub fn compute_id(&self) -> String {
+        let canonical = serde_json::json!([0, self.pubkey, self.created_at, self.kind, self.tags, self.content]);
+        hex::encode(Sha256::digest(canonical.to_string().as_bytes()))
+    }
+
+    /// Checks the id against the content and the BIP-340 Schnorr signature against the id.
+    pub fn verify(&self) -> Result<(), String> {
+        if self.compute_id() != self.id {
+            return Err("event id does not match its content".to_string());
+        }
+        let id = decode_hex32(&self.id, "id")?;
+        let pubkey = decode_hex32(&self.pubkey, "pubkey")?;
+        let sig = hex::decode(&self.sig).map_err(|_| "invalid sig hex".to_string())?;
+
+        let vk = VerifyingKey::from_bytes(&pubkey).map_err(|_| "invalid pubkey".to_string())?;
+        let sig = Signature::try_from(sig.as_slice()).map_err(|_| "invalid sig".to_string())?;
+        vk.verify_raw(&id, &sig).map_err(|_| "invalid signature".to_string())
+    }
+
+    /// Maps the Nostr event onto the relay's event fields. Does not verify it.
+    pub fn to_input(&self) -> Result<EventInput, String> {
+        let event_id = event_id_for(&self.id)?;
+        let occurred_at = Utc
+            .timestamp_opt(self.created_at, 0)
+            .single()
+            .ok_or_else(|| "created_at out of range".to_string())?;
+
+        let mut refs: Vec<EventRef> = Vec::new();
+        for tag in self.tags.iter().filter(|t| t.len() >= 2 && t[0] == EVENT_TAG_REF_KIND) {
+            // Malformed and self-referencing `e` tags stay plain tags
+            let Ok(target) = event_id_for(&tag[1]) else { continue };
+            let r = EventRef { event_id: target, kind: EVENT_TAG_REF_KIND.to_string() };
+            if target != event_id && !refs.contains(&r) && refs.len() < MAX_REFS_PER_EVENT {
+                refs.push(r);
+            }
+        }
+
+        Ok(EventInput {
+            event_id,
+            author_pubkey: self.pubkey.clone(),
+            signature: self.sig.clone(),
+            payload_hash: String::new(),
+            device_id: None,
+            author_id: None,
+            content_id: None,
+            event_type: Some(format!("{}{}", EVENT_TYPE_PREFIX, self.kind)),
+            payload_json: Some(serde_json::to_value(self).map_err(|e| e.to_string())?),
+            occurred_at: Some(occurred_at),
+            lamport: None,
+            refs,
+            tags: self.tags.clone(),
+            sig_scheme: SIG_SCHEME_NOSTR.to_string(),
+        })
+    }
+
+    /// The original Nostr event of a stored event, if it came in through NIP-01.
+    pub fn from_event(ev: &Event) -> Option<NostrEvent> {
+        if ev.sig_scheme != SIG_SCHEME_NOSTR {
+            return None;
+        }
+        serde_json::from_value(ev.payload_json.clone()?).ok()
+    }
+}
+
+/// Verification for events with `sig_scheme = "nostr"`, however they arrive (NIP-01, push
+/// or replication): the embedded Nostr event must verify and the relay fields must be
+/// exactly its mapping, so nothing outside the signed event can be altered.
+pub fn verify_input(ev: &EventInput) -> Result<(), String> {
+    let payload = ev.payload_json.clone().ok_or_else(|| "missing Nostr event payload".to_string())?;
+    let nostr: NostrEvent = serde_json::from_value(payload).map_err(|e| format!("invalid Nostr event: {}", e))?;
+    nostr.verify()?;
+
+    let expected = nostr.to_input()?;
+    let consistent = ev.event_id == expected.event_id
+        && ev.author_pubkey == expected.author_pubkey
+        && ev.signature == expected.signature
+        && ev.event_type == expected.event_type
+        && ev.occurred_at == expected.occurred_at
+        && ev.device_id.is_none()
+        && ev.author_id.is_none()
+        && ev.content_id.is_none()
+        && ev.lamport.is_none()
+        && ev.refs == expected.refs
+        && ev.tags == expected.tags;
+    if !consistent {
+        return Err("event fields do not match the embedded Nostr event".to_string());
+    }
+    Ok(())
+}
+
+/// A NIP-01 `REQ` filter
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct NostrFilter {
+    pub ids: Option<Vec<String>>,
+    pub authors: Option<Vec<String>>,
+    pub kinds: Option<Vec<u32>>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<i64>,
+    /// `#<letter>` tag filters
+    #[serde(flatten)]
+    pub tags: BTreeMap<String, serde_json::Value>,
+}
+
+impl NostrFilter {
+    /// Translates to an `EventFilter` over Nostr events only. Returns `None` for a filter
+    /// that can match nothing (an explicitly empty list).
+    pub fn to_event_filter(&self) -> Result<Option<EventFilter>, String> {
+        let mut filter = EventFilter {
+            sig_schemes: vec![SIG_SCHEME_NOSTR.to_string()],
+            ..Default::default()
+        };
+
+        if let Some(ids) = &self.ids {
+            if ids.is_empty() {
+                return Ok(None);
+            }
+            filter.event_ids = ids
+                .iter()
+                .map(|id| event_id_for(id).map_err(|_| "ids must be 64-character hex".to_string()))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(authors) = &self.authors {
+            if authors.is_empty() {
+                return Ok(None);
+            }
+            filter.authors = authors.clone();
+        }
+        if let Some(kinds) = &self.kinds {
+            if kinds.is_empty() {
+                return Ok(None);
+            }
+            filter.event_types = kinds.iter().map(|k| format!("{}{}", EVENT_TYPE_PREFIX, k)).collect();
+        }
+        for (name, value) in &self.tags {
+            let Some(key) = name.strip_prefix('#') else { continue };
+            let values: Vec<String> = serde_json::from_value(value.clone())
+                .map_err(|_| format!("{} must be a list of strings", name))?;
+            if values.is_empty() {
+                return Ok(None);
+            }
+            filter.tags.entry(key.to_string()).or_default().extend(values);
+        }
+
+        let timestamp = |secs: i64| Utc.timestamp_opt(secs, 0).single().ok_or_else(|| "timestamp out of range".to_string());
+        filter.since = self.since.map(timestamp).transpose()?;
+        filter.until = self.until.map(timestamp).transpose()?;
+
+        Ok(Some(filter))
+    }
+}
@@ -0,0 +1,248 @@
+// ----- NOSTR (NIP-01) WEBSOCKET -----
+//
+// Client -> relay:
+//   ["EVENT", <nostr event>]
+//   ["REQ", <sub id>, <filter>, ...]
+//   ["CLOSE", <sub id>]
+// Relay -> client:
+//   ["EVENT", <sub id>, <nostr event>]
+//   ["OK", <event id>, <true|false>, <message>]
+//   ["EOSE", <sub id>]
+//   ["CLOSED", <sub id>, <message>]
+//   ["NOTICE", <message>]
+//
+// Only events that arrived as Nostr events are visible here; native events have no
+// Nostr signature a client could check.
+
+use std::collections::{HashMap, HashSet};
+
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, State},
+    response::IntoResponse,
+};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::sync::{broadcast, mpsc};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+use uuid::Uuid;
+
+use tisane_relay::db;
+use tisane_relay::filter::EventFilter;
+use tisane_relay::ingest::{self, OutcomeStatus};
+use tisane_relay::nostr::{NostrEvent, NostrFilter};
+use tisane_relay::utils::compute_payload_hash;
+
+use crate::AppState;
+
+const OUTBOUND_BUFFER: usize = 256;
+const MAX_SUBSCRIPTIONS: usize = 32;
+const MAX_SUB_ID_LEN: usize = 64;
+/// Cap on stored events returned per filter before EOSE
+const MAX_REQ_LIMIT: i64 = 500;
+
+pub async fn nostr_handler(State(state): State<AppState>, ws: WebSocketUpgrade) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(state, socket))
+}
+
+async fn handle_socket(state: AppState, socket: WebSocket) {
+    let (mut sink, mut stream) = socket.split();
+    let (out_tx, mut out_rx) = mpsc::channel::<Value>(OUTBOUND_BUFFER);
+
+    let writer = tokio::spawn(async move {
+        while let Some(msg) = out_rx.recv().await {
+            if sink.send(Message::Text(msg.to_string())).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut subs: HashMap<String, JoinHandle<()>> = HashMap::new();
+
+    while let Some(Ok(msg)) = stream.next().await {
+        let text = match msg {
+            Message::Text(t) => t,
+            Message::Close(_) => break,
+            _ => continue,
+        };
+
+        let reply = match serde_json::from_str::<Vec<Value>>(&text) {
+            Ok(msg) => match msg.first().and_then(Value::as_str) {
+                Some("EVENT") => Some(handle_event(&state, msg.get(1)).await),
+                Some("REQ") => handle_req(&state, &msg, &mut subs, &out_tx),
+                Some("CLOSE") => {
+                    if let Some(task) = msg.get(1).and_then(Value::as_str).and_then(|id| subs.remove(id)) {
+                        task.abort();
+                    }
+                    None
+                },
+                _ => Some(json!(["NOTICE", "unsupported message type"])),
+            },
+            Err(e) => Some(json!(["NOTICE", format!("invalid: {}", e)])),
+        };
+
+        if let Some(reply) = reply {
+            if out_tx.send(reply).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    for (_, task) in subs {
+        task.abort();
+    }
+    writer.abort();
+    info!("nostr connection closed");
+}
+
+async fn handle_event(state: &AppState, raw: Option<&Value>) -> Value {
+    let event: NostrEvent = match raw.cloned().map(serde_json::from_value) {
+        Some(Ok(ev)) => ev,
+        Some(Err(e)) => return json!(["NOTICE", format!("invalid: malformed event: {}", e)]),
+        None => return json!(["NOTICE", "invalid: EVENT without event"]),
+    };
+    let input = match event.to_input() {
+        Ok(input) => input,
+        Err(msg) => return json!(["OK", event.id, false, format!("invalid: {}", msg)]),
+    };
+
+    // Signature checks happen in ingest, through the Nostr verification path
+    let (event_id, payload_hash) = (input.event_id, compute_payload_hash(&input.payload_json));
+    match ingest::ingest_each(&state.pool, &state.ephemeral, vec![input]).await {
+        Ok(mut outcomes) => match outcomes.pop() {
+            Some(o) if matches!(o.status, OutcomeStatus::Accepted | OutcomeStatus::Ephemeral) => json!(["OK", event.id, true, ""]),
+            Some(o) if o.status == OutcomeStatus::Duplicate => duplicate_reply(state, &event.id, event_id, &payload_hash).await,
+            Some(o) => json!(["OK", event.id, false, format!("invalid: {}", o.error.unwrap_or_default())]),
+            None => json!(["OK", event.id, false, "error: no result"]),
+        },
+        Err(e) => {
+            error!("nostr insert error: {}", e);
+            json!(["OK", event.id, false, "error: could not store event"])
+        }
+    }
+}
+
+/// The event ID maps to a stored event; only confirm it if that is this very Nostr event and
+/// not a different one whose ID happens to map to the same UUID (e.g. a native event).
+async fn duplicate_reply(state: &AppState, nostr_id: &str, event_id: Uuid, payload_hash: &str) -> Value {
+    match db::fetch_event(&state.pool, event_id).await {
+        Ok(Some(stored)) if stored.sig_scheme == db::SIG_SCHEME_NOSTR && stored.payload_hash == payload_hash => {
+            json!(["OK", nostr_id, true, "duplicate: already have this event"])
+        },
+        Ok(_) => json!(["OK", nostr_id, false, "invalid: event id collides with a different stored event"]),
+        Err(e) => {
+            error!("nostr duplicate check error: {}", e);
+            json!(["OK", nostr_id, false, "error: could not check the stored event"])
+        },
+    }
+}
+
+fn handle_req(
+    state: &AppState,
+    msg: &[Value],
+    subs: &mut HashMap<String, JoinHandle<()>>,
+    out: &mpsc::Sender<Value>,
+) -> Option<Value> {
+    let id = match msg.get(1).and_then(Value::as_str) {
+        Some(id) if !id.is_empty() && id.len() <= MAX_SUB_ID_LEN => id.to_string(),
+        _ => return Some(json!(["NOTICE", "invalid: subscription id must be 1-64 characters"])),
+    };
+
+    subs.retain(|_, task| !task.is_finished());
+    if let Some(old) = subs.remove(&id) {
+        old.abort();
+    }
+    if subs.len() >= MAX_SUBSCRIPTIONS {
+        return Some(json!(["CLOSED", id, format!("error: too many subscriptions (max {})", MAX_SUBSCRIPTIONS)]));
+    }
+
+    let mut filters = Vec::new();
+    for raw in &msg[2..] {
+        let parsed = serde_json::from_value::<NostrFilter>(raw.clone())
+            .map_err(|e| e.to_string())
+            .and_then(|f| Ok((f.to_event_filter()?, f.limit)));
+        match parsed {
+            Ok((Some(filter), limit)) => filters.push((filter, limit.unwrap_or(MAX_REQ_LIMIT).clamp(0, MAX_REQ_LIMIT))),
+            Ok((None, _)) => {},
+            Err(msg) => return Some(json!(["CLOSED", id, format!("invalid: {}", msg)])),
+        }
+    }
+
+    let task = tokio::spawn(run_subscription(state.clone(), id.clone(), filters, out.clone()));
+    subs.insert(id, task);
+    None
+}
+
+/// Sends stored matches newest first, then EOSE, then live matches until closed.
+async fn run_subscription(state: AppState, id: String, filters: Vec<(EventFilter, i64)>, out: mpsc::Sender<Value>) {
+    // Subscribe before querying so nothing committed meanwhile is missed; the hub only
+    // publishes events after subscribing, and ones the query also returned are deduplicated
+    let mut rx = state.hub.subscribe();
+    let result = async {
+        let mut stored: Vec<db::Event> = Vec::new();
+        for (filter, limit) in filters.iter().filter(|(_, limit)| *limit > 0) {
+            stored.extend(db::fetch_latest_events(&state.pool, filter, *limit).await?);
+        }
+        Ok::<_, sqlx::Error>(stored)
+    }
+    .await;
+
+    let mut stored = match result {
+        Ok(r) => r,
+        Err(e) => {
+            error!("nostr REQ error: {}", e);
+            let _ = out.send(json!(["CLOSED", id, "error: could not query events"])).await;
+            return;
+        }
+    };
+
+    let mut seen: HashSet<Uuid> = HashSet::new();
+    stored.retain(|ev| seen.insert(ev.event_id));
+    stored.sort_by(|a, b| b.occurred_at.cmp(&a.occurred_at).then(b.server_seq.cmp(&a.server_seq)));
+    for ev in &stored {
+        if let Some(nostr) = NostrEvent::from_event(ev) {
+            if out.send(json!(["EVENT", id, nostr])).await.is_err() {
+                return;
+            }
+        }
+    }
+    if out.send(json!(["EOSE", id])).await.is_err() {
+        return;
+    }
+    let mut ephemeral_rx = state.hub.subscribe_ephemeral();
+
+    loop {
+        let ev = tokio::select! {
+            _ = out.closed() => return,
+            // Ephemeral kinds are best effort: dropped rather than queued for a slow client
+            Ok(ev) = ephemeral_rx.recv() => {
+                if filters.iter().any(|(f, _)| f.matches(&ev)) {
+                    if let Some(nostr) = NostrEvent::from_event(&ev) {
+                        let _ = out.try_send(json!(["EVENT", id, nostr]));
+                    }
+                }
+                continue;
+            },
+            recv = rx.recv() => match recv {
+                Ok(published) => published.event,
+                Err(broadcast::error::RecvError::Lagged(_)) => {
+                    let _ = out.send(json!(["CLOSED", id, "error: subscription fell behind"])).await;
+                    return;
+                },
+                Err(broadcast::error::RecvError::Closed) => return,
+            },
+        };
+        if seen.contains(&ev.event_id) || !filters.iter().any(|(f, _)| f.matches(&ev)) {
+            continue;
+        }
+        let Some(nostr) = NostrEvent::from_event(&ev) else { continue };
+        match out.try_send(json!(["EVENT", id, nostr])) {
+            Ok(()) => {},
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                let _ = out.send(json!(["CLOSED", id, "error: subscriber too slow"])).await;
+                return;
+            },
+            Err(mpsc::error::TrySendError::Closed(_)) => return,
+        }
+    }
+}
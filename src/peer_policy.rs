@@ -0,0 +1,78 @@
+// ----- INBOUND PEER POLICY -----
+//
+// Each peer's inbound_policy limits what it may push to /relay/replicate: a read-only peer
+// may not push at all, batches and the event rate can be capped, and events outside the
+// allowed types and authors are dropped. Events over the batch or rate limit are handed
+// back to retry, which is flow control rather than misbehaviour. Pushing while read-only
+// and sending disallowed events count as violations; a peer with too many violations
+// within an hour is suspended until an operator lifts it.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use axum::{http::StatusCode, response::IntoResponse, Json};
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use tisane_relay::db;
+use tisane_relay::ephemeral::RateLimiter;
+
+use crate::AppState;
+
+/// Per-peer event budgets. A peer's limiter is replaced when its configured rate changes.
+#[derive(Default)]
+pub struct PeerRateLimits {
+    limiters: Mutex<HashMap<Uuid, (u32, RateLimiter)>>,
+}
+
+impl PeerRateLimits {
+    /// Takes up to `n` events from the peer's budget of `per_minute`; returns how many fit.
+    pub fn take(&self, peer_id: Uuid, per_minute: u32, n: u32) -> u32 {
+        let mut limiters = self.limiters.lock().unwrap_or_else(|e| e.into_inner());
+        let (rate, limiter) = limiters
+            .entry(peer_id)
+            .or_insert_with(|| (per_minute, RateLimiter::per_minute(per_minute)));
+        if *rate != per_minute {
+            *rate = per_minute;
+            *limiter = RateLimiter::per_minute(per_minute);
+        }
+        limiter.take_up_to(&peer_id.to_string(), n)
+    }
+}
+
+/// Checks a pushed batch of `len` events against the peer's read-only flag, batch size and
+/// rate, and returns how many of them (from the front) may be processed now. Pushing to a
+/// read-only relay is refused and counted as a violation; when the rate budget is used up
+/// entirely the batch is refused with 429. Either way the response to send comes back.
+pub async fn check_batch(state: &AppState, peer: &db::Peer, len: usize) -> Result<usize, axum::response::Response> {
+    let policy = &peer.inbound_policy;
+    if policy.read_only {
+        let msg = "peer is read-only";
+        record_violation(state, peer, msg).await;
+        return Err((StatusCode::FORBIDDEN, Json(serde_json::json!({"error": msg}))).into_response());
+    }
+
+    let mut admitted = u32::try_from(len).unwrap_or(u32::MAX);
+    if let Some(max) = policy.max_batch_events {
+        admitted = admitted.min(max);
+    }
+    if let Some(rate) = policy.max_events_per_min {
+        admitted = state.peer_rates.take(peer.peer_id, rate, admitted);
+        if admitted == 0 && len > 0 {
+            let msg = format!("more than {} events per minute", rate);
+            return Err((StatusCode::TOO_MANY_REQUESTS, Json(serde_json::json!({"error": msg}))).into_response());
+        }
+    }
+    Ok(admitted as usize)
+}
+
+/// Counts a violation against `peer`, suspending it once it reaches the configured limit.
+pub async fn record_violation(state: &AppState, peer: &db::Peer, reason: &str) {
+    match db::record_peer_violation(&state.pool, peer.peer_id, reason, state.violation_limit).await {
+        Ok(updated) if updated.is_suspended() && !peer.is_suspended() => {
+            warn!("Peer {} suspended after {} policy violations (last: {})", peer.peer_id, updated.policy_violations, reason);
+        },
+        Ok(_) => warn!("Policy violation by peer {}: {}", peer.peer_id, reason),
+        Err(e) => error!("Failed to record policy violation by peer {}: {}", peer.peer_id, e),
+    }
+}
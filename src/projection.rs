@@ -0,0 +1,68 @@
+use serde_json::Value;
+
+use crate::db::Event;
+
+/// Fields that can be requested with `?fields=`.
+pub const EVENT_FIELDS: &[&str] = &[
+    "event_id",
+    "server_seq",
+    "author_pubkey",
+    "signature",
+    "payload_hash",
+    "device_id",
+    "author_id",
+    "content_id",
+    "event_type",
+    "payload_json",
+    "occurred_at",
+    "lamport",
+    "refs",
+    "tags",
+    "sig_scheme",
+];
+
+/// Fields that are always returned: `event_id` to fetch the body later,
+/// `server_seq` so clients can check the page against its cursors.
+const REQUIRED_FIELDS: &[&str] = &["event_id", "server_seq"];
+
+/// Subset of event fields to return from a pull (header-only sync).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Projection {
+    fields: Vec<&'static str>,
+}
+
+impl Projection {
+    /// Parses a comma-separated field list. Unknown fields are rejected rather than ignored,
+    /// so a typo doesn't silently produce empty events.
+    pub fn parse(list: &str) -> Result<Self, String> {
+        let mut fields: Vec<&'static str> = REQUIRED_FIELDS.to_vec();
+        for name in list.split(',').map(|s| s.trim()).filter(|s| !s.is_empty()) {
+            let field = EVENT_FIELDS
+                .iter()
+                .find(|f| **f == name)
+                .ok_or_else(|| format!("unknown field: {}", name))?;
+            if !fields.contains(field) {
+                fields.push(field);
+            }
+        }
+        Ok(Projection { fields })
+    }
+
+    pub fn includes(&self, field: &str) -> bool {
+        self.fields.contains(&field)
+    }
+
+    pub fn apply(&self, event: &Event) -> Value {
+        let mut full = match serde_json::to_value(event) {
+            Ok(Value::Object(map)) => map,
+            _ => return Value::Null,
+        };
+        let mut out = serde_json::Map::with_capacity(self.fields.len());
+        for field in &self.fields {
+            if let Some(v) = full.remove(*field) {
+                out.insert(field.to_string(), v);
+            }
+        }
+        Value::Object(out)
+    }
+}
@@ -0,0 +1,107 @@
+// Protobuf messages from proto/relay.proto and their mapping onto db::EventInput /
+// db::Event, for the gRPC API.
+
+use chrono::{DateTime, TimeZone, Utc};
+use tonic::Status;
+use uuid::Uuid;
+
+use crate::db;
+use crate::filter::EventFilter;
+use crate::ingest::{EventOutcome, OutcomeStatus};
+
+pub mod pb {
+    tonic::include_proto!("tisane.relay.v1");
+}
+
+pub fn parse_uuid(value: &str, what: &str) -> Result<Uuid, Status> {
+    Uuid::parse_str(value).map_err(|_| Status::invalid_argument(format!("invalid {}", what)))
+}
+
+fn to_timestamp(t: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp { seconds: t.timestamp(), nanos: t.timestamp_subsec_nanos() as i32 }
+}
+
+fn from_timestamp(t: prost_types::Timestamp) -> Result<DateTime<Utc>, Status> {
+    Utc.timestamp_opt(t.seconds, t.nanos.max(0) as u32)
+        .single()
+        .ok_or_else(|| Status::invalid_argument("occurred_at out of range"))
+}
+
+pub fn input_from_pb(ev: pb::EventInput) -> Result<db::EventInput, Status> {
+    let refs = ev
+        .refs
+        .into_iter()
+        .map(|r| Ok(db::EventRef { event_id: parse_uuid(&r.event_id, "ref event_id")?, kind: r.kind }))
+        .collect::<Result<_, Status>>()?;
+    let payload_json = ev
+        .payload_json
+        .map(|p| serde_json::from_str(&p))
+        .transpose()
+        .map_err(|e| Status::invalid_argument(format!("payload_json is not valid JSON: {}", e)))?;
+
+    Ok(db::EventInput {
+        event_id: parse_uuid(&ev.event_id, "event_id")?,
+        author_pubkey: ev.author_pubkey,
+        signature: ev.signature,
+        payload_hash: ev.payload_hash,
+        device_id: ev.device_id,
+        author_id: ev.author_id,
+        content_id: ev.content_id,
+        event_type: ev.event_type,
+        payload_json,
+        occurred_at: ev.occurred_at.map(from_timestamp).transpose()?,
+        lamport: ev.lamport,
+        refs,
+        tags: ev.tags.into_iter().map(|t| t.values).collect(),
+        sig_scheme: if ev.sig_scheme.is_empty() { db::SIG_SCHEME_ED25519.to_string() } else { ev.sig_scheme },
+    })
+}
+
+pub fn event_to_pb(ev: &db::Event) -> pb::Event {
+    pb::Event {
+        event_id: ev.event_id.to_string(),
+        author_pubkey: ev.author_pubkey.clone(),
+        signature: ev.signature.clone(),
+        payload_hash: ev.payload_hash.clone(),
+        device_id: ev.device_id.clone(),
+        author_id: ev.author_id.clone(),
+        content_id: ev.content_id.clone(),
+        event_type: ev.event_type.clone(),
+        payload_json: ev.payload_json.as_ref().map(|p| p.to_string()),
+        occurred_at: ev.occurred_at.map(to_timestamp),
+        lamport: ev.lamport,
+        refs: ev.refs.iter().map(|r| pb::EventRef { event_id: r.event_id.to_string(), kind: r.kind.clone() }).collect(),
+        tags: ev.tags.iter().map(|t| pb::Tag { values: t.clone() }).collect(),
+        sig_scheme: ev.sig_scheme.clone(),
+        server_seq: ev.server_seq,
+    }
+}
+
+pub fn filter_from_pb(f: Option<pb::Filter>) -> EventFilter {
+    let Some(f) = f else { return EventFilter::default() };
+    let mut filter = EventFilter {
+        authors: f.authors,
+        event_types: f.event_types,
+        content_ids: f.content_ids,
+        ..Default::default()
+    };
+    for t in f.tags.into_iter().filter(|t| !t.key.is_empty() && !t.values.is_empty()) {
+        filter.tags.entry(t.key).or_default().extend(t.values);
+    }
+    filter
+}
+
+pub fn outcome_to_pb(o: EventOutcome) -> pb::EventOutcome {
+    let status = match o.status {
+        OutcomeStatus::Accepted => pb::OutcomeStatus::Accepted,
+        OutcomeStatus::Duplicate => pb::OutcomeStatus::Duplicate,
+        OutcomeStatus::Rejected => pb::OutcomeStatus::Rejected,
+        OutcomeStatus::Ephemeral => pb::OutcomeStatus::Ephemeral,
+    };
+    pb::EventOutcome {
+        event_id: o.event_id.to_string(),
+        status: status as i32,
+        server_seq: o.server_seq,
+        error: o.error,
+    }
+}
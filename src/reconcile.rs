@@ -0,0 +1,213 @@
+// ----- ANTI-ENTROPY RECONCILIATION -----
+//
+// Cursor replication can't repair events lost to pruning, restores or bugs. Periodically
+// each relay compares the events it sends each peer with the ones that peer sends it, by
+// range fingerprints (see `tisane_relay::fingerprints`):
+//
+//   POST /relay/reconcile {"ranges":[{"range":{"lower","upper"},"summary":{"count","fingerprint"}}]}
+//
+// For every range the peer answers "match", lists its IDs when it holds few events there,
+// or splits the range into sub-ranges with its own summaries. The initiator compares those
+// locally and asks again only about the sub-ranges that differ, so a few missing events
+// among millions cost a handful of small round trips. Missing events then move through
+// POST /relay/reconcile/events (fetch by ID) and the regular /relay/replicate.
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+use axum::{extract::State, http::HeaderMap, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use tisane_relay::db;
+use tisane_relay::fingerprints::{self, Divergence, ReconcileRequest, ReconcileResponse, MAX_RANGES_PER_REQUEST};
+use tisane_relay::ingest;
+
+use crate::replication::{quarantine_rejected, store_inbound};
+use crate::{authenticate_peer, inbound_events, AppState, ReplicateResp};
+
+fn internal(e: sqlx::Error) -> axum::response::Response {
+    (StatusCode::INTERNAL_SERVER_ERROR, Json(serde_json::json!({"error": e.to_string()}))).into_response()
+}
+
+pub async fn reconcile_handler(State(state): State<AppState>, headers: HeaderMap, Json(req): Json<ReconcileRequest>) -> impl IntoResponse {
+    let peer = match authenticate_peer(&state, &headers).await {
+        Ok(peer) => peer,
+        Err(resp) => return resp,
+    };
+    if req.ranges.len() > MAX_RANGES_PER_REQUEST {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": format!("at most {} ranges per request", MAX_RANGES_PER_REQUEST)}))).into_response();
+    }
+
+    // Compared against what we send this peer, like its own side does
+    match fingerprints::answer(&state.pool, &peer.outbound_filter, req).await {
+        Ok(resp) => (StatusCode::OK, Json(resp)).into_response(),
+        Err(e) => internal(e),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ReconcileEventsReq {
+    pub event_ids: Vec<Uuid>,
+}
+
+/// Hands a peer the events it found missing, with provenance like regular replication
+pub async fn reconcile_events_handler(State(state): State<AppState>, headers: HeaderMap, Json(req): Json<ReconcileEventsReq>) -> impl IntoResponse {
+    let peer = match authenticate_peer(&state, &headers).await {
+        Ok(peer) => peer,
+        Err(resp) => return resp,
+    };
+    if req.event_ids.len() > db::MAX_FETCH_IDS {
+        return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": format!("at most {} event_ids per request", db::MAX_FETCH_IDS)}))).into_response();
+    }
+    match db::fetch_events_with_provenance(&state.pool, &req.event_ids).await {
+        Ok(events) => {
+            let now = chrono::Utc::now();
+            let events: Vec<db::ReplicatedEvent> = events
+                .into_iter()
+                .filter(|(ev, _)| peer.outbound_filter.matches(ev, now))
+                .map(|(ev, prov)| db::ReplicatedEvent { event: ev.to_input(), provenance: prov.forwarded_by(state.relay_id) })
+                .collect();
+            (StatusCode::OK, Json(events)).into_response()
+        },
+        Err(e) => internal(e),
+    }
+}
+
+fn peer_request(client: &reqwest::Client, peer: &db::Peer, relay_id: Option<Uuid>, path: &str) -> reqwest::RequestBuilder {
+    let req = client.post(format!("{}{}", peer.url, path)).header("X-Peer-Token", &peer.shared_secret);
+    match relay_id {
+        Some(rid) => req.header("X-Relay-Id", rid.to_string()),
+        None => req,
+    }
+}
+
+async fn post_json<T: Serialize, R: serde::de::DeserializeOwned>(
+    client: &reqwest::Client,
+    peer: &db::Peer,
+    relay_id: Option<Uuid>,
+    path: &str,
+    body: &T,
+) -> Result<R, String> {
+    let resp = peer_request(client, peer, relay_id, path).json(body).send().await.map_err(|e| format!("{}: {}", path, e))?;
+    if !resp.status().is_success() {
+        return Err(format!("{}: status {}", path, resp.status()));
+    }
+    resp.json().await.map_err(|e| format!("{}: invalid response: {}", path, e))
+}
+
+/// Compares the events we send `peer` with the ones it sends us, without changing either
+/// side. `relay_id` identifies us to the peer when known.
+pub async fn diff(pool: &sqlx::PgPool, client: &reqwest::Client, peer: &db::Peer, relay_id: Option<Uuid>) -> Result<Divergence, String> {
+    fingerprints::compare(pool, &peer.outbound_filter, move |req| async move {
+        post_json::<_, ReconcileResponse>(client, peer, relay_id, "/relay/reconcile", &req).await
+    })
+    .await
+}
+
+/// Leaves out the events already quarantined with `peer` in `direction`; they were
+/// refused for good and would only be refused again.
+async fn without_quarantined(state: &AppState, peer: &db::Peer, direction: &str, ids: &[Uuid]) -> Result<Vec<Uuid>, String> {
+    let quarantined: HashSet<Uuid> = db::fetch_quarantined_ids(&state.pool, peer.peer_id, direction, ids)
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .collect();
+    Ok(ids.iter().copied().filter(|id| !quarantined.contains(id)).collect())
+}
+
+/// Transfers what `diff` found, as far as the peer's direction allows: missing events are
+/// fetched from pull peers and sent to push peers, through the peer's outbound filter.
+/// Refusals are handled like regular replication: events refused for good are quarantined
+/// and not offered again, ones refused for now wait for the next run.
+async fn repair(state: &AppState, client: &reqwest::Client, peer: &db::Peer, divergence: &Divergence) -> Result<(), String> {
+    let chunk_size = (peer.batch_size.max(1) as usize).min(db::MAX_FETCH_IDS);
+
+    if peer.pulls() {
+        for ids in divergence.missing_local.chunks(chunk_size) {
+            let event_ids = without_quarantined(state, peer, db::QUARANTINE_INBOUND, ids).await?;
+            if event_ids.is_empty() {
+                continue;
+            }
+            let req = ReconcileEventsReq { event_ids };
+            let events: Vec<db::ReplicatedEvent> = post_json(client, peer, Some(state.relay_id), "/relay/reconcile/events", &req).await?;
+            let events = inbound_events(state, peer, events, 1).events;
+            if let Some(reason) = store_inbound(state, peer, events).await.map_err(|e| format!("storing fetched events: {}", e))? {
+                info!("Reconcile with peer {}: {}", peer.peer_id, reason);
+            }
+        }
+    }
+
+    if peer.pushes() {
+        let now = chrono::Utc::now();
+        for ids in divergence.missing_remote.chunks(chunk_size) {
+            let ids = without_quarantined(state, peer, db::QUARANTINE_OUTBOUND, ids).await?;
+            let (events, sent): (Vec<db::ReplicatedEvent>, Vec<(Uuid, i64)>) = db::fetch_events_with_provenance(&state.pool, &ids)
+                .await
+                .map_err(|e| e.to_string())?
+                .into_iter()
+                .filter(|(ev, _)| peer.outbound_filter.matches(ev, now))
+                .map(|(ev, prov)| {
+                    let sent = (ev.event_id, ev.server_seq);
+                    (db::ReplicatedEvent { event: ev.to_input(), provenance: prov.forwarded_by(state.relay_id) }, sent)
+                })
+                .unzip();
+            if events.is_empty() {
+                continue;
+            }
+            let reply: ReplicateResp = post_json(client, peer, Some(state.relay_id), "/relay/replicate", &events).await?;
+            let ack = ingest::acknowledge(&sent, reply.outcomes);
+            quarantine_rejected(state, peer, &events, &ack).await.map_err(|e| format!("quarantining refused events: {}", e))?;
+            if let Some((_, reason)) = ack.held_back {
+                info!("Reconcile with peer {}: {}", peer.peer_id, reason);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reconciles with every reachable peer each `interval`.
+pub async fn reconcile_worker(state: AppState, client: reqwest::Client, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick fires immediately; give replication a head start after boot
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let peers = match db::fetch_healthy_peers(&state.pool).await {
+            Ok(p) => p,
+            Err(e) => {
+                error!("Reconcile failed to fetch peers: {}", e);
+                continue;
+            }
+        };
+
+        for peer in peers.iter().filter(|p| p.pushes() || p.pulls()) {
+            if peer.backing_off(chrono::Utc::now()) {
+                continue;
+            }
+            let divergence = match diff(&state.pool, &client, peer, Some(state.relay_id)).await {
+                Ok(d) => d,
+                Err(e) => {
+                    warn!("Reconcile with peer {} failed: {}", peer.peer_id, e);
+                    continue;
+                }
+            };
+            if divergence.is_empty() {
+                continue;
+            }
+            info!(
+                "Reconcile with peer {}: we lack {}, peer lacks {}, {} ranges unresolved after {} rounds",
+                peer.peer_id,
+                divergence.missing_local.len(),
+                divergence.missing_remote.len(),
+                divergence.unresolved,
+                divergence.rounds
+            );
+            if let Err(e) = repair(&state, &client, peer, &divergence).await {
+                warn!("Reconcile repair with peer {} failed: {}", peer.peer_id, e);
+            }
+        }
+    }
+}
@@ -0,0 +1,429 @@
+// ----- BACKGROUND WORKER -----
+//
+// A supervisor owns one task per peer. It sequences newly committed events into the
+// replication log and wakes the peer tasks; each task pushes to and pulls from its peer
+// on its own schedule, so a slow or failing peer only delays itself. A semaphore bounds
+// how many requests to peers are in flight at once; it is taken per batch, so a peer with
+// a long backlog doesn't keep the others waiting until it has caught up.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::{watch, Semaphore};
+use tokio::time::Instant;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use tisane_relay::db;
+use tisane_relay::ingest::{self, Acknowledgement, OutcomeStatus};
+use tisane_relay::supervisor::Supervisor;
+
+use crate::{inbound_events, AppState, ReplicatePullResp, ReplicateResp};
+
+/// Longest a peer task sleeps without new events (retries, pulls, settings changes)
+const IDLE_POLL_SECS: u64 = 30;
+/// How often the supervisor looks for added and removed peers
+const PEER_REFRESH_SECS: u64 = 30;
+/// How often pull-mode peers are polled for new events once caught up
+const PULL_INTERVAL_SECS: u64 = 10;
+/// Failed peers are retried after 5s, doubling up to 10 minutes; after 5 failures in a
+/// row they are down and only probed on that schedule
+const BACKOFF: db::PeerBackoff = db::PeerBackoff { base_secs: 5, max_secs: 600, down_after: 5 };
+const PROBE_TIMEOUT_SECS: u64 = 10;
+const CONNECT_TIMEOUT_SECS: u64 = 5;
+/// Pause before restarting a peer task that panicked
+const RESTART_DELAY_SECS: u64 = 5;
+/// Pause before offering events that were refused for now (e.g. rate limited) again
+const DEFERRED_RETRY_SECS: u64 = 10;
+
+pub struct WorkerConfig {
+    /// Batches exchanged with peers at the same time
+    pub concurrency: usize,
+    /// Per-request timeout for replication traffic
+    pub request_timeout: Duration,
+}
+
+/// Why a round with a peer stopped early
+enum PeerError {
+    /// Our own storage failed; retried on the next wake without counting against the peer
+    Local,
+    /// The peer failed or misbehaved; counts towards its backoff and health
+    Remote(String),
+    /// Events were refused for now, by the peer or by us; the cursor stays put and the round
+    /// is retried shortly without counting against the peer
+    Deferred(String),
+}
+
+/// Everything a peer task needs, cheap to clone for restarts
+#[derive(Clone)]
+struct PeerTaskContext {
+    state: AppState,
+    client: reqwest::Client,
+    permits: Arc<Semaphore>,
+    /// Bumped whenever new events were sequenced
+    log: watch::Receiver<u64>,
+}
+
+pub async fn replication_worker(state: AppState, config: WorkerConfig) {
+    info!("Helper: Replication worker started with Relay ID: {}", state.relay_id);
+    let client = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(CONNECT_TIMEOUT_SECS))
+        .timeout(config.request_timeout)
+        .build()
+        .expect("failed to build replication HTTP client");
+    let (log_tx, log_rx) = watch::channel(0u64);
+    let ctx = PeerTaskContext { state: state.clone(), client, permits: Arc::new(Semaphore::new(config.concurrency)), log: log_rx };
+
+    let mut head = state.hub.watch_head();
+    let mut refresh = tokio::time::interval(Duration::from_secs(PEER_REFRESH_SECS));
+    let mut tasks: Supervisor<Uuid> = Supervisor::default();
+
+    loop {
+        tokio::select! {
+            _ = refresh.tick() => {
+                sequence_log(&state, &log_tx).await;
+                let peers = match db::fetch_all_peers(&state.pool).await {
+                    Ok(p) => p,
+                    Err(e) => {
+                        error!("Worker failed to fetch peers: {}", e);
+                        continue;
+                    }
+                };
+                tasks.retain(|peer_id| peers.iter().any(|p| p.peer_id == *peer_id));
+                for peer in peers.iter().filter(|p| !tasks.contains(&p.peer_id)) {
+                    tasks.spawn(peer.peer_id, peer_task(ctx.clone(), peer.peer_id, Duration::ZERO));
+                }
+            },
+            changed = head.changed() => {
+                if changed.is_err() {
+                    return;
+                }
+                // The hub sequences new events before it moves its head
+                log_tx.send_modify(|n| *n += 1);
+            },
+            Some(peer_id) = tasks.next_panicked() => {
+                error!("Replication task for peer {} panicked; restarting in {}s", peer_id, RESTART_DELAY_SECS);
+                tasks.spawn(peer_id, peer_task(ctx.clone(), peer_id, Duration::from_secs(RESTART_DELAY_SECS)));
+            },
+        }
+    }
+}
+
+/// Gives newly committed events their place in the replication log and wakes peer tasks.
+async fn sequence_log(state: &AppState, log: &watch::Sender<u64>) {
+    match db::sequence_replication_log(&state.pool).await {
+        Ok(0) => {},
+        Ok(_) => log.send_modify(|n| *n += 1),
+        Err(e) => error!("Worker failed to sequence new events: {}", e),
+    }
+}
+
+/// Replicates with one peer until it is removed. Settings are reloaded every round, so
+/// direction and batching changes apply without a restart.
+async fn peer_task(mut ctx: PeerTaskContext, peer_id: Uuid, start_delay: Duration) {
+    tokio::time::sleep(start_delay).await;
+    let mut next_pull: Option<Instant> = None;
+
+    loop {
+        // Events sequenced from here on will wake the next sleep
+        ctx.log.borrow_and_update();
+        let mut wake_at = Instant::now() + Duration::from_secs(IDLE_POLL_SECS);
+
+        match db::fetch_peer(&ctx.state.pool, peer_id).await {
+            Ok(Some(mut peer)) => {
+                if let Some(at) = replication_round(&ctx, &mut peer, &mut next_pull).await {
+                    wake_at = wake_at.min(at);
+                }
+            },
+            Ok(None) => return,
+            Err(e) => error!("Failed to load peer {}: {}", peer_id, e),
+        }
+
+        if tokio::time::timeout_at(wake_at, ctx.log.changed()).await.is_ok_and(|r| r.is_err()) {
+            // The supervisor is gone
+            return;
+        }
+    }
+}
+
+/// One round with a peer: probe it if down, otherwise pull if due and push until caught
+/// up. Returns when the peer next needs attention, if sooner than the idle poll.
+async fn replication_round(ctx: &PeerTaskContext, peer: &mut db::Peer, next_pull: &mut Option<Instant>) -> Option<Instant> {
+    if peer.is_suspended() || (!peer.pushes() && !peer.pulls()) {
+        return None;
+    }
+    if peer.backing_off(Utc::now()) {
+        return Some(instant_at(peer.next_attempt_at));
+    }
+
+    let state = &ctx.state;
+    if peer.is_down() {
+        let Ok(_permit) = ctx.permits.acquire().await else { return None };
+        probe(state, &ctx.client, peer).await;
+        if peer.is_down() {
+            return Some(instant_at(peer.next_attempt_at));
+        }
+    }
+
+    match replicate_with(ctx, peer, next_pull).await {
+        Ok(()) | Err(PeerError::Local) => next_pull.filter(|_| peer.pulls()),
+        Err(PeerError::Remote(msg)) => {
+            record_failure(state, peer, msg).await;
+            Some(instant_at(peer.next_attempt_at))
+        },
+        Err(PeerError::Deferred(msg)) => {
+            info!("Replication with peer {} deferred: {}", peer.peer_id, msg);
+            Some(Instant::now() + Duration::from_secs(DEFERRED_RETRY_SECS))
+        },
+    }
+}
+
+/// Pulls if due, then pushes until caught up, holding a permit for one batch at a time.
+async fn replicate_with(ctx: &PeerTaskContext, peer: &mut db::Peer, next_pull: &mut Option<Instant>) -> Result<(), PeerError> {
+    let (state, client) = (&ctx.state, &ctx.client);
+    if peer.pulls() && next_pull.is_none_or(|at| at <= Instant::now()) {
+        let result = drain_pull(ctx, peer).await;
+        *next_pull = Some(Instant::now() + Duration::from_secs(PULL_INTERVAL_SECS));
+        match result {
+            // What was held back is pulled again next time; pushing doesn't wait for it
+            Err(PeerError::Deferred(msg)) => info!("Pull from peer {} deferred: {}", peer.peer_id, msg),
+            other => other?,
+        }
+    }
+    if peer.pushes() {
+        loop {
+            let Ok(_permit) = ctx.permits.acquire().await else { return Ok(()) };
+            if !send_batch(state, client, peer).await? {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn instant_at(at: Option<DateTime<Utc>>) -> Instant {
+    let wait = at.map(|at| (at - Utc::now()).to_std().unwrap_or_default()).unwrap_or_default();
+    Instant::now() + wait
+}
+
+async fn record_success(state: &AppState, peer: &mut db::Peer) {
+    let recovered = peer.health != db::PEER_HEALTH_HEALTHY;
+    match db::record_peer_success(&state.pool, peer.peer_id).await {
+        Ok(updated) => {
+            if recovered && peer.consecutive_failures > 0 {
+                info!("Peer {} is healthy again after {} failures", peer.peer_id, peer.consecutive_failures);
+            }
+            *peer = updated;
+        },
+        Err(e) => error!("Failed to record success for peer {}: {}", peer.peer_id, e),
+    }
+}
+
+async fn record_failure(state: &AppState, peer: &mut db::Peer, msg: String) {
+    warn!("Replication with peer {} failed: {}", peer.peer_id, msg);
+    match db::record_peer_failure(&state.pool, peer.peer_id, &msg, BACKOFF).await {
+        Ok(updated) => {
+            if updated.is_down() && !peer.is_down() {
+                warn!("Peer {} is down after {} consecutive failures", peer.peer_id, updated.consecutive_failures);
+            }
+            *peer = updated;
+        },
+        Err(e) => error!("Failed to record failure for peer {}: {}", peer.peer_id, e),
+    }
+}
+
+/// Checks whether a down peer answers on `/health`. If it does, replication is retried
+/// immediately; otherwise the probe counts as another failure and extends the backoff.
+async fn probe(state: &AppState, client: &reqwest::Client, peer: &mut db::Peer) {
+    let res = client.get(format!("{}/health", peer.url))
+        .timeout(Duration::from_secs(PROBE_TIMEOUT_SECS))
+        .send()
+        .await;
+
+    match res {
+        Ok(resp) if resp.status().is_success() => {
+            info!("Peer {} answers its health probe; resuming replication", peer.peer_id);
+            match db::record_peer_probe_ok(&state.pool, peer.peer_id, BACKOFF).await {
+                Ok(updated) => *peer = updated,
+                Err(e) => error!("Failed to record probe for peer {}: {}", peer.peer_id, e),
+            }
+        },
+        Ok(resp) => record_failure(state, peer, format!("health probe: status {}", resp.status())).await,
+        Err(e) => record_failure(state, peer, format!("health probe: {}", e)).await,
+    }
+}
+
+/// Sends the next batch to `peer` and advances its cursor past everything the batch
+/// covered, including events the peer already has or that reached the hop limit.
+/// Returns whether there is probably more to send.
+async fn send_batch(state: &AppState, client: &reqwest::Client, peer: &mut db::Peer) -> Result<bool, PeerError> {
+    let limit = peer.batch_size.max(1) as i64;
+    let log = match db::fetch_replication_batch(&state.pool, Some(peer.peer_id), &peer.outbound_filter, peer.last_cursor_seq, limit).await {
+        Ok(log) => log,
+        Err(e) => {
+            error!("Failed to fetch replication batch for {}: {}", peer.peer_id, e);
+            return Err(PeerError::Local);
+        }
+    };
+
+    if log.entries.is_empty() && log.scanned_to <= peer.last_cursor_seq {
+        return Ok(false);
+    }
+
+    let batch = log.into_outbound(state.relay_id, state.max_hops, peer, peer.last_cursor_seq);
+    let mut cursor = batch.cursor;
+    let mut held_back = None;
+    if !batch.events.is_empty() {
+        let res = client.post(format!("{}/relay/replicate", peer.url))
+            .header("X-Peer-Token", &peer.shared_secret)
+            .header("X-Relay-Id", state.relay_id.to_string())
+            .json(&batch.events)
+            .send()
+            .await;
+
+        // Older relays answer without their id or per-event outcomes; a 2xx then covers the
+        // whole batch
+        let reply = match res {
+            Ok(resp) if resp.status().is_success() => resp.json::<ReplicateResp>().await.ok(),
+            // The peer's inbound budget for us is used up; not a failure
+            Ok(resp) if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS => {
+                return Err(PeerError::Deferred(format!("replicate: status {}", resp.status())));
+            },
+            Ok(resp) => return Err(PeerError::Remote(format!("replicate: status {}", resp.status()))),
+            Err(e) => return Err(PeerError::Remote(format!("replicate: {}", e))),
+        };
+        if let Some(rid) = reply.as_ref().and_then(|r| r.relay_id) {
+            learn_relay_id(state, peer, rid).await;
+        }
+        record_success(state, peer).await;
+        let sent: Vec<(Uuid, i64)> = batch.events.iter().map(|ev| ev.event.event_id).zip(batch.seqs.iter().copied()).collect();
+        let ack = ingest::acknowledge(&sent, reply.and_then(|r| r.outcomes));
+        if let Err(e) = quarantine_rejected(state, peer, &batch.events, &ack).await {
+            error!("Failed to quarantine events for peer {}: {}", peer.peer_id, e);
+            return Err(PeerError::Local);
+        }
+        cursor = ack.cursor(cursor);
+        held_back = ack.held_back.map(|(_, reason)| reason);
+    }
+
+    // Update cursor
+    if let Err(e) = db::update_peer_cursor(&state.pool, peer.peer_id, cursor).await {
+        error!("Failed to update cursor for peer {}: {}", peer.peer_id, e);
+        return Err(PeerError::Local);
+    }
+    peer.last_cursor_seq = cursor;
+    if !batch.events.is_empty() {
+        record_traffic(state, peer, batch.events.len(), 0).await;
+        info!("Replicated {} events to peer {}", batch.events.len(), peer.peer_id);
+    }
+    if let Some(reason) = held_back {
+        return Err(PeerError::Deferred(format!("replicate: {}", reason)));
+    }
+    Ok(batch.has_more(limit))
+}
+
+/// Quarantines the events `peer` refused for good, which then count as delivered.
+pub async fn quarantine_rejected(state: &AppState, peer: &db::Peer, sent: &[db::ReplicatedEvent], ack: &Acknowledgement) -> Result<(), sqlx::Error> {
+    for (event_id, error) in &ack.rejected {
+        let Some(ev) = sent.iter().find(|ev| ev.event.event_id == *event_id) else { continue };
+        db::quarantine_event(&state.pool, peer.peer_id, db::QUARANTINE_OUTBOUND, ev, error).await?;
+        warn!("Peer {} rejected event {} ({}); quarantined", peer.peer_id, event_id, error);
+    }
+    Ok(())
+}
+
+/// Counts events exchanged with `peer` for its throughput; a failure only loses statistics.
+async fn record_traffic(state: &AppState, peer: &db::Peer, sent: usize, received: usize) {
+    if let Err(e) = db::record_peer_traffic(&state.pool, peer.peer_id, sent as i64, received as i64).await {
+        error!("Failed to record traffic with peer {}: {}", peer.peer_id, e);
+    }
+}
+
+/// Records the relay id a peer reported, so later batches skip events it has already held.
+async fn learn_relay_id(state: &AppState, peer: &mut db::Peer, relay_id: Uuid) {
+    if peer.remote_relay_id == Some(relay_id) {
+        return;
+    }
+    if let Err(e) = db::update_peer_relay_id(&state.pool, peer.peer_id, relay_id).await {
+        error!("Failed to record relay id of peer {}: {}", peer.peer_id, e);
+        return;
+    }
+    peer.remote_relay_id = Some(relay_id);
+}
+
+/// Pulls from `peer` until it reports no more events.
+async fn drain_pull(ctx: &PeerTaskContext, peer: &mut db::Peer) -> Result<(), PeerError> {
+    loop {
+        let Ok(_permit) = ctx.permits.acquire().await else { return Ok(()) };
+        if !pull_batch(&ctx.state, &ctx.client, peer).await? {
+            return Ok(());
+        }
+    }
+}
+
+/// Fetches the next batch from the peer's `/relay/replicate/pull`, stores it through the
+/// regular validation path and advances the stored remote cursor. Returns `has_more`.
+async fn pull_batch(state: &AppState, client: &reqwest::Client, peer: &mut db::Peer) -> Result<bool, PeerError> {
+    let res = client.get(format!("{}/relay/replicate/pull", peer.url))
+        .query(&[("after", peer.remote_cursor), ("limit", peer.batch_size as i64)])
+        .header("X-Peer-Token", &peer.shared_secret)
+        .header("X-Relay-Id", state.relay_id.to_string())
+        .send()
+        .await;
+
+    let batch: ReplicatePullResp = match res {
+        Ok(resp) if resp.status().is_success() => match resp.json().await {
+            Ok(b) => b,
+            Err(e) => return Err(PeerError::Remote(format!("pull: invalid response: {}", e))),
+        },
+        Ok(resp) => return Err(PeerError::Remote(format!("pull: status {}", resp.status()))),
+        Err(e) => return Err(PeerError::Remote(format!("pull: {}", e))),
+    };
+    record_success(state, peer).await;
+
+    if let Some(rid) = batch.relay_id {
+        learn_relay_id(state, peer, rid).await;
+    }
+    let events = inbound_events(state, peer, batch.events, 1).events;
+    let count = events.len();
+    // Anything worth retrying keeps the remote cursor where it is
+    match store_inbound(state, peer, events).await {
+        Ok(None) => {},
+        Ok(Some(reason)) => return Err(PeerError::Deferred(format!("pull: {}", reason))),
+        Err(e) => {
+            error!("Failed to store pulled batch from peer {}: {}", peer.peer_id, e);
+            return Err(PeerError::Local);
+        },
+    }
+    if let Err(e) = db::update_peer_remote_cursor(&state.pool, peer.peer_id, batch.next_cursor).await {
+        error!("Failed to update remote cursor for peer {}: {}", peer.peer_id, e);
+        return Err(PeerError::Local);
+    }
+    peer.remote_cursor = batch.next_cursor;
+    if count > 0 {
+        record_traffic(state, peer, 0, count).await;
+        info!("Pulled {} events from peer {}", count, peer.peer_id);
+    }
+    Ok(batch.has_more)
+}
+
+/// Stores events received from `peer` one by one. Invalid events are quarantined so they
+/// don't block the rest; returns why the first event refused only for now was refused.
+pub async fn store_inbound(state: &AppState, peer: &db::Peer, events: Vec<db::ReplicatedEvent>) -> Result<Option<String>, sqlx::Error> {
+    let received: HashMap<Uuid, db::ReplicatedEvent> = events.iter().map(|ev| (ev.event.event_id, ev.clone())).collect();
+    let outcomes = ingest::ingest_each_replicated(&state.pool, &state.ephemeral, events).await?;
+    let mut deferred = None;
+    for outcome in outcomes.iter().filter(|o| o.status == OutcomeStatus::Rejected) {
+        let error = outcome.error.as_deref().unwrap_or("rejected");
+        if outcome.retryable {
+            deferred.get_or_insert_with(|| format!("event {} deferred: {}", outcome.event_id, error));
+            continue;
+        }
+        let Some(ev) = received.get(&outcome.event_id) else { continue };
+        db::quarantine_event(&state.pool, peer.peer_id, db::QUARANTINE_INBOUND, ev, error).await?;
+        warn!("Rejected event {} from peer {} ({}); quarantined", outcome.event_id, peer.peer_id, error);
+    }
+    Ok(deferred)
+}
@@ -0,0 +1,93 @@
+// ----- SERVER-SENT EVENTS -----
+//
+// GET /relay/stream emits one SSE message per event with `id:` set to `server_seq`,
+// so EventSource reconnects resume via the Last-Event-ID header.
+
+use std::convert::Infallible;
+use std::time::Duration;
+
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{sse::{Event as SseEvent, KeepAlive, Sse}, IntoResponse},
+    Json,
+};
+use futures::stream;
+use serde::Deserialize;
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+use tisane_relay::cursor::Order;
+use tisane_relay::filter::EventFilter;
+use tisane_relay::hub::{self, Delivery, FollowError};
+
+use crate::AppState;
+
+/// Events buffered per stream before the client is considered too slow and disconnected
+const STREAM_BUFFER: usize = 256;
+const KEEPALIVE_SECS: u64 = 15;
+
+#[derive(Deserialize)]
+pub struct StreamQuery {
+    since: Option<i64>,
+    cursor: Option<String>,
+    author: Option<String>,
+    event_type: Option<String>,
+    content_id: Option<String>,
+}
+
+pub async fn stream_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(q): Query<StreamQuery>,
+    Query(params): Query<Vec<(String, String)>>,
+) -> impl IntoResponse {
+    let (filter, after) = match q.cursor.as_deref() {
+        Some(token) => match state.cursors.decode(token) {
+            Ok(c) if c.order == Order::Asc && !c.backward => (c.filter, Some(c.seq)),
+            Ok(_) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": "stream needs an ascending forward cursor"}))).into_response(),
+            Err(e) => return (StatusCode::BAD_REQUEST, Json(serde_json::json!({"error": e.to_string()}))).into_response(),
+        },
+        None => (
+            EventFilter::from_query(q.author.as_deref(), q.event_type.as_deref(), q.content_id.as_deref())
+                .with_tag_params(&params),
+            q.since,
+        ),
+    };
+    // A reconnecting EventSource knows best where it stopped
+    let last_event_id = headers.get("Last-Event-ID").and_then(|v| v.to_str().ok());
+
+    let after = hub::resume_position(after, last_event_id);
+
+    let (tx, rx) = mpsc::channel::<Delivery>(STREAM_BUFFER);
+    tokio::spawn(async move {
+        match hub::follow(&state.pool, &state.hub, &filter, after, &tx, |d| d).await {
+            Ok(()) | Err(FollowError::Closed) => {},
+            Err(FollowError::SlowConsumer) => warn!("disconnecting slow SSE consumer"),
+            Err(e) => warn!("SSE stream ended: {}", e),
+        }
+        info!("SSE stream closed");
+        // Dropping tx ends the response once the buffer is drained
+    });
+
+    let events = stream::unfold(rx, |mut rx| async move {
+        let delivery = rx.recv().await?;
+        let sse = match delivery {
+            Delivery::Event(ev) => SseEvent::default()
+                .id(ev.server_seq.to_string())
+                .json_data(&*ev)
+                .unwrap_or_else(|e| SseEvent::default().event("error").data(e.to_string())),
+            // No id: ephemeral events have no position to resume from
+            Delivery::Ephemeral(ev) => SseEvent::default()
+                .event("ephemeral")
+                .json_data(&*ev)
+                .unwrap_or_else(|e| SseEvent::default().event("error").data(e.to_string())),
+            Delivery::EndOfStored => SseEvent::default().event("eose").data(""),
+        };
+        Some((Ok::<_, Infallible>(sse), rx))
+    });
+
+    Sse::new(events)
+        .keep_alive(KeepAlive::new().interval(Duration::from_secs(KEEPALIVE_SECS)).text("keepalive"))
+        .into_response()
+}
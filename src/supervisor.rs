@@ -0,0 +1,71 @@
+// ----- TASK SUPERVISION -----
+//
+// Keeps one task per key (e.g. per peer) and reports the tasks that panicked, so the
+// owner can restart them. Tasks that finish or are aborted are simply forgotten.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+
+use tokio::task::{self, AbortHandle, JoinSet};
+
+pub struct Supervisor<K> {
+    tasks: JoinSet<()>,
+    keys: HashMap<task::Id, K>,
+    current: HashMap<K, AbortHandle>,
+}
+
+impl<K: Copy + Eq + Hash> Default for Supervisor<K> {
+    fn default() -> Self {
+        Supervisor { tasks: JoinSet::new(), keys: HashMap::new(), current: HashMap::new() }
+    }
+}
+
+impl<K: Copy + Eq + Hash> Supervisor<K> {
+    pub fn contains(&self, key: &K) -> bool {
+        self.current.contains_key(key)
+    }
+
+    /// Starts the task for `key`, aborting one that is still running for it.
+    pub fn spawn<F>(&mut self, key: K, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let handle = self.tasks.spawn(task);
+        self.keys.insert(handle.id(), key);
+        if let Some(old) = self.current.insert(key, handle) {
+            old.abort();
+        }
+    }
+
+    /// Aborts the tasks whose key `keep` rejects.
+    pub fn retain(&mut self, mut keep: impl FnMut(&K) -> bool) {
+        self.current.retain(|key, handle| {
+            let kept = keep(key);
+            if !kept {
+                handle.abort();
+            }
+            kept
+        });
+    }
+
+    /// Waits for the next current task to panic and returns its key; the caller decides
+    /// whether to restart it. Returns `None` once no tasks are left. Cancel safe.
+    pub async fn next_panicked(&mut self) -> Option<K> {
+        loop {
+            let (id, panicked) = match self.tasks.join_next_with_id().await? {
+                Ok((id, ())) => (id, false),
+                Err(e) => (e.id(), e.is_panic()),
+            };
+            let Some(key) = self.keys.remove(&id) else { continue };
+            // A task that was replaced or aborted is no longer the current one for its key
+            if self.current.get(&key).is_none_or(|h| h.id() != id) {
+                continue;
+            }
+            self.current.remove(&key);
+            if panicked {
+                return Some(key);
+            }
+        }
+    }
+}
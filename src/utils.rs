@@ -1,6 +1,8 @@
 use infusion::infusion::cid::cid_blake3;
 use serde_json::Value;
 
+use crate::db::EventRef;
+
 /// Computes a canonical payload hash using BLAKE3 via Infusion.
 /// This function is shared between the relay and can be replicated in clients.
 pub fn compute_payload_hash(payload_json: &Option<Value>) -> String {
@@ -15,3 +17,27 @@ pub fn compute_payload_hash(payload_json: &Option<Value>) -> String {
     let hash_bytes = cid_blake3(&payload_bytes);
     hex::encode(hash_bytes)
 }
+
+/// Bytes covered by the author's signature.
+/// Events without refs or tags sign the bare payload string, so existing clients keep working.
+/// Otherwise the signed message is `{"payload":<payload_json>,"refs":[..],"tags":[..]}`,
+/// where `refs` and `tags` are omitted when empty.
+pub fn signing_bytes(payload_json: &Option<Value>, refs: &[EventRef], tags: &[Vec<String>]) -> Vec<u8> {
+    if refs.is_empty() && tags.is_empty() {
+        return match payload_json.as_ref() {
+            Some(p) => p.to_string().into_bytes(),
+            None => vec![],
+        };
+    }
+
+    // Keys are inserted in sorted order, so the output is the same with or without preserve_order
+    let mut msg = serde_json::Map::new();
+    msg.insert("payload".into(), payload_json.clone().unwrap_or(Value::Null));
+    if !refs.is_empty() {
+        msg.insert("refs".into(), serde_json::json!(refs));
+    }
+    if !tags.is_empty() {
+        msg.insert("tags".into(), serde_json::json!(tags));
+    }
+    Value::Object(msg).to_string().into_bytes()
+}
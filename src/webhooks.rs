@@ -0,0 +1,152 @@
+// ----- WEBHOOK DELIVERY -----
+//
+// Each delivery is a POST of {"delivery_id","webhook_id","event"} with headers:
+//   X-Tisane-Delivery:  delivery id
+//   X-Tisane-Timestamp: unix seconds
+//   X-Tisane-Signature: "sha256=<hex>"  HMAC-SHA256(secret, "<timestamp>.<body>")
+//                    or "ed25519=<hex>" relay signature over the same bytes
+//   X-Tisane-Relay-Key: relay public key (ed25519 only)
+
+use std::time::Duration;
+
+use ed25519_dalek::{Signer, SigningKey};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use tracing::{error, info, warn};
+
+use tisane_relay::db;
+
+use crate::AppState;
+
+const ENQUEUE_BATCH: i64 = 500;
+const DELIVERY_BATCH: i64 = 20;
+/// How long a claimed delivery stays invisible to other senders
+const DELIVERY_LEASE_SECS: i64 = 60;
+const DELIVERY_TIMEOUT_SECS: u64 = 10;
+/// Longest sleep without an insert notification; also how soon retries are noticed
+const IDLE_POLL_SECS: u64 = 5;
+
+/// Enqueues matching events for every active webhook, then sends whatever is due.
+pub async fn webhook_worker(state: AppState) {
+    info!("webhook worker started");
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_secs(DELIVERY_TIMEOUT_SECS))
+        .build()
+    {
+        Ok(c) => c,
+        Err(e) => {
+            error!("webhook worker: failed to build HTTP client: {}", e);
+            return;
+        }
+    };
+    let mut head = state.hub.watch_head();
+
+    loop {
+        head.borrow_and_update();
+
+        match db::fetch_all_webhooks(&state.pool).await {
+            Ok(hooks) => {
+                for hook in hooks.iter().filter(|h| h.active) {
+                    // Drain the backlog in bounded scans
+                    loop {
+                        match db::enqueue_webhook_deliveries(&state.pool, hook.webhook_id, ENQUEUE_BATCH).await {
+                            Ok(scanned) if scanned as i64 == ENQUEUE_BATCH => continue,
+                            Ok(_) => break,
+                            Err(e) => {
+                                error!("webhook {}: enqueue failed: {}", hook.webhook_id, e);
+                                break;
+                            }
+                        }
+                    }
+                }
+            },
+            Err(e) => error!("webhook worker: failed to fetch webhooks: {}", e),
+        }
+
+        loop {
+            let due = match db::claim_due_deliveries(&state.pool, DELIVERY_BATCH, DELIVERY_LEASE_SECS).await {
+                Ok(d) => d,
+                Err(e) => {
+                    error!("webhook worker: failed to claim deliveries: {}", e);
+                    break;
+                }
+            };
+            let count = due.len();
+            futures::future::join_all(due.into_iter().map(|d| deliver(&state, &client, d))).await;
+            if (count as i64) < DELIVERY_BATCH {
+                break;
+            }
+        }
+
+        let _ = tokio::time::timeout(Duration::from_secs(IDLE_POLL_SECS), head.changed()).await;
+    }
+}
+
+async fn deliver(state: &AppState, client: &reqwest::Client, delivery: db::WebhookDelivery) {
+    let result = attempt(state, client, &delivery).await;
+    let outcome = match result {
+        Ok(()) => db::mark_delivery_delivered(&state.pool, delivery.delivery_id).await,
+        Err(msg) => {
+            let attempts = delivery.attempts + 1;
+            let dead = attempts >= db::MAX_DELIVERY_ATTEMPTS;
+            if dead {
+                warn!("webhook delivery {} dead-lettered after {} attempts: {}", delivery.delivery_id, attempts, msg);
+            }
+            db::mark_delivery_failed(&state.pool, delivery.delivery_id, &msg, db::delivery_backoff_secs(attempts), dead).await
+        }
+    };
+    if let Err(e) = outcome {
+        error!("webhook delivery {}: failed to record result: {}", delivery.delivery_id, e);
+    }
+}
+
+async fn attempt(state: &AppState, client: &reqwest::Client, delivery: &db::WebhookDelivery) -> Result<(), String> {
+    let hook = db::fetch_webhook(&state.pool, delivery.webhook_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("webhook removed")?;
+    let event = db::fetch_event(&state.pool, delivery.event_id)
+        .await
+        .map_err(|e| e.to_string())?
+        .ok_or("event no longer stored")?;
+
+    let body = serde_json::to_vec(&serde_json::json!({
+        "delivery_id": delivery.delivery_id,
+        "webhook_id": hook.webhook_id,
+        "event": event,
+    }))
+    .map_err(|e| e.to_string())?;
+
+    let timestamp = chrono::Utc::now().timestamp().to_string();
+    let mut signed = Vec::with_capacity(timestamp.len() + 1 + body.len());
+    signed.extend_from_slice(timestamp.as_bytes());
+    signed.push(b'.');
+    signed.extend_from_slice(&body);
+
+    let mut req = client
+        .post(&hook.url)
+        .header("Content-Type", "application/json")
+        .header("X-Tisane-Delivery", delivery.delivery_id.to_string())
+        .header("X-Tisane-Timestamp", &timestamp);
+
+    req = match hook.signing.as_str() {
+        "ed25519" => {
+            let key: &SigningKey = state.signing_key.as_ref().ok_or("relay has no signing key (RELAY_SIGNING_KEY)")?;
+            req.header("X-Tisane-Signature", format!("ed25519={}", hex::encode(key.sign(&signed).to_bytes())))
+                .header("X-Tisane-Relay-Key", hex::encode(key.verifying_key().to_bytes()))
+        },
+        _ => {
+            let secret = hook.secret.as_deref().ok_or("webhook has no HMAC secret")?;
+            let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).map_err(|e| e.to_string())?;
+            mac.update(&signed);
+            req.header("X-Tisane-Signature", format!("sha256={}", hex::encode(mac.finalize().into_bytes())))
+        },
+    };
+
+    let resp = req.body(body).send().await.map_err(|e| e.to_string())?;
+    if resp.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("endpoint returned {}", resp.status()))
+    }
+}
@@ -0,0 +1,192 @@
+// ----- WEBSOCKET SUBSCRIPTIONS -----
+//
+// Client -> relay:
+//   {"type":"subscribe","id":"s1","filter":{...},"cursor":"..."}   (or "since": <server_seq>)
+//   {"type":"unsubscribe","id":"s1"}
+//   {"type":"push","id":"r1","events":[...]}
+// Relay -> client:
+//   {"type":"event","sub":"s1","event":{...}}
+//   {"type":"ephemeral","sub":"s1","event":{...}}  not stored; server_seq 0, nothing to resume from
+//   {"type":"eose","sub":"s1"}                 stored events done, live from here
+//   {"type":"closed","sub":"s1","reason":"..."}
+//   {"type":"ack","id":"r1","results":[{"event_id","status",...}]}
+//   {"type":"error","message":"..."}
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::{
+    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, State},
+    response::IntoResponse,
+};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use tisane_relay::cursor::Order;
+use tisane_relay::db;
+use tisane_relay::filter::EventFilter;
+use tisane_relay::hub::{self, Delivery, FollowError};
+use tisane_relay::ingest::{self, EventOutcome};
+
+use crate::AppState;
+
+const OUTBOUND_BUFFER: usize = 256;
+const MAX_SUBSCRIPTIONS: usize = 32;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ClientMsg {
+    Subscribe {
+        id: String,
+        #[serde(default)]
+        filter: EventFilter,
+        /// Opaque ascending cursor from /relay/pull; its filter replaces `filter`
+        cursor: Option<String>,
+        since: Option<i64>,
+    },
+    Unsubscribe {
+        id: String,
+    },
+    Push {
+        id: String,
+        events: Vec<db::EventInput>,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerMsg {
+    Event { sub: String, event: Arc<db::Event> },
+    Ephemeral { sub: String, event: Arc<db::Event> },
+    Eose { sub: String },
+    Closed { sub: String, reason: String },
+    Ack { id: String, results: Vec<EventOutcome> },
+    Error { message: String },
+}
+
+pub async fn ws_handler(State(state): State<AppState>, ws: WebSocketUpgrade) -> impl IntoResponse {
+    ws.on_upgrade(move |socket| handle_socket(state, socket))
+}
+
+async fn handle_socket(state: AppState, socket: WebSocket) {
+    let (mut sink, mut stream) = socket.split();
+    let (out_tx, mut out_rx) = mpsc::channel::<ServerMsg>(OUTBOUND_BUFFER);
+
+    // Single writer: subscriptions and acks all go through out_tx
+    let writer = tokio::spawn(async move {
+        while let Some(msg) = out_rx.recv().await {
+            let text = match serde_json::to_string(&msg) {
+                Ok(t) => t,
+                Err(e) => {
+                    error!("ws encode error: {}", e);
+                    continue;
+                }
+            };
+            if sink.send(Message::Text(text)).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut subs: HashMap<String, JoinHandle<()>> = HashMap::new();
+
+    while let Some(Ok(msg)) = stream.next().await {
+        let text = match msg {
+            Message::Text(t) => t,
+            Message::Close(_) => break,
+            // Pings are answered by the websocket layer
+            _ => continue,
+        };
+
+        let reply = match serde_json::from_str::<ClientMsg>(&text) {
+            Ok(ClientMsg::Subscribe { id, filter, cursor, since }) => {
+                subs.retain(|_, task| !task.is_finished());
+                // Re-subscribing with the same id replaces the previous subscription
+                if let Some(old) = subs.remove(&id) {
+                    old.abort();
+                }
+                if subs.len() >= MAX_SUBSCRIPTIONS {
+                    Some(ServerMsg::Closed { sub: id, reason: format!("too many subscriptions (max {})", MAX_SUBSCRIPTIONS) })
+                } else {
+                    match start_position(&state, filter, cursor, since) {
+                        Ok((filter, after)) => {
+                            let task = spawn_subscription(state.clone(), id.clone(), filter, after, out_tx.clone());
+                            subs.insert(id, task);
+                            None
+                        },
+                        Err(reason) => Some(ServerMsg::Closed { sub: id, reason }),
+                    }
+                }
+            },
+            Ok(ClientMsg::Unsubscribe { id }) => subs.remove(&id).map(|task| {
+                task.abort();
+                ServerMsg::Closed { sub: id, reason: "unsubscribed".to_string() }
+            }),
+            Ok(ClientMsg::Push { id, events }) => match ingest::ingest_each(&state.pool, &state.ephemeral, events).await {
+                Ok(results) => Some(ServerMsg::Ack { id, results }),
+                Err(e) => {
+                    error!("ws push error: {}", e);
+                    Some(ServerMsg::Error { message: e.to_string() })
+                }
+            },
+            Err(e) => Some(ServerMsg::Error { message: format!("invalid message: {}", e) }),
+        };
+
+        if let Some(reply) = reply {
+            if out_tx.send(reply).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    for (_, task) in subs {
+        task.abort();
+    }
+    writer.abort();
+    info!("ws connection closed");
+}
+
+/// Resolves where a subscription starts: an ascending pull cursor, a raw `server_seq`,
+/// or (with neither) `None`, meaning live events only.
+fn start_position(
+    state: &AppState,
+    filter: EventFilter,
+    cursor: Option<String>,
+    since: Option<i64>,
+) -> Result<(EventFilter, Option<i64>), String> {
+    if let Some(token) = cursor {
+        let c = state.cursors.decode(&token).map_err(|e| e.to_string())?;
+        if c.order != Order::Asc || c.backward {
+            return Err("subscriptions need an ascending forward cursor".to_string());
+        }
+        return Ok((c.filter, Some(c.seq)));
+    }
+    Ok((filter, since))
+}
+
+fn spawn_subscription(
+    state: AppState,
+    id: String,
+    filter: EventFilter,
+    after: Option<i64>,
+    out: mpsc::Sender<ServerMsg>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let result = hub::follow(&state.pool, &state.hub, &filter, after, &out, |delivery| match delivery {
+            Delivery::Event(event) => ServerMsg::Event { sub: id.clone(), event },
+            Delivery::Ephemeral(event) => ServerMsg::Ephemeral { sub: id.clone(), event },
+            Delivery::EndOfStored => ServerMsg::Eose { sub: id.clone() },
+        })
+        .await;
+
+        let reason = match result {
+            Ok(()) => "relay shutting down".to_string(),
+            Err(FollowError::Closed) => return,
+            Err(e) => e.to_string(),
+        };
+        let _ = out.send(ServerMsg::Closed { sub: id, reason }).await;
+    })
+}
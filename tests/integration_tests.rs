@@ -23,9 +23,6 @@ async fn test_push_then_pull() -> anyhow::Result<()> {
 
     db::run_migrations(&pool).await?;
 
-    // Ensure starting clean
-    sqlx::query("TRUNCATE TABLE events").execute(&pool).await?;
-
     // Generate Infusion keypair
     let mut rng = thread_rng();
     let signing_key = SigningKey::generate(&mut rng);
@@ -53,14 +50,18 @@ async fn test_push_then_pull() -> anyhow::Result<()> {
         payload_json,
         occurred_at: Some(Utc::now()),
         lamport: Some(1),
+        refs: vec![],
+        tags: vec![],
+        sig_scheme: db::SIG_SCHEME_ED25519.to_string(),
     };
 
     let inserted = db::insert_events(&pool, &[ev1.clone()]).await?;
     assert_eq!(inserted.len(), 1, "one event should be inserted");
 
-    let (events, next_cursor) = db::fetch_events_since(&pool, 0, 100).await?;
-    assert!(events.len() >= 1);
-    assert!(next_cursor >= 1);
+    // Other tests share the table, so read from just before our event instead of from the start
+    let (events, next_cursor) = db::fetch_events_since(&pool, inserted[0] - 1, 100).await?;
+    assert_eq!(events[0].event_id, ev1.event_id);
+    assert!(next_cursor >= inserted[0]);
 
     Ok(())
 }
@@ -71,7 +72,6 @@ async fn test_dedup() -> anyhow::Result<()> {
     let pool = PgPool::connect(&database_url).await?;
 
     db::run_migrations(&pool).await?;
-    sqlx::query("TRUNCATE TABLE events").execute(&pool).await?;
 
     let mut rng = thread_rng();
     let signing_key = SigningKey::generate(&mut rng);
@@ -93,6 +93,9 @@ async fn test_dedup() -> anyhow::Result<()> {
         payload_json,
         occurred_at: Some(Utc::now()),
         lamport: Some(5),
+        refs: vec![],
+        tags: vec![],
+        sig_scheme: db::SIG_SCHEME_ED25519.to_string(),
     };
 
     let first = db::insert_events(&pool, &[ev.clone()]).await?;
@@ -101,7 +104,7 @@ async fn test_dedup() -> anyhow::Result<()> {
     let second = db::insert_events(&pool, &[ev.clone()]).await?;
     assert_eq!(second.len(), 0, "duplicate insert should be ignored");
 
-    let (events, _) = db::fetch_events_since(&pool, 0, 100).await?;
+    let (events, _) = db::fetch_events_since(&pool, first[0] - 1, 100).await?;
     let count = events.iter().filter(|e| e.event_id == ev.event_id).count();
     assert_eq!(count, 1, "there should be a single persisted event");
 
@@ -118,3 +121,1579 @@ async fn test_hash_consistency() {
     
     assert_eq!(hash, expected_hash, "Hash must be stable and consistent");
 }
+
+#[tokio::test]
+async fn test_cursor_roundtrip_and_tamper() {
+    use tisane_relay::cursor::{Cursor, CursorCodec, CursorError, Order};
+    use tisane_relay::filter::EventFilter;
+
+    let codec = CursorCodec::from_secret("test-secret");
+    let filter = EventFilter::from_query(Some("aa,bb"), Some("message"), None);
+    let cursor = Cursor::new(Order::Desc, true, 42, filter);
+
+    let token = codec.encode(&cursor);
+    assert_eq!(codec.decode(&token).unwrap(), cursor);
+
+    // Flip a character in the body: the MAC must no longer match
+    let mut tampered = token.clone().into_bytes();
+    tampered[0] = if tampered[0] == b'A' { b'B' } else { b'A' };
+    let tampered = String::from_utf8(tampered).unwrap();
+    assert!(codec.decode(&tampered).is_err());
+
+    // A relay with another secret must reject the token
+    let other = CursorCodec::from_secret("other-secret");
+    assert_eq!(other.decode(&token), Err(CursorError::BadSignature));
+}
+
+fn signed_event(signing_key: &SigningKey, payload: serde_json::Value, refs: Vec<db::EventRef>, tags: Vec<Vec<String>>) -> EventInput {
+    let payload_json = Some(payload);
+    let payload_bytes = tisane_relay::utils::signing_bytes(&payload_json, &refs, &tags);
+    EventInput {
+        event_id: Uuid::new_v4(),
+        author_pubkey: hex::encode(signing_key.verifying_key().to_bytes()),
+        signature: hex::encode(sign::sign(signing_key, &payload_bytes)),
+        payload_hash: compute_payload_hash(&payload_json),
+        device_id: None,
+        author_id: None,
+        content_id: None,
+        event_type: Some("message".into()),
+        payload_json,
+        occurred_at: Some(Utc::now()),
+        lamport: None,
+        refs,
+        tags,
+        sig_scheme: db::SIG_SCHEME_ED25519.to_string(),
+    }
+}
+
+/// A peer added for a test, removed again when dropped (also when the test fails)
+struct TestPeer {
+    peer_id: Uuid,
+}
+
+impl Drop for TestPeer {
+    fn drop(&mut self) {
+        // Drop can't await, and a task spawned on the test's runtime may never run once the
+        // test returns, so the peer is removed on a runtime of its own
+        let peer_id = self.peer_id;
+        let removed = std::thread::spawn(move || -> anyhow::Result<()> {
+            tokio::runtime::Builder::new_current_thread().enable_all().build()?.block_on(async {
+                let pool = PgPool::connect(&get_database_url()).await?;
+                db::remove_peer(&pool, peer_id).await?;
+                Ok(())
+            })
+        })
+        .join();
+        if let Ok(Err(e)) = removed {
+            eprintln!("could not remove test peer {}: {}", peer_id, e);
+        }
+    }
+}
+
+/// Adds a peer with `settings` but direction 'none': that keeps a running relay's worker
+/// away from it, so only the test moves its cursor and health.
+async fn add_test_peer(pool: &PgPool, settings: &db::PeerSettings) -> anyhow::Result<TestPeer> {
+    let settings = db::PeerSettings { direction: "none".to_string(), ..settings.clone() };
+    let peer_id = db::add_peer(pool, format!("http://test-{}.invalid", Uuid::new_v4()), Uuid::new_v4().to_string(), &settings).await?;
+    Ok(TestPeer { peer_id })
+}
+
+#[tokio::test]
+async fn test_thread_traversal() -> anyhow::Result<()> {
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+
+    // root <- reply <- reply-to-reply
+    let root = signed_event(&signing_key, serde_json::json!({"text":"root"}), vec![], vec![]);
+    let reply = signed_event(&signing_key, serde_json::json!({"text":"reply"}), vec![
+        db::EventRef { event_id: root.event_id, kind: "reply".into() },
+    ], vec![]);
+    let nested = signed_event(&signing_key, serde_json::json!({"text":"nested"}), vec![
+        db::EventRef { event_id: reply.event_id, kind: "reply".into() },
+    ], vec![]);
+    db::insert_events(&pool, &[root.clone(), reply.clone(), nested.clone()]).await?;
+
+    let down = db::fetch_thread(&pool, root.event_id, 10).await?;
+    let ids: Vec<(i32, Uuid)> = down.descendants.iter().map(|t| (t.depth, t.event.event_id)).collect();
+    assert_eq!(ids, vec![(1, reply.event_id), (2, nested.event_id)]);
+    assert!(down.ancestors.is_empty());
+
+    let up = db::fetch_thread(&pool, nested.event_id, 1).await?;
+    assert_eq!(up.ancestors.len(), 1, "depth limit should stop at the direct parent");
+    assert_eq!(up.ancestors[0].event.refs[0].event_id, root.event_id);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_tag_filter() -> anyhow::Result<()> {
+    use tisane_relay::cursor::Order;
+    use tisane_relay::filter::EventFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let channel = Uuid::new_v4().to_string();
+
+    let tagged = signed_event(&signing_key, serde_json::json!({"text":"in channel"}), vec![], vec![
+        vec!["channel".into(), channel.clone()],
+        vec!["topic".into(), "rust".into()],
+    ]);
+    let other = signed_event(&signing_key, serde_json::json!({"text":"elsewhere"}), vec![], vec![
+        vec!["channel".into(), "another".into()],
+    ]);
+    db::insert_events(&pool, &[tagged.clone(), other]).await?;
+
+    let params = vec![("tag.channel".to_string(), channel.clone())];
+    let filter = EventFilter::default().with_tag_params(&params);
+    let page = db::fetch_events_page(&pool, &filter, Order::Asc, None, false, 100, true).await?;
+    assert_eq!(page.events.len(), 1);
+    assert_eq!(page.events[0].event_id, tagged.event_id);
+
+    // Distinct keys are AND-ed
+    let params = vec![("tag.channel".to_string(), channel), ("tag.topic".to_string(), "go".to_string())];
+    let filter = EventFilter::default().with_tag_params(&params);
+    let page = db::fetch_events_page(&pool, &filter, Order::Asc, None, false, 100, true).await?;
+    assert!(page.events.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_filter_matches_live_events() {
+    use tisane_relay::filter::EventFilter;
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let ev = signed_event(&signing_key, serde_json::json!({"text":"hi"}), vec![], vec![
+        vec!["topic".into(), "rust".into()],
+    ])
+    .to_event(7);
+
+    let params = vec![("tag.topic".to_string(), "go,rust".to_string())];
+    let filter = EventFilter::from_query(None, Some("message"), None).with_tag_params(&params);
+    assert!(filter.matches(&ev));
+
+    let params = vec![("tag.topic".to_string(), "go".to_string())];
+    assert!(!EventFilter::default().with_tag_params(&params).matches(&ev));
+    assert!(!EventFilter::from_query(Some("someone-else"), None, None).matches(&ev));
+}
+
+#[tokio::test]
+async fn test_consumer_claims_and_acks() -> anyhow::Result<()> {
+    use tisane_relay::filter::EventFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let channel = Uuid::new_v4().to_string();
+    let name = format!("test-{}", channel);
+
+    let filter = EventFilter::default().with_tag_params(&[("tag.channel".to_string(), channel.clone())]);
+    let head = db::fetch_replication_log_head(&pool).await?;
+    db::upsert_consumer(&pool, &name, &filter, 1, Some(head)).await?;
+
+    let events: Vec<EventInput> = (0..3)
+        .map(|i| signed_event(&signing_key, serde_json::json!({"n": i}), vec![], vec![vec!["channel".into(), channel.clone()]]))
+        .collect();
+    db::insert_events(&pool, &events).await?;
+    db::sequence_replication_log(&pool).await?;
+
+    // Two workers get disjoint batches
+    let first = db::claim_consumer_batch(&pool, &name, 2).await?.expect("first batch");
+    let second = db::claim_consumer_batch(&pool, &name, 2).await?.expect("second batch");
+    assert_eq!(first.events.len(), 2);
+    assert_eq!(second.events.len(), 1);
+    assert_eq!(second.events[0].event_id, events[2].event_id);
+
+    // Acking the later batch can't move past the earlier one still in flight
+    let acked = db::ack_consumer_claim(&pool, &name, second.claim_id).await?.unwrap();
+    assert_eq!(acked, head);
+
+    // An expired claim is re-issued, and the original holder's ack is refused
+    tokio::time::sleep(std::time::Duration::from_millis(1200)).await;
+    let retry = db::claim_consumer_batch(&pool, &name, 2).await?.expect("re-issued batch");
+    assert_eq!(retry.events.len(), 2);
+    assert_eq!(retry.events[0].event_id, events[0].event_id);
+    assert!(db::ack_consumer_claim(&pool, &name, first.claim_id).await?.is_none());
+
+    let last_position: i64 = sqlx::query_scalar("SELECT repl_seq FROM events WHERE event_id = $1")
+        .bind(events[2].event_id)
+        .fetch_one(&pool)
+        .await?;
+    let acked = db::ack_consumer_claim(&pool, &name, retry.claim_id).await?.unwrap();
+    assert_eq!(acked, last_position);
+    assert!(db::claim_consumer_batch(&pool, &name, 2).await?.is_none());
+
+    let consumer = db::fetch_consumer(&pool, &name).await?.unwrap();
+    assert_eq!(db::fetch_consumer_lag(&pool, &consumer).await?.events, 0);
+
+    db::remove_consumer(&pool, &name).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_consumer_claims_late_commits() -> anyhow::Result<()> {
+    use tisane_relay::filter::EventFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let author = hex::encode(signing_key.verifying_key().to_bytes());
+    let name = format!("test-{}", Uuid::new_v4());
+    let filter = EventFilter::from_query(Some(author.as_str()), None, None);
+    db::upsert_consumer(&pool, &name, &filter, 30, Some(db::fetch_replication_log_head(&pool).await?)).await?;
+
+    // `early` takes the lower server_seq but commits after `late` has been claimed and acked
+    let early = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    let late = signed_event(&signing_key, serde_json::json!({"n": 2}), vec![], vec![]);
+    let mut tx = pool.begin().await?;
+    insert_in_tx(&mut tx, &early).await?;
+    db::insert_event(&pool, &late).await?;
+    db::sequence_replication_log(&pool).await?;
+
+    let claim = db::claim_consumer_batch(&pool, &name, 10).await?.expect("late batch");
+    assert_eq!(claim.events.iter().map(|e| e.event_id).collect::<Vec<_>>(), vec![late.event_id]);
+    db::ack_consumer_claim(&pool, &name, claim.claim_id).await?.unwrap();
+
+    // Committed but not sequenced yet still counts as lag
+    tx.commit().await?;
+    let consumer = db::fetch_consumer(&pool, &name).await?.unwrap();
+    assert_eq!(db::fetch_consumer_lag(&pool, &consumer).await?.events, 1);
+
+    db::sequence_replication_log(&pool).await?;
+    let claim = db::claim_consumer_batch(&pool, &name, 10).await?.expect("early batch");
+    assert_eq!(claim.events.iter().map(|e| e.event_id).collect::<Vec<_>>(), vec![early.event_id]);
+    let acked = db::ack_consumer_claim(&pool, &name, claim.claim_id).await?.unwrap();
+    let consumer = db::fetch_consumer(&pool, &name).await?.unwrap();
+    let lag = db::fetch_consumer_lag(&pool, &consumer).await?;
+    assert_eq!(lag.events, 0);
+    assert!(acked <= lag.head_seq);
+
+    db::remove_consumer(&pool, &name).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_nostr_event_verification() {
+    use tisane_relay::nostr::{NostrEvent, NostrFilter};
+
+    let mut rng = thread_rng();
+    let key = k256::schnorr::SigningKey::random(&mut rng);
+    let mut ev = NostrEvent {
+        id: String::new(),
+        pubkey: hex::encode(key.verifying_key().to_bytes()),
+        created_at: 1_700_000_000,
+        kind: 1,
+        tags: vec![vec!["t".into(), "rust".into()]],
+        content: "hello \"nostr\"\n".into(),
+        sig: String::new(),
+    };
+    ev.id = ev.compute_id();
+    let id_bytes = hex::decode(&ev.id).unwrap();
+    ev.sig = hex::encode(key.sign_raw(&id_bytes, &[7u8; 32]).unwrap().to_bytes());
+    assert!(ev.verify().is_ok());
+
+    let mut input = ev.to_input().unwrap();
+    assert_eq!(input.event_type.as_deref(), Some("nostr:1"));
+    assert!(tisane_relay::ingest::validate_event(&mut input).is_ok());
+
+    // Relay fields can't be changed around a valid Nostr event
+    let mut altered = input.clone();
+    altered.content_id = Some("elsewhere".into());
+    assert!(tisane_relay::ingest::validate_event(&mut altered).is_err());
+
+    let mut tampered = ev.clone();
+    tampered.content = "changed".into();
+    assert!(tampered.verify().is_err());
+
+    let filter: NostrFilter = serde_json::from_value(serde_json::json!({"kinds": [1], "#t": ["rust"]})).unwrap();
+    let filter = filter.to_event_filter().unwrap().unwrap();
+    assert!(filter.matches(&input.to_event(1)));
+
+    // Follow lists carry far more tags than native events may
+    let mut follows = NostrEvent {
+        kind: 3,
+        tags: (0..500).map(|i| vec!["p".into(), format!("{:064x}", i)]).collect(),
+        content: String::new(),
+        ..ev.clone()
+    };
+    follows.id = follows.compute_id();
+    follows.sig = hex::encode(key.sign_raw(&hex::decode(&follows.id).unwrap(), &[7u8; 32]).unwrap().to_bytes());
+    assert!(tisane_relay::ingest::validate_event(&mut follows.to_input().unwrap()).is_ok());
+    let mut native = signed_event(&SigningKey::generate(&mut rng), serde_json::json!({}), vec![], follows.tags.clone());
+    assert!(tisane_relay::ingest::validate_event(&mut native).is_err());
+}
+
+#[tokio::test]
+async fn test_ephemeral_classification_and_rate_limit() {
+    use tisane_relay::ephemeral::Ephemeral;
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let ephemeral = Ephemeral::new(vec!["typing".to_string()], 2);
+
+    let mut ev = signed_event(&signing_key, serde_json::json!({"typing": true}), vec![], vec![]);
+    assert!(!ephemeral.is_ephemeral(&ev));
+    ev.event_type = Some("typing".into());
+    assert!(ephemeral.is_ephemeral(&ev));
+    ev.event_type = Some("nostr:20001".into());
+    assert!(ephemeral.is_ephemeral(&ev));
+    ev.event_type = Some("nostr:1".into());
+    assert!(!ephemeral.is_ephemeral(&ev));
+
+    // Two per minute per author
+    assert!(ephemeral.admit(&ev).is_ok());
+    assert!(ephemeral.admit(&ev).is_ok());
+    let (code, _) = ephemeral.admit(&ev).unwrap_err();
+    assert_eq!(code, axum::http::StatusCode::TOO_MANY_REQUESTS);
+
+    let other = signed_event(&SigningKey::generate(&mut rng), serde_json::json!({}), vec![], vec![]);
+    assert!(ephemeral.admit(&other).is_ok());
+
+    // A batch over any author's budget is refused without charging the others
+    let fresh = signed_event(&SigningKey::generate(&mut rng), serde_json::json!({}), vec![], vec![]);
+    assert!(ephemeral.admit_all([&fresh, &other, &other]).is_err());
+    assert!(ephemeral.admit_all([&fresh, &fresh, &other]).is_ok());
+    assert!(ephemeral.admit(&other).is_err());
+}
+
+#[tokio::test]
+async fn test_latest_events_order_by_occurrence() -> anyhow::Result<()> {
+    use tisane_relay::filter::EventFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let author = hex::encode(signing_key.verifying_key().to_bytes());
+    let current = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    let mut backdated = signed_event(&signing_key, serde_json::json!({"n": 2}), vec![], vec![]);
+    backdated.occurred_at = Some("2000-01-01T00:00:00Z".parse()?);
+    db::insert_events(&pool, &[current.clone(), backdated.clone()]).await?;
+
+    // The backdated event was stored last but occurred first, so a REQ with limit 1 gets the other
+    let filter = EventFilter::from_query(Some(author.as_str()), None, None);
+    let latest = db::fetch_latest_events(&pool, &filter, 1).await?;
+    assert_eq!(latest.iter().map(|e| e.event_id).collect::<Vec<_>>(), vec![current.event_id]);
+
+    let all = db::fetch_latest_events(&pool, &filter, 10).await?;
+    assert_eq!(all.iter().map(|e| e.event_id).collect::<Vec<_>>(), vec![current.event_id, backdated.event_id]);
+    Ok(())
+}
+
+#[test]
+fn test_rate_limiter_partial_take() {
+    use tisane_relay::ephemeral::RateLimiter;
+
+    // A batch larger than the budget gets what fits, and the rest has to wait
+    let limiter = RateLimiter::per_minute(5);
+    assert_eq!(limiter.take_up_to("peer", 3), 3);
+    assert_eq!(limiter.take_up_to("peer", 4), 2);
+    assert_eq!(limiter.take_up_to("peer", 4), 0);
+    assert!(!limiter.check_n("peer", 1));
+    assert_eq!(limiter.take_up_to("other", 10), 5);
+}
+
+#[tokio::test]
+async fn test_replication_cursor_keeps_backdated_events() -> anyhow::Result<()> {
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let test_peer = add_test_peer(&pool, &Default::default()).await?;
+    let peer_id = test_peer.peer_id;
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let recent = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    db::insert_events(&pool, &[recent.clone()]).await?;
+    db::sequence_replication_log(&pool).await?;
+    let cursor: i64 = sqlx::query_scalar("SELECT repl_seq FROM events WHERE event_id = $1")
+        .bind(recent.event_id)
+        .fetch_one(&pool)
+        .await?;
+    db::update_peer_cursor(&pool, peer_id, cursor).await?;
+
+    // Arrives after the peer's cursor but claims to be much older
+    let mut backdated = signed_event(&signing_key, serde_json::json!({"n": 2}), vec![], vec![]);
+    backdated.occurred_at = Some("2000-01-01T00:00:00Z".parse()?);
+    db::insert_events(&pool, &[backdated.clone()]).await?;
+    db::sequence_replication_log(&pool).await?;
+
+    let entries = db::fetch_replication_batch(&pool, Some(peer_id), &Default::default(), cursor, 1000).await?.entries;
+    assert!(entries.iter().any(|e| e.event.event_id == backdated.event_id));
+    assert!(entries.iter().all(|e| e.event.event_id != recent.event_id && e.repl_seq > cursor));
+
+    Ok(())
+}
+
+#[test]
+fn test_provenance_forwarding() {
+    let (a, b, c) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    let mut rng = thread_rng();
+    let ev = signed_event(&SigningKey::generate(&mut rng), serde_json::json!({}), vec![], vec![]);
+
+    // A stores the event locally, forwards it to B, which forwards it to C
+    let at_b = db::Provenance::default().forwarded_by(a);
+    let at_c = at_b.forwarded_by(b);
+    assert_eq!(at_c.origin_relay, Some(a));
+    assert_eq!(at_c.hops, 2);
+    assert_eq!(at_c.path, vec![a, b]);
+    assert!(at_c.has_visited(a) && at_c.has_visited(b) && !at_c.has_visited(c));
+
+    // Provenance travels next to the signed fields
+    let wire = serde_json::to_value(db::ReplicatedEvent { event: ev.clone(), provenance: at_c.clone() }).unwrap();
+    let back: db::ReplicatedEvent = serde_json::from_value(wire).unwrap();
+    assert_eq!(back.provenance, at_c);
+    assert_eq!(back.event.event_id, ev.event_id);
+
+    // Plain events from older relays parse with empty provenance
+    let legacy: db::ReplicatedEvent = serde_json::from_value(serde_json::to_value(&ev).unwrap()).unwrap();
+    assert_eq!(legacy.provenance, db::Provenance::default());
+}
+
+#[tokio::test]
+async fn test_peer_health_backoff() -> anyhow::Result<()> {
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let test_peer = add_test_peer(&pool, &Default::default()).await?;
+    let peer_id = test_peer.peer_id;
+    let backoff = db::PeerBackoff { base_secs: 5, max_secs: 60, down_after: 3 };
+
+    let peer = db::record_peer_failure(&pool, peer_id, "connection refused", backoff).await?;
+    assert_eq!(peer.health, db::PEER_HEALTH_DEGRADED);
+    assert_eq!(peer.consecutive_failures, 1);
+    assert_eq!(peer.last_error.as_deref(), Some("connection refused"));
+    let first_wait = peer.next_attempt_at.unwrap() - peer.last_error_at.unwrap();
+    assert!(peer.backing_off(Utc::now()));
+
+    db::record_peer_failure(&pool, peer_id, "timeout", backoff).await?;
+    let peer = db::record_peer_failure(&pool, peer_id, "timeout", backoff).await?;
+    assert!(peer.is_down());
+    assert!(peer.next_attempt_at.unwrap() - peer.last_error_at.unwrap() > first_wait);
+
+    // A probe brings it back one failure short of down; a real exchange makes it healthy
+    let peer = db::record_peer_probe_ok(&pool, peer_id, backoff).await?;
+    assert_eq!(peer.health, db::PEER_HEALTH_DEGRADED);
+    assert!(!peer.backing_off(Utc::now()));
+    assert!(db::record_peer_failure(&pool, peer_id, "timeout", backoff).await?.is_down());
+
+    let peer = db::record_peer_success(&pool, peer_id).await?;
+    assert_eq!(peer.health, db::PEER_HEALTH_HEALTHY);
+    assert_eq!(peer.consecutive_failures, 0);
+    assert!(peer.last_success_at.is_some() && peer.next_attempt_at.is_none());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_range_fingerprints() -> anyhow::Result<()> {
+    use db::IdRange;
+    use tisane_relay::filter::OutboundFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    // IDs under a random 64-bit prefix, so other tests' events don't land in the range
+    let prefix = rand::random::<u64>() as u128 >> 1;
+    let range = IdRange { lower: Uuid::from_u128(prefix << 64), upper: Some(Uuid::from_u128((prefix + 1) << 64)) };
+    let unfiltered = OutboundFilter::default();
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let events: Vec<EventInput> = (0..40u128)
+        .map(|i| {
+            let mut ev = signed_event(&signing_key, serde_json::json!({"n": i as u64}), vec![], vec![]);
+            ev.event_id = Uuid::from_u128((prefix << 64) | (i * 7919));
+            ev
+        })
+        .collect();
+    db::insert_events(&pool, &events[..39]).await?;
+
+    let before = db::summarize_range(&pool, range, &unfiltered).await?;
+    assert_eq!(before.count, 39);
+
+    // Sub-ranges tile the range and their fingerprints combine to the whole
+    let parts = db::split_range(&pool, range, 4, &unfiltered).await?;
+    assert_eq!(parts.len(), 4);
+    assert_eq!(parts[0].0.lower, range.lower);
+    assert_eq!(parts[3].0.upper, range.upper);
+    assert!(parts.windows(2).all(|w| w[0].0.upper == Some(w[1].0.lower)));
+    assert_eq!(parts.iter().map(|(_, s)| s.count).sum::<i64>(), 39);
+    assert_eq!(parts.iter().fold(0, |fp, (_, s)| fp ^ s.fingerprint), before.fingerprint);
+
+    // One more event changes the fingerprint, and it shows up in exactly one sub-range
+    db::insert_events(&pool, &events[39..]).await?;
+    let after = db::summarize_range(&pool, range, &unfiltered).await?;
+    assert_eq!(after.count, 40);
+    assert_ne!(after.fingerprint, before.fingerprint);
+    let mut changed = 0;
+    for (sub, summary) in &parts {
+        if db::summarize_range(&pool, *sub, &unfiltered).await? != *summary {
+            changed += 1;
+            assert!(db::fetch_ids_in_range(&pool, *sub, &unfiltered).await?.contains(&events[39].event_id));
+        }
+    }
+    assert_eq!(changed, 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reconcile_ignores_filtered_out_events() -> anyhow::Result<()> {
+    use sqlx::postgres::PgConnectOptions;
+    use tisane_relay::filter::OutboundFilter;
+    use tisane_relay::fingerprints::{self, Divergence};
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    // The peer's store is a schema of its own in the same database
+    let schema = format!("peer_{}", Uuid::new_v4().simple());
+    sqlx::query(&format!("CREATE SCHEMA {}", schema)).execute(&pool).await?;
+    let peer_pool = PgPool::connect_with(database_url.parse::<PgConnectOptions>()?.options([("search_path", schema.as_str())])).await?;
+    db::run_migrations(&peer_pool).await?;
+
+    // Types of our own, so events of concurrent tests don't pass the filters
+    let (shared_type, their_type) = (format!("shared-{}", Uuid::new_v4()), format!("theirs-{}", Uuid::new_v4()));
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let typed = |n: u64, event_type: &str| {
+        let mut ev = signed_event(&signing_key, serde_json::json!({"n": n}), vec![], vec![]);
+        ev.event_type = Some(event_type.to_string());
+        ev
+    };
+    let common = typed(1, &shared_type);
+    // Only sent from the peer to us, and we hold it already
+    let relayed = typed(2, &their_type);
+    db::insert_events(&pool, &[common.clone(), relayed.clone(), typed(3, "message")]).await?;
+    db::insert_events(&peer_pool, &[common.clone(), relayed.clone(), typed(4, "message")]).await?;
+
+    let mut ours = OutboundFilter::default();
+    ours.filter.event_types = vec![shared_type.clone()];
+    let mut theirs = OutboundFilter::default();
+    theirs.filter.event_types = vec![shared_type.clone(), their_type.clone()];
+
+    async fn diff(pool: &PgPool, ours: &OutboundFilter, peer_pool: &PgPool, theirs: &OutboundFilter) -> anyhow::Result<Divergence> {
+        fingerprints::compare(pool, ours, move |req| async move { fingerprints::answer(peer_pool, theirs, req).await.map_err(|e| e.to_string()) })
+            .await
+            .map_err(anyhow::Error::msg)
+    }
+
+    // Each side holds a "message" the other lacks, but neither sends those
+    let in_sync = diff(&pool, &ours, &peer_pool, &theirs).await;
+    // A real gap still shows up
+    let missing = typed(5, &shared_type);
+    db::insert_events(&pool, &[missing.clone()]).await?;
+    let behind = diff(&pool, &ours, &peer_pool, &theirs).await;
+
+    peer_pool.close().await;
+    sqlx::query(&format!("DROP SCHEMA {} CASCADE", schema)).execute(&pool).await?;
+
+    assert!(in_sync?.is_empty());
+    let behind = behind?;
+    assert!(behind.missing_local.is_empty());
+    assert_eq!(behind.missing_remote, vec![missing.event_id]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_outbound_filter() -> anyhow::Result<()> {
+    use tisane_relay::filter::OutboundFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut rng = thread_rng();
+    let (allowed_key, denied_key) = (SigningKey::generate(&mut rng), SigningKey::generate(&mut rng));
+    // A type of our own, so events of concurrent tests don't pass the filter
+    let event_type = format!("filtered-{}", Uuid::new_v4());
+    let mut filter = OutboundFilter { deny_authors: vec![hex::encode(denied_key.verifying_key().to_bytes())], max_age_secs: Some(3600), ..Default::default() };
+    filter.filter.event_types = vec![event_type.clone()];
+
+    let settings = db::PeerSettings { outbound_filter: filter.clone(), ..Default::default() };
+    let test_peer = add_test_peer(&pool, &settings).await?;
+    let peer_id = test_peer.peer_id;
+    let peer = db::fetch_peer(&pool, peer_id).await?.expect("peer");
+    assert_eq!(peer.outbound_filter.0, filter);
+
+    db::sequence_replication_log(&pool).await?;
+    let cursor: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(repl_seq), 0) FROM events").fetch_one(&pool).await?;
+
+    let typed = |key: &SigningKey, n: u64, event_type: &str| {
+        let mut ev = signed_event(key, serde_json::json!({"n": n}), vec![], vec![]);
+        ev.event_type = Some(event_type.to_string());
+        ev
+    };
+    let wanted = typed(&allowed_key, 1, &event_type);
+    let other_type = typed(&allowed_key, 2, "message");
+    let denied = typed(&denied_key, 3, &event_type);
+    let mut stale = typed(&allowed_key, 4, &event_type);
+    stale.occurred_at = Some("2000-01-01T00:00:00Z".parse()?);
+    let all = [other_type, denied, stale, wanted.clone()];
+    db::insert_events(&pool, &all).await?;
+    db::sequence_replication_log(&pool).await?;
+
+    let log = db::fetch_replication_batch(&pool, Some(peer_id), &peer.outbound_filter, cursor, 1000).await?;
+    assert_eq!(log.entries.iter().map(|e| e.event.event_id).collect::<Vec<_>>(), vec![wanted.event_id]);
+    // Reconciliation only fingerprints events the filter lets through
+    let ids: Vec<Uuid> = all.iter().map(|e| e.event_id).collect();
+    assert_eq!(db::fetch_ids_passing(&pool, &peer.outbound_filter, &ids).await?, vec![wanted.event_id]);
+    assert_eq!(db::fetch_ids_in_range(&pool, db::IdRange::ALL, &peer.outbound_filter).await?, vec![wanted.event_id]);
+    let now = Utc::now();
+    for ev in &all {
+        let stored = db::fetch_events_with_provenance(&pool, &[ev.event_id]).await?;
+        assert_eq!(filter.matches(&stored[0].0, now), ev.event_id == wanted.event_id);
+    }
+
+    // A short batch moves the cursor past every filtered-out event, so they aren't scanned again
+    let last: i64 = sqlx::query_scalar("SELECT MAX(repl_seq) FROM events WHERE event_id = ANY($1)")
+        .bind(all.iter().map(|e| e.event_id).collect::<Vec<_>>())
+        .fetch_one(&pool)
+        .await?;
+    assert!(log.scanned_to >= last);
+    let again = db::fetch_replication_batch(&pool, Some(peer_id), &peer.outbound_filter, log.scanned_to, 1000).await?;
+    assert!(again.entries.is_empty());
+
+    // A full batch only vouches for what it returned
+    let full = db::fetch_replication_batch(&pool, Some(peer_id), &peer.outbound_filter, cursor, 1).await?;
+    assert_eq!(full.scanned_to, full.entries[0].repl_seq);
+
+    // Updating the filter keeps the cursor
+    let mut updated = peer.settings();
+    updated.outbound_filter = OutboundFilter::default();
+    let peer = db::update_peer_settings(&pool, peer_id, &updated).await?.expect("peer");
+    assert!(peer.outbound_filter.is_empty());
+    assert_eq!(peer.last_cursor_seq, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_inbound_policy_suspension() -> anyhow::Result<()> {
+    use tisane_relay::filter::InboundPolicy;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut rng = thread_rng();
+    let allowed_key = SigningKey::generate(&mut rng);
+    let policy = InboundPolicy {
+        event_types: vec!["message".to_string()],
+        authors: vec![hex::encode(allowed_key.verifying_key().to_bytes())],
+        max_batch_events: Some(10),
+        ..Default::default()
+    };
+    let mut ev = signed_event(&allowed_key, serde_json::json!({}), vec![], vec![]);
+    assert!(policy.admits(&ev));
+    ev.event_type = Some("reaction".to_string());
+    assert!(!policy.admits(&ev));
+    assert!(!policy.admits(&signed_event(&SigningKey::generate(&mut rng), serde_json::json!({}), vec![], vec![])));
+
+    let settings = db::PeerSettings { inbound_policy: policy.clone(), ..Default::default() };
+    let test_peer = add_test_peer(&pool, &settings).await?;
+    let peer_id = test_peer.peer_id;
+    let peer = db::fetch_peer(&pool, peer_id).await?.expect("peer");
+    assert_eq!(peer.inbound_policy.0, policy);
+
+    // Read-only peers are never pulled from
+    let mut read_only = peer.settings();
+    read_only.inbound_policy.read_only = true;
+    let mut peer = db::update_peer_settings(&pool, peer_id, &read_only).await?.expect("peer");
+    assert!(peer.inbound_policy.read_only);
+    peer.direction = "both".to_string();
+    assert!(peer.pushes() && !peer.pulls());
+
+    // The third violation within the hour suspends the peer
+    for n in 1..=3 {
+        let peer = db::record_peer_violation(&pool, peer_id, "peer is read-only", 3).await?;
+        assert_eq!(peer.policy_violations, n);
+        assert_eq!(peer.is_suspended(), n == 3);
+        assert_eq!(peer.last_violation.as_deref(), Some("peer is read-only"));
+    }
+    assert!(db::fetch_healthy_peers(&pool).await?.iter().all(|p| p.peer_id != peer_id));
+
+    // Violations older than an hour no longer count
+    sqlx::query("UPDATE peers SET last_violation_at = NOW() - INTERVAL '2 hours' WHERE peer_id = $1")
+        .bind(peer_id)
+        .execute(&pool)
+        .await?;
+    let peer = db::unsuspend_peer(&pool, peer_id).await?.expect("peer");
+    assert!(!peer.is_suspended());
+    let peer = db::record_peer_violation(&pool, peer_id, "1 events outside the inbound policy", 3).await?;
+    assert_eq!(peer.policy_violations, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_peer_status() -> anyhow::Result<()> {
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    // Pushes only events of its own type, so the pending count isn't disturbed by other tests
+    let mut settings = db::PeerSettings::default();
+    settings.outbound_filter.filter.event_types = vec![format!("status-{}", Uuid::new_v4())];
+    let test_peer = add_test_peer(&pool, &settings).await?;
+    let peer_id = test_peer.peer_id;
+    let mut peer = db::fetch_peer(&pool, peer_id).await?.expect("peer");
+    assert!(serde_json::to_value(&peer)?.get("shared_secret").is_none());
+
+    let status = db::fetch_peer_status(&pool, &peer).await?;
+    assert_eq!(status.pending_events, None);
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let events: Vec<EventInput> = (0..3u64)
+        .map(|n| {
+            let mut ev = signed_event(&signing_key, serde_json::json!({"n": n}), vec![], vec![]);
+            ev.event_type = Some(settings.outbound_filter.filter.event_types[0].clone());
+            ev
+        })
+        .collect();
+    db::insert_events(&pool, &events).await?;
+
+    peer.direction = "push".to_string();
+    let status = db::fetch_peer_status(&pool, &peer).await?;
+    assert_eq!(status.pending_events, Some(3));
+    assert!(status.lag_secs.is_some_and(|s| s >= 0));
+
+    // The lag follows the oldest pending event
+    sqlx::query("UPDATE events SET received_at = NOW() - INTERVAL '1 hour' WHERE event_id = $1")
+        .bind(events[0].event_id)
+        .execute(&pool)
+        .await?;
+    let status = db::fetch_peer_status(&pool, &peer).await?;
+    assert!(status.lag_secs.is_some_and(|s| s >= 3600));
+
+    db::record_peer_traffic(&pool, peer_id, 3, 0).await?;
+    db::record_peer_traffic(&pool, peer_id, 2, 4).await?;
+    let status = db::fetch_peer_status(&pool, &peer).await?;
+    assert_eq!((status.sent_last_5m, status.received_last_5m), (5, 4));
+    assert_eq!((status.sent_last_hour, status.received_last_hour), (5, 4));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replicated_batch_outcomes_and_quarantine() -> anyhow::Result<()> {
+    use tisane_relay::ephemeral::Ephemeral;
+    use tisane_relay::ingest::{self, OutcomeStatus};
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let good = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    let mut forged = signed_event(&signing_key, serde_json::json!({"n": 2}), vec![], vec![]);
+    forged.payload_json = Some(serde_json::json!({"n": 3}));
+    let after = signed_event(&signing_key, serde_json::json!({"n": 4}), vec![], vec![]);
+
+    // The forged event is refused for good without failing the events around it
+    let batch: Vec<db::ReplicatedEvent> = [good.clone(), forged.clone(), after.clone(), good.clone()]
+        .into_iter()
+        .map(db::ReplicatedEvent::local)
+        .collect();
+    let ephemeral = Ephemeral::new(Vec::new(), 10);
+    let outcomes = ingest::ingest_each_replicated(&pool, &ephemeral, batch.clone()).await?;
+    let statuses: Vec<_> = outcomes.iter().map(|o| (o.status, o.retryable)).collect();
+    assert_eq!(statuses, vec![
+        (OutcomeStatus::Accepted, false),
+        (OutcomeStatus::Rejected, false),
+        (OutcomeStatus::Accepted, false),
+        (OutcomeStatus::Duplicate, false),
+    ]);
+
+    let test_peer = add_test_peer(&pool, &Default::default()).await?;
+    let peer_id = test_peer.peer_id;
+    let error = outcomes[1].error.clone().expect("rejection reason");
+    db::quarantine_event(&pool, peer_id, db::QUARANTINE_OUTBOUND, &batch[1], &error).await?;
+    // A repeat refusal doesn't duplicate the entry
+    db::quarantine_event(&pool, peer_id, db::QUARANTINE_OUTBOUND, &batch[1], &error).await?;
+
+    let quarantined = db::fetch_quarantine(&pool, Some(peer_id), 10).await?;
+    assert_eq!(quarantined.len(), 1);
+    assert_eq!(quarantined[0].event_id, forged.event_id);
+    assert_eq!(quarantined[0].event.event.payload_json, forged.payload_json);
+    assert_eq!(quarantined[0].error, error);
+    let peer = db::fetch_peer(&pool, peer_id).await?.expect("peer");
+    assert_eq!(db::fetch_peer_status(&pool, &peer).await?.quarantined, 1);
+
+    drop(test_peer);
+    assert!(db::fetch_quarantine(&pool, Some(peer_id), 10).await?.is_empty());
+    Ok(())
+}
+
+// Insert an event inside a transaction the caller commits later, to interleave commits
+async fn insert_in_tx(tx: &mut sqlx::Transaction<'_, sqlx::Postgres>, ev: &EventInput) -> Result<i64, sqlx::Error> {
+    sqlx::query_scalar("INSERT INTO events (event_id, author_pubkey, signature, payload_hash, event_type, payload_json, occurred_at, sig_scheme) VALUES ($1,$2,$3,$4,$5,$6,$7,$8) RETURNING server_seq")
+        .bind(ev.event_id)
+        .bind(&ev.author_pubkey)
+        .bind(&ev.signature)
+        .bind(&ev.payload_hash)
+        .bind(&ev.event_type)
+        .bind(&ev.payload_json)
+        .bind(ev.occurred_at)
+        .bind(&ev.sig_scheme)
+        .fetch_one(&mut **tx)
+        .await
+}
+
+#[tokio::test]
+async fn test_hub_publishes_in_commit_order() -> anyhow::Result<()> {
+    use std::time::Duration;
+    use tisane_relay::filter::EventFilter;
+    use tisane_relay::hub::{self, Delivery, EventHub, Published};
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let hub = EventHub::new();
+    let mut rx = hub.subscribe();
+    tokio::spawn(hub::run_listener(pool.clone(), hub.clone()));
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let author = hex::encode(signing_key.verifying_key().to_bytes());
+    let early = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    let late = signed_event(&signing_key, serde_json::json!({"n": 2}), vec![], vec![]);
+
+    // `early` takes the lower server_seq but commits after `late`
+    let mut tx = pool.begin().await?;
+    let early_seq = insert_in_tx(&mut tx, &early).await?;
+    let late_seq = db::insert_event(&pool, &late).await?.expect("new event");
+    assert!(early_seq < late_seq);
+
+    async fn next_of(rx: &mut tokio::sync::broadcast::Receiver<Published>, event_id: Uuid) -> anyhow::Result<Published> {
+        loop {
+            let published = tokio::time::timeout(Duration::from_secs(10), rx.recv()).await??;
+            if published.event.event_id == event_id {
+                return Ok(published);
+            }
+        }
+    }
+
+    let published_late = next_of(&mut rx, late.event_id).await?;
+
+    // A subscriber that has already seen `late` still gets `early` once it commits
+    let filter = EventFilter::from_query(Some(author.as_str()), None, None);
+    let (out, mut deliveries) = tokio::sync::mpsc::channel(16);
+    let follower = {
+        let (pool, hub) = (pool.clone(), hub.clone());
+        tokio::spawn(async move { hub::follow(&pool, &hub, &filter, Some(late_seq), &out, |d| d).await })
+    };
+    assert!(matches!(tokio::time::timeout(Duration::from_secs(10), deliveries.recv()).await?, Some(Delivery::EndOfStored)));
+
+    tx.commit().await?;
+    let published_early = next_of(&mut rx, early.event_id).await?;
+    assert!(published_early.repl_seq > published_late.repl_seq);
+    match tokio::time::timeout(Duration::from_secs(10), deliveries.recv()).await? {
+        Some(Delivery::Event(ev)) => assert_eq!(ev.event_id, early.event_id),
+        other => panic!("expected the late commit, got {:?}", other),
+    }
+
+    follower.abort();
+    Ok(())
+}
+
+#[test]
+fn test_webhook_delivery_backoff() {
+    assert_eq!(db::delivery_backoff_secs(1), 10);
+    assert_eq!(db::delivery_backoff_secs(2), 20);
+    assert_eq!(db::delivery_backoff_secs(4), 80);
+    assert_eq!(db::delivery_backoff_secs(0), 10);
+    assert_eq!(db::delivery_backoff_secs(db::MAX_DELIVERY_ATTEMPTS), 3600);
+    assert_eq!(db::delivery_backoff_secs(i32::MAX), 3600);
+}
+
+#[tokio::test]
+async fn test_webhook_enqueue_claim_and_dead_letter() -> anyhow::Result<()> {
+    use tisane_relay::filter::EventFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let author = hex::encode(signing_key.verifying_key().to_bytes());
+    let filter = EventFilter::from_query(Some(author.as_str()), None, None);
+    let webhook_id = db::add_webhook(&pool, format!("http://test-{}.invalid", Uuid::new_v4()), "hmac".into(), Some("secret".into()), &filter).await?;
+
+    async fn enqueue_all(pool: &PgPool, webhook_id: Uuid) -> anyhow::Result<()> {
+        db::sequence_replication_log(pool).await?;
+        while db::enqueue_webhook_deliveries(pool, webhook_id, 100).await? == 100 {}
+        Ok(())
+    }
+    async fn queued(pool: &PgPool, webhook_id: Uuid) -> anyhow::Result<Vec<Uuid>> {
+        Ok(sqlx::query_scalar("SELECT event_id FROM webhook_deliveries WHERE webhook_id = $1")
+            .bind(webhook_id)
+            .fetch_all(pool)
+            .await?)
+    }
+
+    // `early` takes the lower server_seq but commits after the cursor has passed `late`
+    let early = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    let late = signed_event(&signing_key, serde_json::json!({"n": 2}), vec![], vec![]);
+    let other = signed_event(&SigningKey::generate(&mut thread_rng()), serde_json::json!({"n": 3}), vec![], vec![]);
+    let mut tx = pool.begin().await?;
+    insert_in_tx(&mut tx, &early).await?;
+    db::insert_events(&pool, &[late.clone(), other.clone()]).await?;
+    enqueue_all(&pool, webhook_id).await?;
+    assert_eq!(queued(&pool, webhook_id).await?, vec![late.event_id]);
+
+    tx.commit().await?;
+    enqueue_all(&pool, webhook_id).await?;
+    let mut ids = queued(&pool, webhook_id).await?;
+    ids.sort();
+    let mut expected = vec![early.event_id, late.event_id];
+    expected.sort();
+    assert_eq!(ids, expected);
+
+    // Claimed deliveries are leased, so a second claim doesn't hand them out again
+    let claimed: Vec<_> = db::claim_due_deliveries(&pool, 1000, 60).await?.into_iter().filter(|d| d.webhook_id == webhook_id).collect();
+    assert_eq!(claimed.len(), 2);
+    assert!(db::claim_due_deliveries(&pool, 1000, 60).await?.iter().all(|d| d.webhook_id != webhook_id));
+
+    let (failing, delivered) = (&claimed[0], &claimed[1]);
+    db::mark_delivery_delivered(&pool, delivered.delivery_id).await?;
+    db::mark_delivery_failed(&pool, failing.delivery_id, "HTTP 500", db::delivery_backoff_secs(1), false).await?;
+    assert!(db::fetch_dead_deliveries(&pool, Some(webhook_id), 10).await?.is_empty());
+    db::mark_delivery_failed(&pool, failing.delivery_id, "HTTP 500", db::delivery_backoff_secs(2), true).await?;
+    let dead = db::fetch_dead_deliveries(&pool, Some(webhook_id), 10).await?;
+    assert_eq!(dead.len(), 1);
+    assert_eq!((dead[0].delivery_id, dead[0].attempts), (failing.delivery_id, 2));
+    assert_eq!(dead[0].last_error.as_deref(), Some("HTTP 500"));
+
+    // Redelivery requeues the dead letter with a fresh budget and leaves delivered ones alone
+    assert_eq!(db::redeliver(&pool, None, Some(webhook_id)).await?, 1);
+    let claimed: Vec<_> = db::claim_due_deliveries(&pool, 1000, 60).await?.into_iter().filter(|d| d.webhook_id == webhook_id).collect();
+    assert_eq!(claimed.len(), 1);
+    assert_eq!((claimed[0].delivery_id, claimed[0].attempts), (failing.delivery_id, 0));
+
+    db::remove_webhook(&pool, webhook_id).await?;
+    Ok(())
+}
+
+#[test]
+fn test_acknowledge_replicated_batch() {
+    use tisane_relay::ingest::{acknowledge, ReplicateOutcome, ReplicateStatus};
+
+    let ids: Vec<Uuid> = (0..4).map(|_| Uuid::new_v4()).collect();
+    let sent: Vec<(Uuid, i64)> = ids.iter().copied().zip([11, 12, 15, 16]).collect();
+    let outcome = |i: usize, status, error: Option<&str>| ReplicateOutcome { event_id: ids[i], status, error: error.map(str::to_string) };
+
+    // Relays without per-event outcomes: a 2xx covers the whole batch
+    let ack = acknowledge(&sent, None);
+    assert!(ack.rejected.is_empty() && ack.held_back.is_none());
+    assert_eq!(ack.cursor(20), 20);
+
+    // Events refused for good are quarantined and passed; the cursor stops just before
+    // the first event refused for now, even if later ones were stored
+    let ack = acknowledge(&sent, Some(vec![
+        outcome(0, ReplicateStatus::Stored, None),
+        outcome(1, ReplicateStatus::Rejected, Some("invalid signature")),
+        outcome(2, ReplicateStatus::Retry, Some("rate limited")),
+        outcome(3, ReplicateStatus::Stored, None),
+    ]));
+    assert_eq!(ack.rejected, vec![(ids[1], "invalid signature".to_string())]);
+    assert_eq!(ack.held_back.as_ref().map(|(seq, _)| *seq), Some(15));
+    assert_eq!(ack.cursor(20), 14);
+
+    // An event the peer said nothing about is held back too
+    let ack = acknowledge(&sent, Some(vec![
+        outcome(0, ReplicateStatus::Duplicate, None),
+        outcome(1, ReplicateStatus::Skipped, None),
+        outcome(2, ReplicateStatus::Rejected, None),
+    ]));
+    assert_eq!(ack.rejected, vec![(ids[2], "no reason given".to_string())]);
+    assert_eq!(ack.cursor(20), 15);
+}
+
+#[tokio::test]
+async fn test_outbound_batch_byte_budget() -> anyhow::Result<()> {
+    use tisane_relay::filter::OutboundFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    // A type of our own, so events of concurrent tests don't get into the batches
+    let event_type = format!("batched-{}", Uuid::new_v4());
+    let mut filter = OutboundFilter::default();
+    filter.filter.event_types = vec![event_type.clone()];
+    let settings = db::PeerSettings { outbound_filter: filter, ..Default::default() };
+    let test_peer = add_test_peer(&pool, &settings).await?;
+    let peer_id = test_peer.peer_id;
+    let mut peer = db::fetch_peer(&pool, peer_id).await?.expect("peer");
+
+    db::sequence_replication_log(&pool).await?;
+    let start = db::fetch_replication_log_head(&pool).await?;
+    let mut rng = thread_rng();
+    let key = SigningKey::generate(&mut rng);
+    let events: Vec<EventInput> = (0..3)
+        .map(|n| {
+            let mut ev = signed_event(&key, serde_json::json!({"n": n}), vec![], vec![]);
+            ev.event_type = Some(event_type.clone());
+            ev
+        })
+        .collect();
+    db::insert_events(&pool, &events).await?;
+    db::sequence_replication_log(&pool).await?;
+    let (relay_id, max_hops) = (Uuid::new_v4(), 3);
+    let outbound_filter = peer.outbound_filter.clone();
+    let page = |after: i64, limit: i64| db::fetch_replication_batch(&pool, Some(peer_id), &outbound_filter, after, limit);
+
+    // Without a tight budget the whole page goes out, with provenance extended by us
+    peer.max_batch_bytes = i32::MAX;
+    let whole = page(start, 3).await?.into_outbound(relay_id, max_hops, &peer, start);
+    assert_eq!(whole.events.iter().map(|ev| ev.event.event_id).collect::<Vec<_>>(), events.iter().map(|ev| ev.event_id).collect::<Vec<_>>());
+    assert!(whole.events.iter().all(|ev| ev.provenance.hops == 1 && ev.provenance.path == vec![relay_id]));
+    assert_eq!((whole.consumed, whole.fetched), (3, 3));
+    assert!(whole.has_more(3));
+    let sizes: Vec<usize> = whole.events.iter().map(|ev| serde_json::to_vec(ev).unwrap().len() + 1).collect();
+
+    // A budget for two events cuts the page short; the cursor stops after the second
+    peer.max_batch_bytes = (2 + sizes[0] + sizes[1]) as i32;
+    let cut = page(start, 3).await?.into_outbound(relay_id, max_hops, &peer, start);
+    assert_eq!(cut.events.len(), 2);
+    assert_eq!(cut.cursor, whole.seqs[1]);
+    assert!(cut.has_more(3));
+
+    // The rest comes back short: nothing more, and the cursor passes the scanned log
+    let rest_page = page(cut.cursor, 3).await?;
+    let scanned_to = rest_page.scanned_to;
+    let rest = rest_page.into_outbound(relay_id, max_hops, &peer, cut.cursor);
+    assert_eq!(rest.events.len(), 1);
+    assert_eq!(rest.cursor, scanned_to.max(whole.seqs[2]));
+    assert!(!rest.has_more(3));
+
+    // An event over the budget on its own still goes out, alone
+    peer.max_batch_bytes = 1;
+    let alone = page(start, 3).await?.into_outbound(relay_id, max_hops, &peer, start);
+    assert_eq!((alone.events.len(), alone.consumed), (1, 1));
+    assert_eq!(alone.cursor, whole.seqs[0]);
+    assert!(alone.has_more(3));
+
+    // Events at the hop limit are consumed without being sent
+    let stopped = page(start, 3).await?.into_outbound(relay_id, 0, &peer, start);
+    assert!(stopped.events.is_empty());
+    assert_eq!(stopped.cursor, whole.seqs[2]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_supervisor_reports_panicked_tasks() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tisane_relay::supervisor::Supervisor;
+
+    let mut tasks: Supervisor<u32> = Supervisor::default();
+    let runs = Arc::new(AtomicUsize::new(0));
+    let task = |runs: Arc<AtomicUsize>| async move {
+        if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+            panic!("first run fails");
+        }
+    };
+
+    // A panic is reported with its key, and the restarted task finishing normally is not
+    tasks.spawn(1, task(runs.clone()));
+    assert_eq!(tasks.next_panicked().await, Some(1));
+    assert!(!tasks.contains(&1));
+    tasks.spawn(1, task(runs.clone()));
+    assert_eq!(tasks.next_panicked().await, None);
+    assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+    // Aborted and replaced tasks are forgotten, not reported
+    tasks.spawn(2, std::future::pending());
+    tasks.spawn(3, std::future::pending());
+    tasks.spawn(3, async {});
+    tasks.retain(|key| *key != 2);
+    assert!(!tasks.contains(&2) && tasks.contains(&3));
+    assert_eq!(tasks.next_panicked().await, None);
+    assert!(!tasks.contains(&3));
+}
+
+#[tokio::test]
+async fn test_event_stats() -> anyhow::Result<()> {
+    use chrono::{DateTime, Duration};
+    use tisane_relay::db::{StatsBucket, StatsDimension};
+    use tisane_relay::filter::EventFilter;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut rng = thread_rng();
+    let (alice, bob) = (SigningKey::generate(&mut rng), SigningKey::generate(&mut rng));
+    let (alice_hex, bob_hex) = (hex::encode(alice.verifying_key().to_bytes()), hex::encode(bob.verifying_key().to_bytes()));
+    // A type of our own, so events of concurrent tests aren't counted
+    let event_type = format!("stats-{}", Uuid::new_v4());
+    let day = |d: u32| -> DateTime<Utc> { format!("2001-01-{:02}T12:00:00Z", d).parse().unwrap() };
+
+    // Day 1: two by alice; day 2: one by bob; day 3: two by alice and one by bob
+    let plan = [(1, &alice), (1, &alice), (2, &bob), (3, &alice), (3, &alice), (3, &bob)];
+    let events: Vec<EventInput> = plan
+        .iter()
+        .enumerate()
+        .map(|(n, (d, key))| {
+            let mut ev = signed_event(key, serde_json::json!({"n": n}), vec![], vec![]);
+            ev.event_type = Some(event_type.clone());
+            ev.occurred_at = Some(day(*d));
+            ev
+        })
+        .collect();
+    db::insert_events(&pool, &events).await?;
+
+    let query = |group_by: Vec<StatsDimension>, bucket: Option<StatsBucket>, max_groups: i64| db::StatsQuery {
+        group_by,
+        bucket,
+        from: day(1) - Duration::hours(12),
+        to: day(3) + Duration::hours(12),
+        filter: EventFilter { event_types: vec![event_type.clone()], ..Default::default() },
+        max_groups,
+    };
+    let counts = |rows: &[serde_json::Value]| rows.iter().map(|r| r["count"].as_i64().unwrap()).collect::<Vec<_>>();
+
+    // By dimension: largest group first
+    let stats = db::fetch_event_stats(&pool, &query(vec![StatsDimension::Author, StatsDimension::EventType], None, 10)).await?;
+    assert!(!stats.truncated);
+    assert_eq!(counts(&stats.rows), vec![4, 2]);
+    assert_eq!((stats.rows[0]["author"].as_str(), stats.rows[1]["author"].as_str()), (Some(alice_hex.as_str()), Some(bob_hex.as_str())));
+    assert!(stats.rows.iter().all(|r| r["event_type"] == event_type.as_str() && r.get("bucket").is_none()));
+
+    // By day and author: oldest bucket first, largest group first within a bucket
+    let stats = db::fetch_event_stats(&pool, &query(vec![StatsDimension::Author], Some(StatsBucket::Day), 10)).await?;
+    assert!(!stats.truncated);
+    assert_eq!(counts(&stats.rows), vec![2, 1, 2, 1]);
+    assert_eq!(stats.rows[2]["author"].as_str(), Some(alice_hex.as_str()));
+    assert!(stats.rows[0]["bucket"].as_str() < stats.rows[1]["bucket"].as_str());
+
+    // Over the cap the oldest buckets are left out, not the newest
+    let stats = db::fetch_event_stats(&pool, &query(vec![], Some(StatsBucket::Day), 2)).await?;
+    assert!(stats.truncated);
+    assert_eq!(counts(&stats.rows), vec![1, 3]);
+    assert!(stats.rows[0]["bucket"].as_str() < stats.rows[1]["bucket"].as_str());
+
+    // Without a bucket the smallest groups are left out
+    let stats = db::fetch_event_stats(&pool, &query(vec![StatsDimension::Author], None, 1)).await?;
+    assert!(stats.truncated);
+    assert_eq!(counts(&stats.rows), vec![4]);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_projected_pull_pages() -> anyhow::Result<()> {
+    use tisane_relay::cursor::{CursorCodec, Order};
+    use tisane_relay::filter::EventFilter;
+    use tisane_relay::projection::Projection;
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    // A type of our own, so events of concurrent tests don't land on the pages
+    let filter = EventFilter { event_types: vec![format!("headers-{}", Uuid::new_v4())], ..Default::default() };
+    let events: Vec<EventInput> = (0..5u64)
+        .map(|n| {
+            let mut ev = signed_event(&signing_key, serde_json::json!({"n": n}), vec![], vec![]);
+            ev.event_type = Some(filter.event_types[0].clone());
+            ev
+        })
+        .collect();
+    db::insert_events(&pool, &events).await?;
+
+    let codec = CursorCodec::from_secret("test-secret");
+    let projection = Projection::parse("event_id,server_seq").map_err(anyhow::Error::msg)?;
+    assert!(!projection.includes("payload_json"));
+
+    // Walks every page forward, as a client following next_cursor would
+    let mut walks = Vec::new();
+    for include_payload in [true, false] {
+        let (mut after, mut pages) = (None, Vec::new());
+        loop {
+            let page = db::fetch_events_page(&pool, &filter, Order::Asc, after, false, 2, include_payload).await?;
+            assert!(page.events.iter().all(|e| e.payload_json.is_some() == include_payload));
+            let (next, prev) = codec.page_cursors(Order::Asc, false, after, &filter, &page);
+            let seen: Vec<(Uuid, i64)> = page.events.iter().map(|e| (e.event_id, e.server_seq)).collect();
+            if !include_payload {
+                for (event, projected) in page.events.iter().zip(page.events.iter().map(|e| projection.apply(e))) {
+                    let fields = projected.as_object().expect("object");
+                    assert_eq!(fields.len(), 2);
+                    assert_eq!(fields["event_id"], serde_json::json!(event.event_id));
+                    assert_eq!(fields["server_seq"], serde_json::json!(event.server_seq));
+                }
+            }
+            let has_more = page.has_more;
+            pages.push((seen, next.clone(), prev, has_more));
+            if !has_more {
+                break;
+            }
+            after = Some(codec.decode(&next.expect("next cursor"))?.seq);
+        }
+        walks.push(pages);
+    }
+
+    // Same events in the same order, with the same cursors
+    assert_eq!(walks[0], walks[1]);
+    assert_eq!(walks[0].len(), 3);
+    let ids: Vec<Uuid> = walks[0].iter().flat_map(|(seen, ..)| seen.iter().map(|(id, _)| *id)).collect();
+    assert_eq!(ids, events.iter().map(|e| e.event_id).collect::<Vec<_>>());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_resumes_after_last_event_id() -> anyhow::Result<()> {
+    use std::time::Duration;
+    use tisane_relay::filter::EventFilter;
+    use tisane_relay::hub::{self, Delivery, EventHub};
+
+    // The reconnect header wins over the query; anything but a server_seq is ignored
+    assert_eq!(hub::resume_position(Some(5), Some(" 12 ")), Some(12));
+    assert_eq!(hub::resume_position(Some(5), Some("abc")), Some(5));
+    assert_eq!(hub::resume_position(None, None), None);
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let hub = EventHub::new();
+    tokio::spawn(hub::run_listener(pool.clone(), hub.clone()));
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let author = hex::encode(signing_key.verifying_key().to_bytes());
+    let events: Vec<EventInput> = (0..3u64).map(|n| signed_event(&signing_key, serde_json::json!({"n": n}), vec![], vec![])).collect();
+    let seqs = db::insert_events(&pool, &events).await?;
+
+    // The client saw the first event before it disconnected
+    let after = hub::resume_position(Some(0), Some(&seqs[0].to_string())).expect("position");
+    let filter = EventFilter::from_query(Some(author.as_str()), None, None);
+    let (out, mut deliveries) = tokio::sync::mpsc::channel(16);
+    let follower = {
+        let (pool, hub) = (pool.clone(), hub.clone());
+        tokio::spawn(async move { hub::follow(&pool, &hub, &filter, Some(after), &out, |d| d).await })
+    };
+
+    let mut resumed = Vec::new();
+    loop {
+        match tokio::time::timeout(Duration::from_secs(10), deliveries.recv()).await? {
+            Some(Delivery::Event(ev)) => resumed.push(ev.event_id),
+            Some(Delivery::EndOfStored) => break,
+            other => panic!("unexpected delivery {:?}", other),
+        }
+    }
+    assert_eq!(resumed, vec![events[1].event_id, events[2].event_id]);
+
+    // Live events follow without repeats
+    let live = signed_event(&signing_key, serde_json::json!({"n": 3}), vec![], vec![]);
+    db::insert_event(&pool, &live).await?;
+    match tokio::time::timeout(Duration::from_secs(10), deliveries.recv()).await? {
+        Some(Delivery::Event(ev)) => assert_eq!(ev.event_id, live.event_id),
+        other => panic!("expected the live event, got {:?}", other),
+    }
+
+    follower.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_stream_resume_replays_late_commits() -> anyhow::Result<()> {
+    use std::time::Duration;
+    use tisane_relay::filter::EventFilter;
+    use tisane_relay::hub::{self, Delivery, EventHub};
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let author = hex::encode(signing_key.verifying_key().to_bytes());
+    let early = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    let late = signed_event(&signing_key, serde_json::json!({"n": 2}), vec![], vec![]);
+
+    // The client got `late` and disconnected before `early`, holding the lower server_seq,
+    // committed
+    let mut tx = pool.begin().await?;
+    let early_seq = insert_in_tx(&mut tx, &early).await?;
+    let late_seq = db::insert_event(&pool, &late).await?.expect("new event");
+    assert!(early_seq < late_seq);
+    db::sequence_replication_log(&pool).await?;
+    tx.commit().await?;
+    db::sequence_replication_log(&pool).await?;
+
+    let hub = EventHub::new();
+    let filter = EventFilter::from_query(Some(author.as_str()), None, None);
+    let (out, mut deliveries) = tokio::sync::mpsc::channel(16);
+    let follower = {
+        let (pool, hub) = (pool.clone(), hub.clone());
+        tokio::spawn(async move { hub::follow(&pool, &hub, &filter, Some(late_seq), &out, |d| d).await })
+    };
+
+    let mut resumed = Vec::new();
+    loop {
+        match tokio::time::timeout(Duration::from_secs(10), deliveries.recv()).await? {
+            Some(Delivery::Event(ev)) => resumed.push(ev.event_id),
+            Some(Delivery::EndOfStored) => break,
+            other => panic!("unexpected delivery {:?}", other),
+        }
+    }
+    assert_eq!(resumed, vec![early.event_id]);
+
+    follower.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_replication_wakes_on_insert_and_drains_full_batches() -> anyhow::Result<()> {
+    use std::time::Duration;
+    use tisane_relay::hub::{self, EventHub};
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    // The worker sleeps on the hub's head; an insert moves it (sequenced) well before any idle poll
+    let hub = EventHub::new();
+    let mut head = hub.watch_head();
+    tokio::spawn(hub::run_listener(pool.clone(), hub.clone()));
+
+    let event_type = format!("drained-{}", Uuid::new_v4());
+    let mut settings = db::PeerSettings { batch_size: 2, ..Default::default() };
+    settings.outbound_filter.filter.event_types = vec![event_type.clone()];
+    let test_peer = add_test_peer(&pool, &settings).await?;
+    let peer_id = test_peer.peer_id;
+    db::sequence_replication_log(&pool).await?;
+    let start = db::fetch_replication_log_head(&pool).await?;
+    db::update_peer_cursor(&pool, peer_id, start).await?;
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let events: Vec<EventInput> = (0..5u64)
+        .map(|n| {
+            let mut ev = signed_event(&signing_key, serde_json::json!({"n": n}), vec![], vec![]);
+            ev.event_type = Some(event_type.clone());
+            ev
+        })
+        .collect();
+    // Let the listener settle on the current head before inserting
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    head.borrow_and_update();
+    db::insert_events(&pool, &events).await?;
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            head.changed().await?;
+            if *head.borrow_and_update() >= start + events.len() as i64 {
+                return anyhow::Ok(());
+            }
+        }
+    })
+    .await??;
+
+    // A full batch is followed straight away by the next one until the backlog is drained
+    let mut peer = db::fetch_peer(&pool, peer_id).await?.expect("peer");
+    let limit = peer.batch_size as i64;
+    let (mut sent, mut rounds) = (Vec::new(), 0);
+    loop {
+        rounds += 1;
+        let log = db::fetch_replication_batch(&pool, Some(peer_id), &peer.outbound_filter, peer.last_cursor_seq, limit).await?;
+        let batch = log.into_outbound(Uuid::new_v4(), 3, &peer, peer.last_cursor_seq);
+        sent.extend(batch.events.iter().map(|ev| ev.event.event_id));
+        db::update_peer_cursor(&pool, peer_id, batch.cursor).await?;
+        peer = db::fetch_peer(&pool, peer_id).await?.expect("peer");
+        if !batch.has_more(limit) {
+            break;
+        }
+    }
+    assert_eq!(rounds, 3);
+    assert_eq!(sent, events.iter().map(|ev| ev.event_id).collect::<Vec<_>>());
+    let rest = db::fetch_replication_batch(&pool, Some(peer_id), &peer.outbound_filter, peer.last_cursor_seq, limit).await?;
+    assert!(rest.entries.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_grpc_conversions_and_push_results() -> anyhow::Result<()> {
+    use tisane_relay::ephemeral::Ephemeral;
+    use tisane_relay::proto::{event_to_pb, input_from_pb, outcome_to_pb, pb};
+
+    let mut rng = thread_rng();
+    let signing_key = SigningKey::generate(&mut rng);
+    let refs = vec![db::EventRef { event_id: Uuid::new_v4(), kind: "reply".into() }];
+    let mut ev = signed_event(&signing_key, serde_json::json!({"text": "hi", "n": [1, 2]}), refs, vec![vec!["topic".into(), "rust".into()]]);
+    ev.device_id = Some("phone".into());
+    ev.lamport = Some(3);
+    ev.occurred_at = Some("2024-05-06T07:08:09.123456789Z".parse()?);
+
+    let to_pb = |ev: &EventInput| pb::EventInput {
+        event_id: ev.event_id.to_string(),
+        author_pubkey: ev.author_pubkey.clone(),
+        signature: ev.signature.clone(),
+        payload_hash: ev.payload_hash.clone(),
+        device_id: ev.device_id.clone(),
+        author_id: ev.author_id.clone(),
+        content_id: ev.content_id.clone(),
+        event_type: ev.event_type.clone(),
+        payload_json: ev.payload_json.as_ref().map(|p| p.to_string()),
+        occurred_at: ev.occurred_at.map(|t| prost_types::Timestamp { seconds: t.timestamp(), nanos: t.timestamp_subsec_nanos() as i32 }),
+        lamport: ev.lamport,
+        refs: ev.refs.iter().map(|r| pb::EventRef { event_id: r.event_id.to_string(), kind: r.kind.clone() }).collect(),
+        tags: ev.tags.iter().map(|t| pb::Tag { values: t.clone() }).collect(),
+        // Empty means ed25519
+        sig_scheme: String::new(),
+    };
+
+    // pb -> EventInput keeps every field, down to the nanosecond
+    let message = to_pb(&ev);
+    let input = input_from_pb(message.clone())?;
+    assert_eq!(serde_json::to_value(&input)?, serde_json::to_value(&ev)?);
+
+    // Event -> pb carries the same fields plus the server_seq
+    let out = event_to_pb(&input.to_event(7));
+    assert_eq!(out.server_seq, 7);
+    assert_eq!((&out.event_id, &out.signature, &out.sig_scheme), (&message.event_id, &message.signature, &db::SIG_SCHEME_ED25519.to_string()));
+    assert_eq!((&out.occurred_at, &out.refs, &out.tags), (&message.occurred_at, &message.refs, &message.tags));
+    let payload: serde_json::Value = serde_json::from_str(out.payload_json.as_deref().unwrap_or_default())?;
+    assert_eq!(Some(payload), ev.payload_json);
+
+    let mut broken = message.clone();
+    broken.event_id = "not-a-uuid".into();
+    assert_eq!(input_from_pb(broken).unwrap_err().code(), tonic::Code::InvalidArgument);
+
+    // Push reports every event on its own: stored, repeated and refused
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let mut forged = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    forged.payload_json = Some(serde_json::json!({"n": 2}));
+    let batch: Vec<EventInput> = [to_pb(&ev), to_pb(&ev), to_pb(&forged)]
+        .into_iter()
+        .map(input_from_pb)
+        .collect::<Result<_, _>>()?;
+    let outcomes = tisane_relay::ingest::ingest_each(&pool, &Ephemeral::new(Vec::new(), 120), batch).await?;
+    let results: Vec<pb::EventOutcome> = outcomes.into_iter().map(outcome_to_pb).collect();
+    let statuses: Vec<i32> = results.iter().map(|r| r.status).collect();
+    assert_eq!(statuses, vec![pb::OutcomeStatus::Accepted as i32, pb::OutcomeStatus::Duplicate as i32, pb::OutcomeStatus::Rejected as i32]);
+    assert_eq!(results[0].event_id, ev.event_id.to_string());
+    assert!(results[0].server_seq.is_some());
+    assert!(results[2].server_seq.is_none() && results[2].error.is_some());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_ndjson_ingest_stream() -> anyhow::Result<()> {
+    use axum::body::Bytes;
+    use tisane_relay::ephemeral::Ephemeral;
+    use tisane_relay::ndjson::{self, CHUNK_SIZE, MAX_LINE_BYTES};
+
+    let database_url = get_database_url();
+    let pool = PgPool::connect(&database_url).await?;
+
+    db::run_migrations(&pool).await?;
+
+    let ephemeral = Ephemeral::new(Vec::new(), 120);
+    // Feeds `body` in pieces that cut through lines, and collects the result log
+    async fn run(pool: &PgPool, ephemeral: &Ephemeral, body: Vec<u8>, piece: usize) -> Vec<serde_json::Value> {
+        let pieces: Vec<Result<Bytes, std::io::Error>> = body.chunks(piece).map(|c| Ok(Bytes::copy_from_slice(c))).collect();
+        let (tx, mut rx) = tokio::sync::mpsc::channel(4096);
+        ndjson::ingest_stream(pool, ephemeral, futures::stream::iter(pieces), tx).await;
+        let mut log = Vec::new();
+        while let Ok(value) = rx.try_recv() {
+            log.push(value);
+        }
+        log
+    }
+
+    let signing_key = SigningKey::generate(&mut thread_rng());
+    let first = signed_event(&signing_key, serde_json::json!({"n": 1}), vec![], vec![]);
+    let last = signed_event(&signing_key, serde_json::json!({"n": 2}), vec![], vec![]);
+    let mut forged = signed_event(&signing_key, serde_json::json!({"n": 3}), vec![], vec![]);
+    forged.payload_json = Some(serde_json::json!({"n": 4}));
+
+    // Enough filler to span more than one chunk; the last line has no newline
+    let mut lines = vec![
+        serde_json::to_string(&first)?,
+        serde_json::to_string(&first)?,
+        "{not json".to_string(),
+        String::new(),
+        serde_json::to_string(&forged)?,
+    ];
+    lines.extend(std::iter::repeat_n("[]".to_string(), CHUNK_SIZE + 100));
+    lines.push(serde_json::to_string(&last)?);
+    let log = run(&pool, &ephemeral, lines.join("\n").into_bytes(), 7).await;
+
+    // One result per non-blank line, in line order across chunks, then the summary
+    let (summary, results) = log.split_last().expect("summary");
+    assert_eq!(results.len(), lines.len() - 1);
+    let numbers: Vec<u64> = results.iter().map(|r| r["line"].as_u64().unwrap()).collect();
+    assert_eq!(numbers, (1..=lines.len() as u64).filter(|n| *n != 4).collect::<Vec<_>>());
+    assert_eq!((results[0]["status"].as_str(), results[0]["event_id"].as_str()), (Some("accepted"), Some(first.event_id.to_string().as_str())));
+    assert_eq!(results[1]["status"], "duplicate");
+    assert_eq!(results[2]["status"], "rejected");
+    assert!(results[2]["error"].as_str().is_some_and(|e| e.starts_with("invalid JSON")));
+    assert_eq!(results[3]["status"], "rejected");
+    assert!(results[3]["error"].is_string());
+    assert_eq!((results.last().unwrap()["status"].as_str(), results.last().unwrap()["event_id"].as_str()), (Some("accepted"), Some(last.event_id.to_string().as_str())));
+    assert_eq!(summary["summary"], serde_json::json!({
+        "lines": lines.len() - 1,
+        "accepted": 2,
+        "duplicate": 1,
+        "rejected": lines.len() - 4,
+        "ephemeral": 0,
+    }));
+
+    // An oversized line ends the log after the lines before it, without a summary
+    let fresh = signed_event(&signing_key, serde_json::json!({"n": 5}), vec![], vec![]);
+    let mut body = serde_json::to_vec(&fresh)?;
+    body.push(b'\n');
+    body.extend(std::iter::repeat_n(b'a', MAX_LINE_BYTES + 1));
+    let log = run(&pool, &ephemeral, body, 64 * 1024).await;
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0]["status"], "accepted");
+    assert_eq!(log[1]["error"], format!("line 2 exceeds {} bytes", MAX_LINE_BYTES));
+    assert!(db::fetch_event(&pool, fresh.event_id).await?.is_some());
+    Ok(())
+}